@@ -1,4 +1,5 @@
-use crate::level::Level;
+use crate::level::{Level, level_asset_path};
+use crate::rolling_stock::RollingStock;
 use bevy::prelude::*;
 use bevy::tasks::AsyncComputeTaskPool;
 use event_listener::Event;
@@ -61,15 +62,22 @@ impl Drop for AssetBarrierGuard {
 }
 #[derive(Clone, Eq, PartialEq, Debug, Hash, Default, States)]
 pub enum LoadingState {
+    /// The level menu is shown, entered only when no level was chosen on start
     #[default]
+    SelectingLevel,
     Loading,
     Loaded,
     Instantiated,
 }
 
+/// Asset path of the level to load
+#[derive(Resource)]
+pub struct SelectedLevel(pub String);
+
 #[derive(Resource)]
 pub struct AssetHandles {
     pub level: Handle<Level>,
+    pub rolling_stock: Handle<RollingStock>,
 }
 
 #[derive(Resource)]
@@ -86,21 +94,34 @@ pub struct SoundHandles {
     pub notification: Handle<AudioSource>,
 }
 
-pub struct AssetLoadingPlugin;
+#[derive(Default)]
+pub struct AssetLoadingPlugin {
+    /// Only load the simulation data, without fonts and sounds
+    pub headless: bool,
+    /// Name of the level to load, the level menu picks one if not given
+    pub level: Option<String>,
+}
 
 impl Plugin for AssetLoadingPlugin {
     fn build(&self, app: &mut App) {
-        app.init_state::<LoadingState>()
-            .add_systems(Startup, setup_assets)
+        match &self.level {
+            Some(name) => {
+                app.insert_resource(SelectedLevel(level_asset_path(name)))
+                    .insert_state(LoadingState::Loading);
+            }
+            None => {
+                app.init_state::<LoadingState>();
+            }
+        }
+        app.add_systems(OnEnter(LoadingState::Loading), setup_assets)
             .add_systems(Update, get_async_loading_state.run_if(in_state(LoadingState::Loading)));
+        if !self.headless {
+            app.add_systems(Startup, setup_presentation_assets);
+        }
     }
 }
 
-fn setup_assets(mut commands: Commands, asset_server: Res<AssetServer>) {
-    let (barrier, guard) = AssetBarrier::new();
-    commands.insert_resource(AssetHandles {
-        level: asset_server.load_acquire("level.toml", guard.clone()),
-    });
+fn setup_presentation_assets(mut commands: Commands, asset_server: Res<AssetServer>) {
     commands.insert_resource(FontHandles {
         mono: asset_server.load("fonts/DejaVuSansMono.ttf"),
     });
@@ -110,6 +131,14 @@ fn setup_assets(mut commands: Commands, asset_server: Res<AssetServer>) {
         message: asset_server.load("sounds/message.wav"),
         notification: asset_server.load("sounds/notification.wav"),
     });
+}
+
+fn setup_assets(selected_level: Res<SelectedLevel>, mut commands: Commands, asset_server: Res<AssetServer>) {
+    let (barrier, guard) = AssetBarrier::new();
+    commands.insert_resource(AssetHandles {
+        level: asset_server.load_acquire(selected_level.0.clone(), guard.clone()),
+        rolling_stock: asset_server.load_acquire("rolling_stock.toml", guard.clone()),
+    });
 
     let future = barrier.wait_async();
     commands.insert_resource(barrier);
@@ -127,8 +156,20 @@ fn setup_assets(mut commands: Commands, asset_server: Res<AssetServer>) {
     info!("Asset load started");
 }
 
-fn get_async_loading_state(state: Res<AsyncLoadingState>, mut next_loading_state: ResMut<NextState<LoadingState>>) {
+fn get_async_loading_state(
+    state: Res<AsyncLoadingState>,
+    asset_server: Res<AssetServer>,
+    handles: Res<AssetHandles>,
+    mut next_loading_state: ResMut<NextState<LoadingState>>,
+    mut exit: MessageWriter<AppExit>,
+) {
     if state.0.load(Ordering::Acquire) {
+        // The loader has logged the reason
+        if asset_server.load_state(&handles.level).is_failed() {
+            error!("The level could not be loaded");
+            exit.write(AppExit::error());
+            return;
+        }
         info!("Asset load complete");
         next_loading_state.set(LoadingState::Loaded);
     }
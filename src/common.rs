@@ -1,7 +1,7 @@
 use bevy::prelude::*;
 use serde::de::Visitor;
-use serde::{Deserialize, Deserializer, de::Error};
-use serde_repr::Deserialize_repr;
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error};
+use serde_repr::{Deserialize_repr, Serialize_repr};
 use std::fmt;
 use std::ops::Neg;
 use std::time::Instant;
@@ -14,14 +14,14 @@ pub type SectionId = u32;
 pub type StationId = u32;
 pub type RouteId = u32;
 
-#[derive(Deserialize, Reflect, PartialEq, Copy, Clone, Debug, Hash, Eq)]
+#[derive(Serialize, Deserialize, Reflect, PartialEq, Copy, Clone, Debug, Hash, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum SwitchPosition {
     Straight,
     Side,
 }
 
-#[derive(Deserialize, Reflect, PartialEq, Copy, Clone, Default, Debug)]
+#[derive(Serialize, Deserialize, Reflect, PartialEq, Copy, Clone, Default, Debug)]
 #[serde(rename_all = "lowercase")]
 pub enum SignalType {
     #[default]
@@ -29,7 +29,37 @@ pub enum SignalType {
     Manual,
 }
 
-#[derive(Deserialize_repr, Reflect, PartialEq, Copy, Clone, Default, Debug, Hash, Eq)]
+/// Who issued a command, only the dispatcher's commands are recorded for replay
+#[derive(PartialEq, Copy, Clone, Default, Debug)]
+pub enum CommandSource {
+    #[default]
+    Dispatcher,
+    /// Issued by the simulation itself, e.g. by automatic route setting or the timetable
+    Automatic,
+}
+
+/// Condition of the rail surface, limiting the wheel-rail adhesion for traction and braking
+#[derive(Serialize, Deserialize, Reflect, Resource, PartialEq, Copy, Clone, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum RailCondition {
+    #[default]
+    Dry,
+    Wet,
+    Leaves,
+}
+
+impl RailCondition {
+    /// Adhesion coefficient between the wheel and the rail
+    pub fn adhesion(&self) -> f64 {
+        match self {
+            RailCondition::Dry => 0.30,
+            RailCondition::Wet => 0.20,
+            RailCondition::Leaves => 0.08,
+        }
+    }
+}
+
+#[derive(Serialize_repr, Deserialize_repr, Reflect, PartialEq, Copy, Clone, Default, Debug, Hash, Eq)]
 #[repr(i8)]
 pub enum Direction {
     #[default]
@@ -76,12 +106,29 @@ impl<'de> Deserialize<'de> for HexColor {
     }
 }
 
+impl Serialize for HexColor {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0.to_hex())
+    }
+}
+
 impl From<HexColor> for Color {
     fn from(c: HexColor) -> Self {
         c.0.into()
     }
 }
 
+/// Value of a `--name <value>` command line option
+pub fn arg_value<'a>(args: &'a [String], name: &str) -> Result<Option<&'a str>, String> {
+    match args.iter().position(|arg| arg == name) {
+        Some(idx) => args
+            .get(idx + 1)
+            .map(|value| Some(value.as_str()))
+            .ok_or_else(|| format!("{} must be followed by a value", name)),
+        None => Ok(None),
+    }
+}
+
 pub trait SpeedConv {
     fn value(&self) -> f64;
 
@@ -95,9 +95,10 @@ pub trait DropDownMenu: Component + Sized {
         **vis = Visibility::Hidden;
     }
 
-    fn register<E: IntoIterator<Item = Entity>>(commands: &mut Commands, entities: E) {
+    /// Opens the menu on clicks on the entities. Clicks on the items are handled by [`Self::on_menu_click`],
+    /// which has to be added once as a global observer.
+    fn attach<E: IntoIterator<Item = Entity>>(commands: &mut Commands, entities: E) {
         commands.spawn(Observer::new(Self::on_entity_click).with_entities(entities));
-        commands.add_observer(Self::on_menu_click);
     }
 }
 
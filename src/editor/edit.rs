@@ -0,0 +1,316 @@
+//! Editing operations on the level data, independent of the editor's input handling
+
+use bevy::math::Vec2;
+use rail_dispatch::common::{BlockId, Direction, SectionId, SignalId, SignalType, SwitchId};
+use rail_dispatch::level::{
+    BlockData, BlockGeometry, ConnectionData, Level, SectionData, SignalData, SpawnerData, SpawnerKind, SwitchData,
+};
+
+/// Polyline points snap to a grid of this step, in level pixels
+pub const GRID_STEP: f32 = 2.0;
+/// A point this close to an existing block end snaps to it, in level pixels
+const END_SNAP_DISTANCE: f32 = 3.0;
+/// Clicks further from every block than this don't pick any, in level pixels
+const PICK_DISTANCE: f32 = 4.0;
+/// Length given to newly drawn blocks
+pub const DEFAULT_BLOCK_LENGTH_M: f64 = 100.0;
+/// Distance of a new signal from the block end it guards
+const SIGNAL_END_OFFSET_M: f64 = 20.0;
+const DEFAULT_SPAWNER_SPEED_KMH: f64 = 50.0;
+
+/// A block picked by a click, with the end closest to the click
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct PickedBlock {
+    pub block_id: BlockId,
+    pub end: Direction,
+}
+
+fn next_id(ids: impl Iterator<Item = u32>) -> u32 {
+    ids.max().unwrap_or(0) + 1
+}
+
+/// Snaps a point to a nearby block end, or to the grid
+pub fn snap(level: &Level, point: Vec2) -> Vec2 {
+    level
+        .geometry
+        .iter()
+        .flat_map(|bg| bg.points.first().into_iter().chain(bg.points.last()))
+        .copied()
+        .filter(|end| end.distance(point) <= END_SNAP_DISTANCE)
+        .min_by(|a, b| a.distance(point).total_cmp(&b.distance(point)))
+        .unwrap_or_else(|| (point / GRID_STEP).round() * GRID_STEP)
+}
+
+fn distance_to_segment(point: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let ab = b - a;
+    let t = if ab.length_squared() > 0.0 {
+        ((point - a).dot(ab) / ab.length_squared()).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    point.distance(a + ab * t)
+}
+
+/// The block whose polyline is closest to the point, if it is close enough
+pub fn pick_block(level: &Level, point: Vec2) -> Option<PickedBlock> {
+    let (geometry, distance) = level
+        .geometry
+        .iter()
+        .filter_map(|bg| {
+            let distance = bg
+                .points
+                .windows(2)
+                .map(|seg| distance_to_segment(point, seg[0], seg[1]))
+                .min_by(f32::total_cmp)?;
+            Some((bg, distance))
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))?;
+    if distance > PICK_DISTANCE {
+        return None;
+    }
+    let (first, last) = (geometry.points.first()?, geometry.points.last()?);
+    let end = if point.distance(*last) < point.distance(*first) {
+        Direction::Even
+    } else {
+        Direction::Odd
+    };
+    Some(PickedBlock {
+        block_id: geometry.id,
+        end,
+    })
+}
+
+pub fn block_length(level: &Level, block_id: BlockId) -> Option<f64> {
+    level.blocks.iter().find(|b| b.id == block_id).map(|b| b.length)
+}
+
+/// Adds a block drawn as the polyline, returns its ID
+pub fn add_block(level: &mut Level, points: Vec<Vec2>, length: f64) -> BlockId {
+    let id = next_id(level.blocks.iter().map(|b| b.id));
+    level.blocks.push(BlockData {
+        id,
+        length,
+        gradient_permille: 0.0,
+        curve_radius_m: None,
+    });
+    level.geometry.push(BlockGeometry { id, points });
+    id
+}
+
+/// Changes the block length. Signals facing the even direction keep their distance from the even end,
+/// speed limits beyond the new length are reported by validation.
+pub fn set_block_length(level: &mut Level, block_id: BlockId, length: f64) -> bool {
+    let Some(block) = level.blocks.iter_mut().find(|b| b.id == block_id) else {
+        return false;
+    };
+    let change = length - block.length;
+    block.length = length;
+    for signal in &mut level.signals {
+        if signal.block_id == block_id && signal.direction == Direction::Even {
+            signal.offset_m = (signal.offset_m + change).max(0.0);
+        }
+    }
+    true
+}
+
+/// Removes the connections and switches joining the end of the block
+fn detach_end(level: &mut Level, block_id: BlockId, end: Direction) {
+    level.connections.retain(|conn| match end {
+        Direction::Even => conn.start != block_id,
+        Direction::Odd => conn.end != block_id,
+    });
+    level.switches.retain(|switch| {
+        let base_end = switch.base == block_id && switch.direction == end;
+        let leg_end = (switch.straight == block_id || switch.side == block_id) && switch.direction == end.reverse();
+        !base_end && !leg_end
+    });
+}
+
+/// Joins the even end of `start` to the odd end of `end`, replacing whatever joined these ends before
+pub fn connect(level: &mut Level, start: BlockId, end: BlockId) {
+    detach_end(level, start, Direction::Even);
+    detach_end(level, end, Direction::Odd);
+    level.connections.push(ConnectionData { start, end });
+}
+
+/// Direction in which the switch splits: from the base's end that is drawn closest to the leg
+pub fn switch_direction(level: &Level, base: BlockId, leg: BlockId) -> Direction {
+    let ends = |id| {
+        let bg = level.geometry.iter().find(|bg| bg.id == id)?;
+        Some((*bg.points.first()?, *bg.points.last()?))
+    };
+    match (ends(base), ends(leg)) {
+        (Some((base_first, base_last)), Some((leg_first, leg_last))) => {
+            let even = base_last.distance(leg_first);
+            let odd = base_first.distance(leg_last);
+            if odd < even { Direction::Odd } else { Direction::Even }
+        }
+        _ => Direction::Even,
+    }
+}
+
+/// Adds a switch, replacing the connections and switches joining its ends, returns its ID
+pub fn add_switch(level: &mut Level, base: BlockId, straight: BlockId, side: BlockId) -> SwitchId {
+    let direction = switch_direction(level, base, straight);
+    detach_end(level, base, direction);
+    detach_end(level, straight, direction.reverse());
+    detach_end(level, side, direction.reverse());
+    let id = next_id(level.switches.iter().map(|s| s.id));
+    level.switches.push(SwitchData {
+        id,
+        base,
+        straight,
+        side,
+        direction,
+        throw_time_s: None,
+    });
+    id
+}
+
+/// Adds a signal guarding the given end of the block, facing trains that run towards it.
+/// If there is one already, it is removed instead. Returns the ID of the added signal.
+pub fn toggle_signal(
+    level: &mut Level,
+    block_id: BlockId,
+    end: Direction,
+    signal_type: SignalType,
+) -> Option<SignalId> {
+    let count = level.signals.len();
+    level.signals.retain(|s| s.block_id != block_id || s.direction != end);
+    if level.signals.len() != count {
+        return None;
+    }
+    let length = block_length(level, block_id)?;
+    let offset_from_end = SIGNAL_END_OFFSET_M.min(length / 2.0);
+    let offset_m = match end {
+        Direction::Even => length - offset_from_end,
+        Direction::Odd => offset_from_end,
+    };
+    let id = next_id(level.signals.iter().map(|s| s.id));
+    level.signals.push(SignalData {
+        id,
+        block_id,
+        offset_m,
+        name: id.to_string(),
+        direction: end,
+        signal_type,
+    });
+    Some(id)
+}
+
+/// Adds a spawner and despawner at the block, or removes the one there. Returns whether one was added.
+pub fn toggle_spawner(level: &mut Level, block_id: BlockId) -> bool {
+    let count = level.spawners.len();
+    level.spawners.retain(|s| s.block_id != block_id);
+    if level.spawners.len() != count {
+        return false;
+    }
+    level.spawners.push(SpawnerData {
+        block_id,
+        kind: SpawnerKind::Both,
+        approach_len: 1,
+        speed_kmh: DEFAULT_SPAWNER_SPEED_KMH,
+        x: 0.0,
+        y: 0.0,
+    });
+    true
+}
+
+/// Groups the blocks into a new section, returns its ID
+pub fn add_section(level: &mut Level, blocks: Vec<BlockId>) -> SectionId {
+    let id = next_id(level.sections.iter().map(|s| s.id));
+    level.sections.push(SectionData { id, blocks });
+    id
+}
+
+/// Removes the block and everything placed on it or joined to it. Routes and the timetable
+/// are left alone, validation reports their references to the removed block.
+pub fn remove_block(level: &mut Level, block_id: BlockId) {
+    detach_end(level, block_id, Direction::Even);
+    detach_end(level, block_id, Direction::Odd);
+    level.blocks.retain(|b| b.id != block_id);
+    level.geometry.retain(|bg| bg.id != block_id);
+    level.signals.retain(|s| s.block_id != block_id);
+    level.spawners.retain(|s| s.block_id != block_id);
+    level.speed_limits.retain(|sl| sl.block_id != block_id);
+    for station in &mut level.stations {
+        station.platforms.retain(|p| p.block_id != block_id);
+    }
+    for section in &mut level.sections {
+        section.blocks.retain(|&id| id != block_id);
+    }
+    level.sections.retain(|s| !s.blocks.is_empty());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_level;
+
+    fn empty_level() -> Level {
+        toml::from_str(
+            r##"
+            blocks = []
+            connections = []
+            switches = []
+            spawners = []
+            signals = []
+            background = "#000000"
+            "##,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn drawn_layout_is_valid() {
+        let mut level = empty_level();
+        let points = [[0.0, 0.0], [20.0, 0.0], [40.0, 0.0], [60.0, 0.0]];
+        let blocks: Vec<BlockId> = points
+            .windows(2)
+            .map(|seg| {
+                let (a, b) = (snap(&level, Vec2::from(seg[0])), snap(&level, Vec2::from(seg[1])));
+                add_block(&mut level, vec![a, b], DEFAULT_BLOCK_LENGTH_M)
+            })
+            .collect();
+        let branch_start = snap(&level, Vec2::new(21.0, 1.0));
+        assert_eq!(branch_start, Vec2::new(20.0, 0.0));
+        let branch = add_block(&mut level, vec![branch_start, Vec2::new(40.0, 10.0)], 200.0);
+
+        connect(&mut level, blocks[0], blocks[1]);
+        connect(&mut level, blocks[1], blocks[2]);
+        // the switch replaces the connection between its base and straight leg
+        assert_eq!(add_switch(&mut level, blocks[0], blocks[1], branch), 1);
+        assert_eq!(level.connections.len(), 1);
+        assert_eq!(level.switches[0].direction, Direction::Even);
+
+        let picked = pick_block(&level, Vec2::new(38.0, 1.0)).unwrap();
+        assert_eq!(
+            picked,
+            PickedBlock {
+                block_id: blocks[1],
+                end: Direction::Even
+            }
+        );
+        assert!(toggle_signal(&mut level, picked.block_id, picked.end, SignalType::Manual).is_some());
+        assert!(toggle_spawner(&mut level, blocks[0]));
+        add_section(&mut level, vec![blocks[0]]);
+        assert!(set_block_length(&mut level, blocks[1], 500.0));
+        assert_eq!(level.signals[0].offset_m, 480.0);
+
+        let contents = toml::to_string(&level).unwrap();
+        let written: Level = toml::from_str(&contents).unwrap();
+        assert_eq!(written.validate(), Ok(()));
+    }
+
+    #[test]
+    fn removed_block_leaves_no_references() {
+        let mut level = test_level();
+        remove_block(&mut level, 4);
+        assert!(level.switches.iter().all(|s| s.id != 1));
+        assert!(level.connections.iter().all(|c| c.start != 4 && c.end != 4));
+        assert!(level.sections.iter().all(|s| !s.blocks.contains(&4)));
+        // removing the signal a second time toggles it back
+        assert_eq!(toggle_signal(&mut level, 6, Direction::Even, SignalType::Manual), None);
+        assert!(toggle_signal(&mut level, 6, Direction::Even, SignalType::Manual).is_some());
+    }
+}
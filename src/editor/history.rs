@@ -0,0 +1,272 @@
+//! Undo and redo of the editing operations. Every operation is recorded as a command holding the
+//! changes it made to each kind of level data, so it can be reverted and applied again.
+
+use bevy::prelude::*;
+use rail_dispatch::level::{
+    BlockData, BlockGeometry, ConnectionData, Level, SectionData, SignalData, SpawnerData, SpeedLimitData, StationData,
+    SwitchData,
+};
+
+/// Older commands are forgotten
+const MAX_COMMANDS: usize = 200;
+
+/// A kind of level data edited as a list
+trait LevelItems: Clone + PartialEq + Send + Sync + 'static {
+    fn items(level: &Level) -> &Vec<Self>;
+    fn items_mut(level: &mut Level) -> &mut Vec<Self>;
+}
+
+macro_rules! level_items {
+    ($($item:ty => $field:ident),* $(,)?) => {
+        $(impl LevelItems for $item {
+            fn items(level: &Level) -> &Vec<Self> {
+                &level.$field
+            }
+
+            fn items_mut(level: &mut Level) -> &mut Vec<Self> {
+                &mut level.$field
+            }
+        })*
+    };
+}
+
+// Routes are edited with their stations
+level_items!(
+    BlockData => blocks,
+    BlockGeometry => geometry,
+    ConnectionData => connections,
+    SwitchData => switches,
+    SpawnerData => spawners,
+    SignalData => signals,
+    SpeedLimitData => speed_limits,
+    SectionData => sections,
+    StationData => stations,
+);
+
+/// Replacement of a run of items in one list, the items before and after the run are unchanged
+struct Splice<T> {
+    index: usize,
+    removed: Vec<T>,
+    inserted: Vec<T>,
+}
+
+impl<T: LevelItems> Splice<T> {
+    fn between(before: &Level, after: &Level) -> Option<Self> {
+        let (before, after) = (T::items(before), T::items(after));
+        let prefix = before.iter().zip(after).take_while(|(a, b)| a == b).count();
+        let suffix = before[prefix..]
+            .iter()
+            .rev()
+            .zip(after[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let removed = &before[prefix..before.len() - suffix];
+        let inserted = &after[prefix..after.len() - suffix];
+        (!removed.is_empty() || !inserted.is_empty()).then(|| Splice {
+            index: prefix,
+            removed: removed.to_vec(),
+            inserted: inserted.to_vec(),
+        })
+    }
+}
+
+trait Change: Send + Sync {
+    fn apply(&self, level: &mut Level);
+    fn revert(&self, level: &mut Level);
+}
+
+impl<T: LevelItems> Change for Splice<T> {
+    fn apply(&self, level: &mut Level) {
+        let range = self.index..self.index + self.removed.len();
+        T::items_mut(level).splice(range, self.inserted.iter().cloned());
+    }
+
+    fn revert(&self, level: &mut Level) {
+        let range = self.index..self.index + self.inserted.len();
+        T::items_mut(level).splice(range, self.removed.iter().cloned());
+    }
+}
+
+/// One editing operation
+struct EditCommand {
+    description: String,
+    changes: Vec<Box<dyn Change>>,
+}
+
+impl EditCommand {
+    fn between(description: String, before: &Level, after: &Level) -> Self {
+        fn push<T: LevelItems>(changes: &mut Vec<Box<dyn Change>>, before: &Level, after: &Level) {
+            if let Some(splice) = Splice::<T>::between(before, after) {
+                changes.push(Box::new(splice));
+            }
+        }
+        let mut changes = Vec::new();
+        push::<BlockData>(&mut changes, before, after);
+        push::<BlockGeometry>(&mut changes, before, after);
+        push::<ConnectionData>(&mut changes, before, after);
+        push::<SwitchData>(&mut changes, before, after);
+        push::<SpawnerData>(&mut changes, before, after);
+        push::<SignalData>(&mut changes, before, after);
+        push::<SpeedLimitData>(&mut changes, before, after);
+        push::<SectionData>(&mut changes, before, after);
+        push::<StationData>(&mut changes, before, after);
+        EditCommand { description, changes }
+    }
+
+    fn apply(&self, level: &mut Level) {
+        for change in &self.changes {
+            change.apply(level);
+        }
+    }
+
+    fn revert(&self, level: &mut Level) {
+        for change in self.changes.iter().rev() {
+            change.revert(level);
+        }
+    }
+}
+
+/// Edits that can be undone and redone, and whether there are changes since the level was saved
+#[derive(Resource)]
+pub struct History {
+    undo: Vec<EditCommand>,
+    redo: Vec<EditCommand>,
+    /// Number of undoable commands when the level was saved, `None` if that state can't be reached
+    saved_at: Option<usize>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            saved_at: Some(0),
+        }
+    }
+}
+
+impl History {
+    /// Records the edit that turned `before` into `after`, returns false if nothing changed
+    pub fn record(&mut self, description: String, before: &Level, after: &Level) -> bool {
+        let command = EditCommand::between(description, before, after);
+        if command.changes.is_empty() {
+            return false;
+        }
+        if self.saved_at.is_some_and(|saved_at| saved_at > self.undo.len()) {
+            // the saved state was among the undone commands, which are gone now
+            self.saved_at = None;
+        }
+        self.redo.clear();
+        self.undo.push(command);
+        if self.undo.len() > MAX_COMMANDS {
+            self.undo.remove(0);
+            self.saved_at = self.saved_at.and_then(|saved_at| saved_at.checked_sub(1));
+        }
+        true
+    }
+
+    /// Reverts the last edit, returns its description
+    pub fn undo(&mut self, level: &mut Level) -> Option<String> {
+        let command = self.undo.pop()?;
+        command.revert(level);
+        let description = command.description.clone();
+        self.redo.push(command);
+        Some(description)
+    }
+
+    /// Applies the last undone edit again, returns its description
+    pub fn redo(&mut self, level: &mut Level) -> Option<String> {
+        let command = self.redo.pop()?;
+        command.apply(level);
+        let description = command.description.clone();
+        self.undo.push(command);
+        Some(description)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.saved_at = Some(self.undo.len());
+    }
+
+    /// Whether the level has changed since it was loaded or saved
+    pub fn is_dirty(&self) -> bool {
+        self.saved_at != Some(self.undo.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{edit, test_level};
+    use rail_dispatch::common::{Direction, SignalType};
+
+    fn edit(history: &mut History, level: &mut Level, description: &str, f: impl FnOnce(&mut Level)) {
+        let before = level.clone();
+        f(level);
+        history.record(description.to_string(), &before, level);
+    }
+
+    #[test]
+    fn undo_and_redo_restore_the_level() {
+        let original = test_level();
+        let mut level = original.clone();
+        let mut history = History::default();
+        edit(&mut history, &mut level, "remove block", |level| {
+            edit::remove_block(level, 4)
+        });
+        edit(&mut history, &mut level, "signal", |level| {
+            edit::toggle_signal(level, 3, Direction::Even, SignalType::Manual);
+        });
+        edit(&mut history, &mut level, "length", |level| {
+            edit::set_block_length(level, 6, 900.0);
+        });
+        let edited = toml::to_string(&level).unwrap();
+
+        for description in ["length", "signal", "remove block"] {
+            assert_eq!(history.undo(&mut level).as_deref(), Some(description));
+        }
+        assert_eq!(history.undo(&mut level), None);
+        assert_eq!(toml::to_string(&level).unwrap(), toml::to_string(&original).unwrap());
+
+        while history.redo(&mut level).is_some() {}
+        assert_eq!(toml::to_string(&level).unwrap(), edited);
+    }
+
+    #[test]
+    fn dirty_until_saved_state_is_back() {
+        let mut level = test_level();
+        let mut history = History::default();
+        assert!(!history.is_dirty());
+        // an edit that changes nothing is not recorded
+        edit(&mut history, &mut level, "same length", |level| {
+            edit::set_block_length(level, 6, 1050.0);
+        });
+        assert!(!history.is_dirty());
+
+        edit(&mut history, &mut level, "spawner", |level| {
+            edit::toggle_spawner(level, 3);
+        });
+        assert!(history.is_dirty());
+        history.mark_saved();
+        assert!(!history.is_dirty());
+        history.undo(&mut level);
+        assert!(history.is_dirty());
+        history.redo(&mut level);
+        assert!(!history.is_dirty());
+
+        // after undoing past the save, a new edit makes the saved state unreachable
+        history.undo(&mut level);
+        edit(&mut history, &mut level, "section", |level| {
+            edit::add_section(level, vec![3]);
+        });
+        history.undo(&mut level);
+        assert!(history.is_dirty());
+    }
+}
@@ -1,11 +1,35 @@
+mod edit;
+mod history;
+mod tools;
+
 use bevy::asset::AssetPlugin;
 use bevy::prelude::*;
 use bevy::window::ExitCondition;
 use rail_dispatch::assets::AssetLoadingPlugin;
+use rail_dispatch::common::arg_value;
 use rail_dispatch::level::LevelPlugin;
+use rail_dispatch::menu::LevelMenuPlugin;
 use rail_dispatch::panel::{CameraControlPlugin, SchematicPlugin};
+use rail_dispatch::rolling_stock::RollingStockPlugin;
+use tools::EditorPlugin;
+
+/// The shipped passing loop level, shared by the editor tests. The library's
+/// [`Level::test_fixture`](rail_dispatch::level::Level) is only built for its own tests.
+#[cfg(test)]
+fn test_level() -> rail_dispatch::level::Level {
+    toml::from_str(include_str!("../../resources/levels/passing_loop.toml")).unwrap()
+}
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let level = match arg_value(&args, "--level") {
+        Ok(level) => level.map(String::from),
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+    };
+
     App::new()
         .add_plugins(
             DefaultPlugins
@@ -15,13 +39,23 @@ fn main() {
                 })
                 .set(WindowPlugin {
                     primary_window: Some(Window {
-                        title: "Rail Dispatch — Map Viewer".to_string(),
+                        title: tools::TITLE.to_string(),
                         ..default()
                     }),
                     exit_condition: ExitCondition::OnPrimaryClosed,
+                    // closing with unsaved changes is confirmed by the editor
+                    close_when_requested: false,
                     ..default()
                 }),
         )
-        .add_plugins((LevelPlugin, AssetLoadingPlugin, SchematicPlugin, CameraControlPlugin))
+        .add_plugins((
+            LevelPlugin,
+            RollingStockPlugin,
+            AssetLoadingPlugin { headless: false, level },
+            LevelMenuPlugin,
+            SchematicPlugin,
+            CameraControlPlugin,
+            EditorPlugin,
+        ))
         .run();
 }
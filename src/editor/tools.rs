@@ -0,0 +1,613 @@
+//! Editing tools of the map editor: the mouse edits the level asset in place, and the schematic
+//! is rebuilt from it like after a reload of the level file
+
+use crate::edit::{self, DEFAULT_BLOCK_LENGTH_M};
+use crate::history::History;
+use bevy::asset::io::file::FileAssetReader;
+use bevy::ecs::system::SystemParam;
+use bevy::input::ButtonState;
+use bevy::input::keyboard::{Key, KeyboardInput};
+use bevy::prelude::*;
+use bevy::window::{PrimaryWindow, WindowCloseRequested};
+use rail_dispatch::assets::{AssetHandles, LoadingState};
+use rail_dispatch::common::{BlockId, SignalType};
+use rail_dispatch::level::{Level, LevelReloaded};
+use rail_dispatch::panel::{ScreenScale, TrackGeometry, level_to_world, world_to_level};
+use rail_dispatch::simulation::route_generator;
+use std::path::PathBuf;
+
+pub const TITLE: &str = "Rail Dispatch — Map Editor";
+const DRAFT_POINT_SIZE: f32 = 5.0;
+const DRAFT_COLOR: Color = Color::srgb(0.3, 0.6, 1.0);
+const LABEL_COLOR: Color = Color::srgb(0.7, 0.72, 0.76);
+const SIGNAL_LABEL_COLOR: Color = Color::srgb(0.95, 0.55, 0.45);
+const LABEL_Z: f32 = 4.0;
+/// Label offset from the track, in world units
+const LABEL_OFFSET: f32 = 10.0;
+
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+enum Tool {
+    #[default]
+    Draw,
+    Length,
+    Connect,
+    Switch,
+    Signal,
+    Spawner,
+    Section,
+    Delete,
+}
+
+impl Tool {
+    const KEYS: [(KeyCode, Tool); 8] = [
+        (KeyCode::KeyD, Tool::Draw),
+        (KeyCode::KeyL, Tool::Length),
+        (KeyCode::KeyC, Tool::Connect),
+        (KeyCode::KeyW, Tool::Switch),
+        (KeyCode::KeyS, Tool::Signal),
+        (KeyCode::KeyP, Tool::Spawner),
+        (KeyCode::KeyG, Tool::Section),
+        (KeyCode::KeyX, Tool::Delete),
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Tool::Draw => "[D]raw block",
+            Tool::Length => "[L]ength",
+            Tool::Connect => "[C]onnect",
+            Tool::Switch => "s[W]itch",
+            Tool::Signal => "[S]ignal",
+            Tool::Spawner => "s[P]awner",
+            Tool::Section => "section [G]roup",
+            Tool::Delete => "[X] delete block",
+        }
+    }
+
+    fn hint(self) -> &'static str {
+        match self {
+            Tool::Draw => "click the polyline points, Enter or right click ends the block",
+            Tool::Length => "click a block, type its length in metres and press Enter",
+            Tool::Connect => "click a block, then the block joined to its even end",
+            Tool::Switch => "click the base block, then the straight leg, then the side leg",
+            Tool::Signal => "click near a block end to add or remove a signal guarding it, [T] toggles the type",
+            Tool::Spawner => "click a block with an open end to add or remove a spawner",
+            Tool::Section => "click the blocks of the section, Enter groups them",
+            Tool::Delete => "click a block to remove it with everything placed on it",
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+struct EditorState {
+    tool: Tool,
+    /// Points of the block being drawn, in level pixels
+    points: Vec<Vec2>,
+    /// Blocks picked so far for the current tool
+    picked: Vec<BlockId>,
+    /// Block length being typed
+    typed: String,
+    signal_type: SignalType,
+    /// Result of the last action
+    message: String,
+    /// Level file contents as last written by the editor
+    written: Option<String>,
+    /// The user has been warned about closing with unsaved changes
+    close_warned: bool,
+}
+
+impl EditorState {
+    fn reset(&mut self) {
+        self.points.clear();
+        self.picked.clear();
+        self.typed.clear();
+    }
+}
+
+/// The edited level, every change made through it is recorded in the history
+#[derive(SystemParam)]
+struct LevelEditor<'w> {
+    handles: Res<'w, AssetHandles>,
+    levels: ResMut<'w, Assets<Level>>,
+    history: ResMut<'w, History>,
+}
+
+impl LevelEditor<'_> {
+    fn level(&self) -> Option<&Level> {
+        self.levels.get(&self.handles.level)
+    }
+
+    /// Applies the edit and records it with the description it returns. The level is only borrowed
+    /// mutably for an edit, as that rebuilds the schematic.
+    fn edit(&mut self, edit: impl FnOnce(&mut Level) -> String) -> String {
+        let Some(level) = self.levels.get_mut(&self.handles.level) else {
+            return String::new();
+        };
+        let before = level.clone();
+        let description = edit(level);
+        self.history.record(description.clone(), &before, level);
+        description
+    }
+
+    fn undo(&mut self) -> Option<String> {
+        if !self.history.can_undo() {
+            return None;
+        }
+        self.history.undo(self.levels.get_mut(&self.handles.level)?)
+    }
+
+    fn redo(&mut self) -> Option<String> {
+        if !self.history.can_redo() {
+            return None;
+        }
+        self.history.redo(self.levels.get_mut(&self.handles.level)?)
+    }
+}
+
+#[derive(Component)]
+struct StatusText;
+
+#[derive(Component)]
+struct DraftPoint;
+
+#[derive(Component)]
+struct EditorLabel;
+
+/// Editing tools for the map editor, the level is written back with Ctrl+S. Edits are undone with
+/// Ctrl+Z and redone with Ctrl+Y, Ctrl+R adds the routes generated from the layout. The window
+/// needs `close_when_requested` disabled, so that closing it with unsaved changes can be confirmed.
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EditorState>()
+            .init_resource::<History>()
+            .add_systems(Startup, spawn_status_text)
+            .add_systems(OnEnter(LoadingState::Loaded), spawn_labels)
+            .add_systems(
+                Update,
+                (confirm_close, show_dirty_title.run_if(resource_changed::<History>)),
+            )
+            .add_systems(
+                Update,
+                (
+                    (
+                        forget_history_on_file_change,
+                        select_tool,
+                        type_length,
+                        edit_level,
+                        undo_redo,
+                        add_generated_routes,
+                        save_level,
+                    )
+                        .chain(),
+                    (show_status, show_draft).run_if(resource_changed::<EditorState>),
+                    (despawn_labels, spawn_labels)
+                        .chain()
+                        .run_if(on_message::<LevelReloaded>),
+                )
+                    .run_if(in_state(LoadingState::Loaded)),
+            );
+    }
+}
+
+fn spawn_status_text(mut commands: Commands) {
+    commands.spawn((
+        StatusText,
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: px(8),
+            left: px(8),
+            padding: UiRect::all(px(6)),
+            ..default()
+        },
+        Text::default(),
+        TextFont::from_font_size(13.0),
+        BackgroundColor(Color::srgba(0.0, 0.0, 0.0, 0.6)),
+        Pickable::IGNORE,
+    ));
+}
+
+fn control_pressed(keys: &ButtonInput<KeyCode>) -> bool {
+    keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight])
+}
+
+fn select_tool(keys: Res<ButtonInput<KeyCode>>, mut state: ResMut<EditorState>) {
+    // digits and keys typed into the block length are not shortcuts
+    if control_pressed(&keys) || !state.typed.is_empty() {
+        return;
+    }
+    if let Some(&(_, tool)) = Tool::KEYS.iter().find(|(key, _)| keys.just_pressed(*key)) {
+        state.tool = tool;
+        state.reset();
+        state.message.clear();
+    }
+    if keys.just_pressed(KeyCode::KeyT) {
+        state.signal_type = match state.signal_type {
+            SignalType::Automatic => SignalType::Manual,
+            SignalType::Manual => SignalType::Automatic,
+        };
+    }
+    if keys.just_pressed(KeyCode::Escape) {
+        state.reset();
+    }
+}
+
+fn type_length(mut keyboard: MessageReader<KeyboardInput>, mut state: ResMut<EditorState>) {
+    if state.tool != Tool::Length || state.picked.is_empty() {
+        keyboard.clear();
+        return;
+    }
+    for input in keyboard.read().filter(|input| input.state == ButtonState::Pressed) {
+        match &input.logical_key {
+            Key::Character(c) if c.chars().all(|c| c.is_ascii_digit() || c == '.') => state.typed.push_str(c),
+            Key::Backspace => {
+                state.typed.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Cursor position in level pixels
+fn cursor_position(window: &Window, camera: (&Camera, &GlobalTransform)) -> Option<Vec2> {
+    let cursor = window.cursor_position()?;
+    let world = camera.0.viewport_to_world_2d(camera.1, cursor).ok()?;
+    Some(world_to_level(world))
+}
+
+fn edit_level(
+    keys: Res<ButtonInput<KeyCode>>,
+    buttons: Res<ButtonInput<MouseButton>>,
+    window: Single<&Window, With<PrimaryWindow>>,
+    camera: Single<(&Camera, &GlobalTransform)>,
+    mut editor: LevelEditor,
+    mut state: ResMut<EditorState>,
+) {
+    let finish = keys.just_pressed(KeyCode::Enter) || buttons.just_pressed(MouseButton::Right);
+    let click = buttons
+        .just_pressed(MouseButton::Left)
+        .then(|| cursor_position(&window, camera.into_inner()))
+        .flatten();
+    if !finish && click.is_none() {
+        return;
+    }
+    let Some(level) = editor.level() else {
+        return;
+    };
+    let picked = click.and_then(|point| edit::pick_block(level, point));
+    let state = state.as_mut();
+
+    match (state.tool, click) {
+        (Tool::Draw, Some(point)) => {
+            let point = edit::snap(level, point);
+            if state.points.last() != Some(&point) {
+                state.points.push(point);
+            }
+        }
+        (Tool::Draw, None) if state.points.len() >= 2 => {
+            let points = std::mem::take(&mut state.points);
+            state.message = editor.edit(|level| {
+                let id = edit::add_block(level, points, DEFAULT_BLOCK_LENGTH_M);
+                format!("Added block {} of {} m", id, DEFAULT_BLOCK_LENGTH_M)
+            });
+        }
+        (Tool::Length, Some(_)) => {
+            if let Some(picked) = picked {
+                state.picked = vec![picked.block_id];
+                state.typed.clear();
+            }
+        }
+        (Tool::Length, None) => {
+            let (Some(&block_id), Ok(length)) = (state.picked.first(), state.typed.parse::<f64>()) else {
+                return;
+            };
+            state.message = editor.edit(|level| {
+                edit::set_block_length(level, block_id, length);
+                format!("Block {} is {} m long", block_id, length)
+            });
+            state.reset();
+        }
+        (Tool::Connect, Some(_)) => {
+            let Some(picked) = picked else { return };
+            state.picked.push(picked.block_id);
+            if let [start, end] = state.picked[..] {
+                state.message = editor.edit(|level| {
+                    edit::connect(level, start, end);
+                    format!("Connected block {} to block {}", start, end)
+                });
+                state.reset();
+            }
+        }
+        (Tool::Switch, Some(_)) => {
+            let Some(picked) = picked else { return };
+            state.picked.push(picked.block_id);
+            if let [base, straight, side] = state.picked[..] {
+                state.message = editor.edit(|level| {
+                    let id = edit::add_switch(level, base, straight, side);
+                    format!("Added switch {}", id)
+                });
+                state.reset();
+            }
+        }
+        (Tool::Signal, Some(_)) => {
+            let Some(picked) = picked else { return };
+            let signal_type = state.signal_type;
+            state.message =
+                editor.edit(
+                    |level| match edit::toggle_signal(level, picked.block_id, picked.end, signal_type) {
+                        Some(id) => format!("Added signal {} on block {}", id, picked.block_id),
+                        None => format!("Removed the signal on block {}", picked.block_id),
+                    },
+                );
+        }
+        (Tool::Spawner, Some(_)) => {
+            let Some(picked) = picked else { return };
+            state.message = editor.edit(|level| {
+                if edit::toggle_spawner(level, picked.block_id) {
+                    format!("Added a spawner on block {}", picked.block_id)
+                } else {
+                    format!("Removed the spawner on block {}", picked.block_id)
+                }
+            });
+        }
+        (Tool::Section, Some(_)) => {
+            let Some(picked) = picked else { return };
+            match state.picked.iter().position(|&id| id == picked.block_id) {
+                Some(idx) => {
+                    state.picked.remove(idx);
+                }
+                None => state.picked.push(picked.block_id),
+            }
+        }
+        (Tool::Section, None) if !state.picked.is_empty() => {
+            let blocks = std::mem::take(&mut state.picked);
+            state.message = editor.edit(|level| {
+                let id = edit::add_section(level, blocks);
+                format!("Added section {}", id)
+            });
+        }
+        (Tool::Delete, Some(_)) => {
+            let Some(picked) = picked else { return };
+            state.message = editor.edit(|level| {
+                edit::remove_block(level, picked.block_id);
+                format!("Removed block {}", picked.block_id)
+            });
+        }
+        _ => {}
+    }
+}
+
+/// File the level asset was loaded from
+fn level_file(handles: &AssetHandles) -> Option<PathBuf> {
+    let path = handles.level.path()?;
+    Some(FileAssetReader::get_base_path().join("resources").join(path.path()))
+}
+
+fn save_level(keys: Res<ButtonInput<KeyCode>>, mut editor: LevelEditor, mut state: ResMut<EditorState>) {
+    if !(control_pressed(&keys) && keys.just_pressed(KeyCode::KeyS)) {
+        return;
+    }
+    let (Some(level), Some(path)) = (editor.level(), level_file(&editor.handles)) else {
+        return;
+    };
+    let result = level
+        .validate()
+        .map_err(|issues| format!("The level has problems, it was not saved:\n{}", issues))
+        .and_then(|()| toml::to_string(level).map_err(|e| e.to_string()))
+        .and_then(|contents| {
+            std::fs::write(&path, &contents).map_err(|e| e.to_string())?;
+            Ok(contents)
+        });
+    state.message = match result {
+        Ok(contents) => {
+            info!("Level saved to {}", path.display());
+            editor.history.mark_saved();
+            state.written = Some(contents);
+            state.close_warned = false;
+            format!("Saved to {}", path.display())
+        }
+        Err(e) => {
+            warn!("Failed to save the level to {}: {}", path.display(), e);
+            e
+        }
+    };
+}
+
+fn undo_redo(keys: Res<ButtonInput<KeyCode>>, mut editor: LevelEditor, mut state: ResMut<EditorState>) {
+    if !control_pressed(&keys) {
+        return;
+    }
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let result = if keys.just_pressed(KeyCode::KeyY) || (shift && keys.just_pressed(KeyCode::KeyZ)) {
+        editor.redo().map(|description| format!("Redone: {}", description))
+    } else if keys.just_pressed(KeyCode::KeyZ) {
+        editor.undo().map(|description| format!("Undone: {}", description))
+    } else {
+        return;
+    };
+    state.reset();
+    state.message = result.unwrap_or_else(|| "Nothing to undo or redo".to_string());
+}
+
+/// Adds the routes generated from the layout that the level is missing, the user reviews them before saving
+fn add_generated_routes(keys: Res<ButtonInput<KeyCode>>, mut editor: LevelEditor, mut state: ResMut<EditorState>) {
+    if !(control_pressed(&keys) && keys.just_pressed(KeyCode::KeyR)) {
+        return;
+    }
+    let Some(level) = editor.level() else {
+        return;
+    };
+    let generated = match route_generator::generate_routes(level) {
+        Ok(generated) => generated,
+        Err(issues) => {
+            state.reset();
+            state.message = format!("The level has problems, no routes were generated:\n{}", issues);
+            return;
+        }
+    };
+    let unassigned = generated
+        .iter()
+        .filter(|g| g.existing.is_none() && g.station_id.is_none())
+        .count();
+    let added: Vec<String> = generated
+        .iter()
+        .filter(|g| g.existing.is_none() && g.station_id.is_some())
+        .map(|g| g.route.id.to_string())
+        .collect();
+    state.reset();
+    state.message = if added.is_empty() {
+        "The level has every generated route".to_string()
+    } else {
+        editor.edit(|level| {
+            route_generator::merge_routes(level, &generated);
+            format!("Added generated routes {}", added.join(", "))
+        })
+    };
+    if unassigned > 0 {
+        state.message += &format!(
+            "\n{} routes have no station platform along them and were left out",
+            unassigned
+        );
+    }
+}
+
+/// A level file changed by another program replaces the edited level, the history doesn't apply to it
+fn forget_history_on_file_change(
+    mut asset_events: MessageReader<AssetEvent<Level>>,
+    mut editor: LevelEditor,
+    mut state: ResMut<EditorState>,
+) {
+    let level_id = editor.handles.level.id();
+    if !asset_events
+        .read()
+        .any(|event| event.is_loaded_with_dependencies(level_id))
+    {
+        return;
+    }
+    let contents = editor.level().and_then(|level| toml::to_string(level).ok());
+    if contents != state.written {
+        if editor.history.can_undo() || editor.history.can_redo() {
+            state.message = "The level file was changed outside the editor, the edit history is cleared".to_string();
+        }
+        *editor.history = History::default();
+        state.written = contents;
+    }
+}
+
+/// With unsaved changes, the first request to close the window only shows a warning
+fn confirm_close(
+    mut requests: MessageReader<WindowCloseRequested>,
+    history: Res<History>,
+    mut state: ResMut<EditorState>,
+    mut commands: Commands,
+) {
+    for request in requests.read() {
+        if history.is_dirty() && !state.close_warned {
+            state.close_warned = true;
+            state.message =
+                "There are unsaved changes: close the window again to discard them, or press Ctrl+S to save"
+                    .to_string();
+        } else {
+            commands.entity(request.window).despawn();
+        }
+    }
+}
+
+fn show_dirty_title(history: Res<History>, mut window: Single<&mut Window, With<PrimaryWindow>>) {
+    window.title = if history.is_dirty() {
+        format!("{} *", TITLE)
+    } else {
+        TITLE.to_string()
+    };
+}
+
+fn show_status(state: Res<EditorState>, mut text: Single<&mut Text, With<StatusText>>) {
+    let tools: Vec<&str> = Tool::KEYS.iter().map(|(_, tool)| tool.label()).collect();
+    let mut status = format!(
+        "{}   [T] signal type: {:?}   Ctrl+Z undo   Ctrl+Y redo   Ctrl+R generate routes   Ctrl+S save\n{}: {}",
+        tools.join("  "),
+        state.signal_type,
+        state.tool.label(),
+        state.tool.hint()
+    );
+    if !state.picked.is_empty() {
+        status += &format!("\npicked blocks: {:?}", state.picked);
+    }
+    if state.tool == Tool::Length && !state.picked.is_empty() {
+        status += &format!("\nlength: {}_ m", state.typed);
+    }
+    if !state.message.is_empty() {
+        status += &format!("\n{}", state.message);
+    }
+    text.0 = status;
+}
+
+fn show_draft(
+    state: Res<EditorState>,
+    query: Query<Entity, With<DraftPoint>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut commands: Commands,
+) {
+    for entity in &query {
+        commands.entity(entity).despawn();
+    }
+    if state.points.is_empty() {
+        return;
+    }
+    let mesh = meshes.add(Rectangle::new(DRAFT_POINT_SIZE, DRAFT_POINT_SIZE));
+    let material = materials.add(ColorMaterial::from_color(DRAFT_COLOR));
+    for &point in &state.points {
+        commands.spawn((
+            DraftPoint,
+            Mesh2d(mesh.clone()),
+            MeshMaterial2d(material.clone()),
+            Transform::from_translation(level_to_world(point).extend(LABEL_Z)),
+            ScreenScale::Uniform,
+        ));
+    }
+}
+
+fn despawn_labels(query: Query<Entity, With<EditorLabel>>, mut commands: Commands) {
+    for entity in &query {
+        commands.entity(entity).despawn();
+    }
+}
+
+/// Block IDs with their lengths and the names of all signals, automatic ones included
+fn spawn_labels(
+    handles: Res<AssetHandles>,
+    levels: Res<Assets<Level>>,
+    geometry: Res<TrackGeometry>,
+    mut commands: Commands,
+) {
+    let Some(level) = levels.get(&handles.level) else {
+        return;
+    };
+    let mut spawn_label = |text: String, position: Vec2, color: Color| {
+        commands.spawn((
+            EditorLabel,
+            Text2d::new(text),
+            TextFont::from_font_size(10.0),
+            TextColor(color),
+            Transform::from_translation(position.extend(LABEL_Z)),
+            ScreenScale::Uniform,
+        ));
+    };
+    for block in &level.blocks {
+        let Some(points) = geometry.polyline(block.id).filter(|points| !points.is_empty()) else {
+            continue;
+        };
+        let middle = (points[(points.len() - 1) / 2] + points[points.len() / 2]) / 2.0;
+        let label = format!("{}: {} m", block.id, block.length);
+        spawn_label(label, middle + Vec2::Y * LABEL_OFFSET, LABEL_COLOR);
+    }
+    for signal in &level.signals {
+        let Some((first, last)) = geometry.endpoints(signal.block_id) else {
+            continue;
+        };
+        let length = edit::block_length(level, signal.block_id).unwrap_or_default();
+        let node = if signal.offset_m >= length / 2.0 { last } else { first };
+        spawn_label(signal.name.clone(), node - Vec2::Y * LABEL_OFFSET, SIGNAL_LABEL_COLOR);
+    }
+}
@@ -0,0 +1,587 @@
+//! Runs the simulation without a window for a given number of simulated seconds, replaying
+//! a scripted command file, and prints a summary of train moves and signal changes.
+//!
+//! Usage: `rail-headless <seconds> [script] [--level <name>] [--seed <n>] [--record <path>] [--replay <path>] [--verbose]`
+//!
+//! The level is a file name in `resources/levels` without the extension, `passing_loop` by default.
+//! Runs with the same seed and script produce the same summary. `--record` writes the dispatcher's
+//! commands to a recording, `--replay` feeds a recording back on its level with its seed.
+//!
+//! Script lines are `<time_s> <command> <args...>`, `#` starts a comment:
+//! - `route <route_id>` / `cancel <route_id>` — set or cancel a route
+//! - `spawn <block_id> <consist_id> [number]` — spawn a train at a spawner
+//! - `switch <switch_id> straight|side|block|unblock` — throw a switch, or block it in its position
+//! - `clear switch|train <id>` — repair a damaged switch or remove a failed train
+//! - `ack <signal_id>` — acknowledge the SPADs at a signal, releasing their trains
+//! - `tsr <block_id> <speed_kmh>|off` — impose or lift a temporary speed restriction
+//! - `ars on|off <signal_id>...` — toggle automatic route setting
+//! - `save <path>` / `load <path>` — save the game state to a file or restore it
+
+use bevy::app::ScheduleRunnerPlugin;
+use bevy::asset::AssetPlugin;
+use bevy::ecs::system::SystemParam;
+use bevy::log::{Level, LogPlugin};
+use bevy::prelude::*;
+use bevy::state::app::StatesPlugin;
+use bevy::time::TimeUpdateStrategy;
+use rail_dispatch::assets::{AssetLoadingPlugin, LoadingState};
+use rail_dispatch::common::{BlockId, CommandSource, RouteId, SignalId, SwitchId, SwitchPosition, TrainId, arg_value};
+use rail_dispatch::level::{DEFAULT_LEVEL, LevelPlugin};
+use rail_dispatch::replay::{Recording, ReplayPlugin, replay_options_from_args};
+use rail_dispatch::rolling_stock::RollingStockPlugin;
+use rail_dispatch::save::{LoadRequest, SavePlugin, SaveRequest};
+use rail_dispatch::simulation::ars::{ArsPlugin, ArsToggle};
+use rail_dispatch::simulation::block::{BlockMap, MapPlugin, SignalAspectChanged, TemporarySpeedRestriction};
+use rail_dispatch::simulation::incident::{ClearanceRequest, Incident, IncidentPlugin};
+use rail_dispatch::simulation::random::{RandomPlugin, SimulationRng, seed_from_args};
+use rail_dispatch::simulation::signal::{SignalAspect, SpeedLimit};
+use rail_dispatch::simulation::spad::{SignalPassedAtDanger, SpadAcknowledgement, SpadPlugin};
+use rail_dispatch::simulation::spawner::{SpawnRequest, SpawnTrainType, SpawnerPlugin};
+use rail_dispatch::simulation::station::{
+    RouteActivationRequest, RouteCancellationRequest, StationPlugin, SwitchBlocking, SwitchUpdate,
+};
+use rail_dispatch::simulation::timetable::TimetablePlugin;
+use rail_dispatch::simulation::train::{TrainDespawnRequest, TrainMove, TrainMoveKind, TrainPlugin};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+/// Simulation step, both the fixed timestep and the virtual time advanced per frame
+const STEP: Duration = Duration::from_micros(15_625);
+
+#[derive(Debug, PartialEq)]
+enum ScriptCommand {
+    Route(RouteId),
+    Cancel(RouteId),
+    Spawn(BlockId, String, Option<String>),
+    Switch(SwitchId, SwitchPosition),
+    BlockSwitch(SwitchId, bool),
+    Clear(ClearanceRequest),
+    Acknowledge(SignalId),
+    Restrict(BlockId, SpeedLimit),
+    Ars(bool, Vec<SignalId>),
+    Save(PathBuf),
+    Load(PathBuf),
+}
+
+fn parse_command(args: &[&str]) -> Result<ScriptCommand, String> {
+    fn parse<T: std::str::FromStr>(arg: Option<&&str>, what: &str) -> Result<T, String> {
+        let arg = arg.ok_or_else(|| format!("missing {}", what))?;
+        arg.parse().map_err(|_| format!("invalid {} '{}'", what, arg))
+    }
+
+    let command = match args.first().copied() {
+        Some("route") => ScriptCommand::Route(parse(args.get(1), "route ID")?),
+        Some("cancel") => ScriptCommand::Cancel(parse(args.get(1), "route ID")?),
+        Some("spawn") => ScriptCommand::Spawn(
+            parse(args.get(1), "block ID")?,
+            parse(args.get(2), "consist ID")?,
+            args.get(3).map(|s| s.to_string()),
+        ),
+        Some("switch") => {
+            let switch_id = parse(args.get(1), "switch ID")?;
+            match args.get(2).copied() {
+                Some("straight") => ScriptCommand::Switch(switch_id, SwitchPosition::Straight),
+                Some("side") => ScriptCommand::Switch(switch_id, SwitchPosition::Side),
+                Some("block") => ScriptCommand::BlockSwitch(switch_id, true),
+                Some("unblock") => ScriptCommand::BlockSwitch(switch_id, false),
+                other => return Err(format!("invalid switch position {:?}", other)),
+            }
+        }
+        Some("clear") => match args.get(1).copied() {
+            Some("switch") => ScriptCommand::Clear(ClearanceRequest::Switch(parse(args.get(2), "switch ID")?)),
+            Some("train") => ScriptCommand::Clear(ClearanceRequest::Train(parse(args.get(2), "train ID")?)),
+            other => return Err(format!("expected switch or train, got {:?}", other)),
+        },
+        Some("ack") => ScriptCommand::Acknowledge(parse(args.get(1), "signal ID")?),
+        Some("tsr") => {
+            let speed_limit = match args.get(2).copied() {
+                Some("off") => SpeedLimit::Unrestricted,
+                _ => SpeedLimit::Restricted(parse(args.get(2), "speed")?),
+            };
+            ScriptCommand::Restrict(parse(args.get(1), "block ID")?, speed_limit)
+        }
+        Some("ars") => {
+            let enabled = match args.get(1).copied() {
+                Some("on") => true,
+                Some("off") => false,
+                other => return Err(format!("expected on or off, got {:?}", other)),
+            };
+            let signals = args[2..]
+                .iter()
+                .map(|arg| parse(Some(arg), "signal ID"))
+                .collect::<Result<_, _>>()?;
+            ScriptCommand::Ars(enabled, signals)
+        }
+        Some("save") => ScriptCommand::Save(parse(args.get(1), "path")?),
+        Some("load") => ScriptCommand::Load(parse(args.get(1), "path")?),
+        Some(other) => return Err(format!("unknown command '{}'", other)),
+        None => return Err("missing command".to_string()),
+    };
+    Ok(command)
+}
+
+/// Parses a script into commands ordered by time
+fn parse_script(contents: &str) -> Result<Vec<(f64, ScriptCommand)>, String> {
+    let mut commands = Vec::new();
+    for (idx, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        let args: Vec<&str> = line.split_whitespace().collect();
+        let Some((time, args)) = args.split_first() else {
+            continue;
+        };
+        let result = time
+            .parse::<f64>()
+            .map_err(|_| format!("invalid time '{}'", time))
+            .and_then(|time_s| Ok((time_s, parse_command(args)?)));
+        commands.push(result.map_err(|e| format!("line {}: {}", idx + 1, e))?);
+    }
+    commands.sort_by(|a, b| a.0.total_cmp(&b.0));
+    Ok(commands)
+}
+
+struct Options {
+    duration_s: f64,
+    script: Vec<(f64, ScriptCommand)>,
+    level: String,
+    seed: Option<u64>,
+    record: Option<PathBuf>,
+    replay: Option<Recording>,
+    verbose: bool,
+}
+
+/// Options followed by a value
+const VALUE_OPTIONS: [&str; 4] = ["--level", "--seed", "--record", "--replay"];
+
+const USAGE: &str = "usage: rail-headless <seconds> [script] [--level <name>] [--seed <n>] [--record <path>] [--replay <path>] [--verbose]";
+
+fn parse_options() -> Result<Options, String> {
+    let all_args: Vec<String> = std::env::args().skip(1).collect();
+    let (record, replay) = replay_options_from_args(&all_args)?;
+    // A replay runs on the recorded level
+    let level = match replay.as_ref().and_then(|r| r.level_name()) {
+        Some(name) => name.to_string(),
+        None => arg_value(&all_args, "--level")?.unwrap_or(DEFAULT_LEVEL).to_string(),
+    };
+    let seed = replay.as_ref().map(|r| r.seed).or(seed_from_args(&all_args)?);
+    let is_option = |idx: usize| {
+        let arg = all_args[idx].as_str();
+        arg == "--verbose"
+            || VALUE_OPTIONS.contains(&arg)
+            || (idx > 0 && VALUE_OPTIONS.contains(&all_args[idx - 1].as_str()))
+    };
+    let mut args = (0..all_args.len())
+        .filter(|&idx| !is_option(idx))
+        .map(|idx| all_args[idx].clone());
+    let duration_s = args
+        .next()
+        .ok_or(USAGE)?
+        .parse()
+        .map_err(|_| "duration must be a number of seconds")?;
+    let script = match args.next() {
+        Some(path) => {
+            let contents = std::fs::read_to_string(&path).map_err(|e| format!("can't read {}: {}", path, e))?;
+            parse_script(&contents).map_err(|e| format!("{}: {}", path, e))?
+        }
+        None => Vec::new(),
+    };
+    Ok(Options {
+        duration_s,
+        script,
+        level,
+        seed,
+        record,
+        replay,
+        verbose: all_args.iter().any(|arg| arg == "--verbose"),
+    })
+}
+
+/// State of the run: the remaining script and the recorded events
+#[derive(Resource)]
+struct HeadlessRun {
+    duration_s: f64,
+    start_s: f64,
+    /// Remaining commands in reverse order, so the next one is popped from the end
+    script: Vec<(f64, ScriptCommand)>,
+    train_numbers: HashMap<TrainId, String>,
+    train_moves: Vec<(f64, String, BlockId)>,
+    despawns: Vec<(f64, String)>,
+    signal_changes: Vec<(f64, SignalId, SignalAspect)>,
+    incidents: Vec<(f64, Incident)>,
+    spads: Vec<(f64, SignalPassedAtDanger)>,
+}
+
+impl HeadlessRun {
+    fn print_summary(&self, block_map: &BlockMap) {
+        let signal_name = |signal_id: SignalId| block_map.signal(signal_id).map_or("?", |s| s.name.as_str());
+        let aspect_name = |aspect: SignalAspect| match aspect {
+            SignalAspect::Unrestricting => "open",
+            SignalAspect::Restricting => "restricting",
+            SignalAspect::Forbidding => "closed",
+        };
+
+        println!("== Train moves ==");
+        for (time_s, number, block_id) in &self.train_moves {
+            println!("{:>9.1} s  train {} entered block {}", time_s, number, block_id);
+        }
+        println!("== Signal changes ==");
+        for &(time_s, signal_id, aspect) in &self.signal_changes {
+            println!(
+                "{:>9.1} s  signal {} ({}) {}",
+                time_s,
+                signal_name(signal_id),
+                signal_id,
+                aspect_name(aspect)
+            );
+        }
+        println!("== Incidents ==");
+        for &(time_s, incident) in &self.incidents {
+            let number = self.train_numbers.get(&incident.train_id()).map_or("?", String::as_str);
+            match incident {
+                Incident::SwitchDamaged { switch_id, .. } => {
+                    println!("{:>9.1} s  train {} trailed switch {}", time_s, number, switch_id)
+                }
+                Incident::Derailment { switch_id, .. } => {
+                    println!("{:>9.1} s  train {} derailed on switch {}", time_s, number, switch_id)
+                }
+                Incident::BufferStopCollision { block_id, .. } => {
+                    println!(
+                        "{:>9.1} s  train {} hit the buffer stop of block {}",
+                        time_s, number, block_id
+                    )
+                }
+            }
+        }
+
+        println!("== SPADs ==");
+        for &(time_s, spad) in &self.spads {
+            let number = self.train_numbers.get(&spad.train_id).map_or("?", String::as_str);
+            println!(
+                "{:>9.1} s  train {} passed signal {} at danger, {:.1} km/h over",
+                time_s, number, spad.signal_id, spad.overspeed_kmh
+            );
+        }
+
+        println!("== Summary ==");
+        let mut trains: BTreeMap<&str, (usize, f64, BlockId)> = BTreeMap::new();
+        for (time_s, number, block_id) in &self.train_moves {
+            let entry = trains.entry(number).or_insert((0, *time_s, *block_id));
+            entry.0 += 1;
+            entry.2 = *block_id;
+        }
+        for (number, (blocks, first_s, last_block)) in &trains {
+            let despawned = self.despawns.iter().find(|(_, n)| n == number);
+            match despawned {
+                Some((time_s, _)) => println!(
+                    "train {}: entered {} blocks, appeared at {:.1} s, left at {:.1} s",
+                    number, blocks, first_s, time_s
+                ),
+                None => println!(
+                    "train {}: entered {} blocks, appeared at {:.1} s, last in block {}",
+                    number, blocks, first_s, last_block
+                ),
+            }
+        }
+        let mut signals: BTreeMap<SignalId, usize> = BTreeMap::new();
+        for (_, signal_id, _) in &self.signal_changes {
+            *signals.entry(*signal_id).or_default() += 1;
+        }
+        for (signal_id, changes) in signals {
+            println!("signal {} ({}): {} changes", signal_name(signal_id), signal_id, changes);
+        }
+    }
+}
+
+#[derive(SystemParam)]
+struct ScriptWriters<'w> {
+    route_activations: MessageWriter<'w, RouteActivationRequest>,
+    route_cancellations: MessageWriter<'w, RouteCancellationRequest>,
+    switch_updates: MessageWriter<'w, SwitchUpdate>,
+    switch_blocking: MessageWriter<'w, SwitchBlocking>,
+    clearances: MessageWriter<'w, ClearanceRequest>,
+    spad_acknowledgements: MessageWriter<'w, SpadAcknowledgement>,
+    restrictions: MessageWriter<'w, TemporarySpeedRestriction>,
+    ars_toggles: MessageWriter<'w, ArsToggle>,
+    saves: MessageWriter<'w, SaveRequest>,
+    loads: MessageWriter<'w, LoadRequest>,
+}
+
+fn start_run(time: Res<Time>, mut run: ResMut<HeadlessRun>) {
+    run.start_s = time.elapsed_secs_f64();
+}
+
+fn run_script(time: Res<Time>, mut run: ResMut<HeadlessRun>, mut writers: ScriptWriters, mut commands: Commands) {
+    let now_s = time.elapsed_secs_f64() - run.start_s;
+    while run.script.last().is_some_and(|(time_s, _)| *time_s <= now_s) {
+        let (_, command) = run.script.pop().expect("checked above");
+        match command {
+            ScriptCommand::Route(route_id) => {
+                writers.route_activations.write(RouteActivationRequest {
+                    route_id,
+                    source: CommandSource::Dispatcher,
+                });
+            }
+            ScriptCommand::Cancel(route_id) => {
+                writers.route_cancellations.write(RouteCancellationRequest {
+                    route_id,
+                    source: CommandSource::Dispatcher,
+                });
+            }
+            ScriptCommand::Spawn(block_id, consist, number) => commands.trigger(SpawnRequest {
+                block_id,
+                train_type: SpawnTrainType(consist),
+                number,
+                destination: None,
+                priority: 0,
+                calling_at: Vec::new(),
+                exit_time_s: None,
+                source: CommandSource::Dispatcher,
+            }),
+            ScriptCommand::Switch(switch_id, position) => {
+                writers
+                    .switch_updates
+                    .write(SwitchUpdate::new(switch_id, position, CommandSource::Dispatcher));
+            }
+            ScriptCommand::BlockSwitch(switch_id, blocked) => {
+                writers.switch_blocking.write(SwitchBlocking { switch_id, blocked });
+            }
+            ScriptCommand::Clear(request) => {
+                writers.clearances.write(request);
+            }
+            ScriptCommand::Acknowledge(signal_id) => {
+                writers.spad_acknowledgements.write(SpadAcknowledgement { signal_id });
+            }
+            ScriptCommand::Restrict(block_id, speed_limit) => {
+                writers.restrictions.write(TemporarySpeedRestriction {
+                    block_id,
+                    speed_limit,
+                    source: CommandSource::Dispatcher,
+                });
+            }
+            ScriptCommand::Ars(enabled, signals) => {
+                writers.ars_toggles.write(ArsToggle { signals, enabled });
+            }
+            ScriptCommand::Save(path) => {
+                writers.saves.write(SaveRequest { path });
+            }
+            ScriptCommand::Load(path) => {
+                writers.loads.write(LoadRequest { path });
+            }
+        }
+    }
+}
+
+fn record_events(
+    time: Res<Time>,
+    mut run: ResMut<HeadlessRun>,
+    mut train_moves: MessageReader<TrainMove>,
+    mut despawns: MessageReader<TrainDespawnRequest>,
+    mut aspects: MessageReader<SignalAspectChanged>,
+    mut incidents: MessageReader<Incident>,
+    mut spads: MessageReader<SignalPassedAtDanger>,
+) {
+    let now_s = time.elapsed_secs_f64() - run.start_s;
+    for mv in train_moves.read().filter(|mv| mv.kind == TrainMoveKind::Entered) {
+        run.train_numbers.insert(mv.train_id, mv.number.clone());
+        run.train_moves.push((now_s, mv.number.clone(), mv.block_id));
+    }
+    for despawn in despawns.read() {
+        if let Some(number) = run.train_numbers.get(&despawn.id).cloned() {
+            run.despawns.push((now_s, number));
+        }
+    }
+    for change in aspects.read() {
+        run.signal_changes.push((now_s, change.signal_id, change.aspect));
+    }
+    for incident in incidents.read() {
+        run.incidents.push((now_s, *incident));
+    }
+    for spad in spads.read() {
+        run.spads.push((now_s, *spad));
+    }
+}
+
+fn finish_run(
+    time: Res<Time>,
+    run: Res<HeadlessRun>,
+    block_map: Res<BlockMap>,
+    rng: Res<SimulationRng>,
+    mut exit: MessageWriter<AppExit>,
+) {
+    if time.elapsed_secs_f64() - run.start_s >= run.duration_s {
+        run.print_summary(&block_map);
+        println!("seed {}", rng.seed());
+        exit.write(AppExit::Success);
+    }
+}
+
+/// The headless simulation app for the options, without logging
+fn build_app(options: Options) -> App {
+    let mut script = options.script;
+    script.reverse();
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::ZERO)),
+        AssetPlugin {
+            file_path: "resources".to_string(),
+            // A run must not change halfway through when the level file is edited
+            watch_for_changes_override: Some(false),
+            ..default()
+        },
+        StatesPlugin,
+    ))
+    .insert_resource(Time::<Fixed>::from_duration(STEP))
+    .insert_resource(TimeUpdateStrategy::ManualDuration(STEP))
+    .insert_resource(HeadlessRun {
+        duration_s: options.duration_s,
+        start_s: 0.0,
+        script,
+        train_numbers: HashMap::new(),
+        train_moves: Vec::new(),
+        despawns: Vec::new(),
+        signal_changes: Vec::new(),
+        incidents: Vec::new(),
+        spads: Vec::new(),
+    })
+    .add_plugins((
+        LevelPlugin,
+        RollingStockPlugin,
+        AssetLoadingPlugin {
+            headless: true,
+            level: Some(options.level),
+        },
+        TrainPlugin,
+        SpawnerPlugin,
+        MapPlugin,
+        StationPlugin,
+        TimetablePlugin,
+        ArsPlugin,
+        IncidentPlugin,
+        SpadPlugin,
+        RandomPlugin { seed: options.seed },
+        SavePlugin,
+        ReplayPlugin {
+            record: options.record,
+            replay: options.replay,
+        },
+    ))
+    .add_systems(OnEnter(LoadingState::Instantiated), start_run)
+    // Commands are issued before the simulation systems run, as the panel's input is
+    .add_systems(PreUpdate, run_script.run_if(in_state(LoadingState::Instantiated)))
+    .add_systems(
+        Update,
+        (record_events, finish_run)
+            .chain()
+            .run_if(in_state(LoadingState::Instantiated)),
+    );
+    app
+}
+
+fn main() -> ExitCode {
+    let options = match parse_options() {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let level = if options.verbose { Level::INFO } else { Level::WARN };
+    let exit = build_app(options).add_plugins(LogPlugin { level, ..default() }).run();
+
+    match exit {
+        AppExit::Success => ExitCode::SUCCESS,
+        AppExit::Error(_) => ExitCode::FAILURE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::app::PluginsState;
+    use rail_dispatch::simulation::train::Train;
+
+    /// Runs the app to the end, returning the front positions of the trains left in the area
+    fn run_to_end(mut app: App) -> Vec<(String, BlockId, f64)> {
+        while app.plugins_state() == PluginsState::Adding {
+            bevy::tasks::tick_global_task_pools_on_main_thread();
+        }
+        app.finish();
+        app.cleanup();
+        while app.should_exit().is_none() {
+            app.update();
+        }
+        let world = app.world_mut();
+        let mut positions: Vec<_> = world
+            .query::<&Train>()
+            .iter(world)
+            .map(|train| {
+                let front = train.front_position();
+                (train.number.clone(), front.block_id, front.offset_m)
+            })
+            .collect();
+        positions.sort_by(|a, b| a.0.cmp(&b.0));
+        positions
+    }
+
+    #[test]
+    fn replayed_run_ends_with_the_recorded_train_positions() {
+        let path = std::env::temp_dir().join(format!("rail-headless-replay-{}.toml", std::process::id()));
+        let options = |script, record, replay: Option<Recording>| Options {
+            duration_s: 300.0,
+            script,
+            level: DEFAULT_LEVEL.to_string(),
+            seed: Some(replay.as_ref().map_or(7, |r| r.seed)),
+            record,
+            replay,
+            verbose: false,
+        };
+        let script = vec![
+            (0.0, ScriptCommand::Ars(true, vec![50, 51, 52, 53, 54, 55])),
+            (100.0, ScriptCommand::Restrict(6, SpeedLimit::Restricted(40.0))),
+            (150.0, ScriptCommand::Ars(false, vec![52])),
+        ];
+        let recorded = run_to_end(build_app(options(script, Some(path.clone()), None)));
+        let recording = Recording::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(recording.commands.len(), 3);
+        assert!(!recorded.is_empty());
+
+        let replayed = run_to_end(build_app(options(Vec::new(), None, Some(recording))));
+        assert_eq!(replayed, recorded);
+    }
+
+    #[test]
+    fn script_is_parsed_and_ordered() {
+        let script = parse_script(
+            "# warm-up\n\
+             30 route 1\n\
+             5.5 spawn 1 cargo 2402  # first train\n\
+             \n\
+             60 ars on 50 51\n\
+             90 tsr 6 off\n\
+             95 clear train 3\n\
+             96 ack 50\n",
+        )
+        .unwrap();
+        assert_eq!(
+            script,
+            [
+                (
+                    5.5,
+                    ScriptCommand::Spawn(1, "cargo".to_string(), Some("2402".to_string()))
+                ),
+                (30.0, ScriptCommand::Route(1)),
+                (60.0, ScriptCommand::Ars(true, vec![50, 51])),
+                (90.0, ScriptCommand::Restrict(6, SpeedLimit::Unrestricted)),
+                (95.0, ScriptCommand::Clear(ClearanceRequest::Train(3))),
+                (96.0, ScriptCommand::Acknowledge(50)),
+            ]
+        );
+        assert_eq!(
+            parse_script("10 switch 1 left").unwrap_err(),
+            "line 1: invalid switch position Some(\"left\")"
+        );
+    }
+}
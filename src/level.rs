@@ -1,57 +1,154 @@
+use crate::assets::{AssetHandles, LoadingState};
 use crate::common::{
-    BlockId, Direction, HexColor, RouteId, SectionId, SignalId, SignalType, StationId, SwitchId, SwitchPosition,
+    BlockId, Direction, HexColor, RailCondition, RouteId, SectionId, SignalId, SignalType, StationId, SwitchId,
+    SwitchPosition,
 };
-use bevy::{asset::AssetLoader, asset::LoadContext, asset::io::Reader, prelude::*};
+use crate::validation::{LevelIssues, validate_level};
+use bevy::{asset::AssetLoadFailedEvent, asset::AssetLoader, asset::LoadContext, asset::io::Reader, prelude::*};
 use futures_lite::AsyncReadExt;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
 use thiserror::Error;
 
-#[derive(Deserialize, Asset, Reflect)]
+/// Directory of the level files, relative to the asset root
+pub const LEVELS_DIR: &str = "levels";
+/// Level loaded when none is chosen and there is no menu to choose from
+pub const DEFAULT_LEVEL: &str = "passing_loop";
+
+/// Asset path of a level in the levels directory, the level name is its file stem
+pub fn level_asset_path(name: &str) -> String {
+    format!("{}/{}.toml", LEVELS_DIR, name)
+}
+
+/// Name of the level at the asset path, if it is in the levels directory
+pub fn level_name(asset_path: &str) -> Option<&str> {
+    asset_path
+        .strip_prefix(LEVELS_DIR)?
+        .strip_prefix('/')?
+        .strip_suffix(".toml")
+        .filter(|name| !name.contains('/'))
+}
+
+#[derive(Serialize, Deserialize, Reflect, PartialEq, Copy, Clone, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Difficulty {
+    #[default]
+    Easy,
+    Medium,
+    Hard,
+}
+
+/// Level metadata shown in the level menu
+#[derive(Serialize, Deserialize, Reflect, Clone, Default, Debug)]
+pub struct LevelInfo {
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub author: String,
+    #[serde(default)]
+    pub description: String,
+    /// Recommended difficulty
+    #[serde(default)]
+    pub difficulty: Difficulty,
+}
+
+/// Names and metadata of the levels in a directory, ordered by name. Files that can't be read are skipped.
+pub fn list_levels(dir: &Path) -> Vec<(String, LevelInfo)> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Can't list levels in {}: {}", dir.display(), e);
+            return Vec::new();
+        }
+    };
+    let mut levels: Vec<(String, LevelInfo)> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .filter_map(|path| {
+            let name = path.file_stem()?.to_string_lossy().into_owned();
+            // Only the metadata is parsed, the other level keys are ignored
+            let info = std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|contents| toml::from_str::<LevelInfo>(&contents).map_err(|e| e.to_string()));
+            match info {
+                Ok(info) => Some((name, info)),
+                Err(e) => {
+                    warn!("Skipping level {}: {}", path.display(), e);
+                    None
+                }
+            }
+        })
+        .collect();
+    levels.sort_by(|a, b| a.0.cmp(&b.0));
+    levels
+}
+
+#[derive(Serialize, Deserialize, Asset, Reflect, Clone)]
 pub struct Level {
+    #[serde(flatten)]
+    pub info: LevelInfo,
     pub blocks: Vec<BlockData>,
     pub connections: Vec<ConnectionData>,
     pub switches: Vec<SwitchData>,
     pub spawners: Vec<SpawnerData>,
     pub signals: Vec<SignalData>,
     #[serde(default)]
+    pub speed_limits: Vec<SpeedLimitData>,
+    #[serde(default)]
     pub sections: Vec<SectionData>,
     #[serde(default)]
     pub stations: Vec<StationData>,
     #[serde(default)]
+    pub timetable: Vec<ServiceData>,
+    #[serde(default)]
     pub geometry: Vec<BlockGeometry>,
+    #[serde(default)]
+    pub rail_condition: RailCondition,
+    /// Seed for the simulation's random draws, a random one is used when omitted
+    #[serde(default)]
+    pub seed: Option<u64>,
     pub background: HexColor,
 }
 
 /// Schematic geometry for a block: a polyline (in level pixel space) along which the
 /// block's track is drawn. The panel renderer maps this to world space.
-#[derive(Deserialize, Reflect)]
+#[derive(Serialize, Deserialize, Reflect, Clone, PartialEq)]
 pub struct BlockGeometry {
     pub id: BlockId,
     pub points: Vec<Vec2>,
 }
 
-#[derive(Deserialize, Reflect)]
+#[derive(Serialize, Deserialize, Reflect, Clone, PartialEq)]
 pub struct BlockData {
     pub id: BlockId,
     pub length: f64,
+    /// Track gradient in per mille, positive when rising in the even direction
+    #[serde(default)]
+    pub gradient_permille: f64,
+    #[serde(default)]
+    pub curve_radius_m: Option<f64>,
 }
 
-#[derive(Deserialize, Reflect)]
+#[derive(Serialize, Deserialize, Reflect, Clone, PartialEq)]
 pub struct ConnectionData {
     pub start: BlockId,
     pub end: BlockId,
 }
 
-#[derive(Deserialize, Reflect)]
+#[derive(Serialize, Deserialize, Reflect, Clone, PartialEq)]
 pub struct SwitchData {
     pub id: SwitchId,
     pub base: BlockId,
     pub straight: BlockId,
     pub side: BlockId,
     pub direction: Direction,
+    /// Seconds the point machine takes to move the switch, a default time is used when omitted
+    #[serde(default)]
+    pub throw_time_s: Option<f32>,
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Deserialize, Reflect)]
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize, Reflect)]
 #[serde(rename_all = "lowercase")]
 pub enum SpawnerKind {
     Spawn,
@@ -59,7 +156,7 @@ pub enum SpawnerKind {
     Both,
 }
 
-#[derive(Deserialize, Reflect)]
+#[derive(Serialize, Deserialize, Reflect, Clone, PartialEq)]
 pub struct SpawnerData {
     pub block_id: BlockId,
     pub kind: SpawnerKind,
@@ -73,7 +170,7 @@ pub struct SpawnerData {
     pub y: f32,
 }
 
-#[derive(Deserialize, Reflect)]
+#[derive(Serialize, Deserialize, Reflect, Clone, PartialEq)]
 pub struct SignalData {
     pub id: SignalId,
     pub block_id: BlockId,
@@ -84,19 +181,28 @@ pub struct SignalData {
     pub signal_type: SignalType,
 }
 
-#[derive(Deserialize, Reflect)]
+/// Permanent line speed limit over a range of a block, offsets are measured in the even direction
+#[derive(Serialize, Deserialize, Reflect, Clone, PartialEq)]
+pub struct SpeedLimitData {
+    pub block_id: BlockId,
+    pub from_offset_m: f64,
+    pub to_offset_m: f64,
+    pub speed_kmh: f64,
+}
+
+#[derive(Serialize, Deserialize, Reflect, Clone, PartialEq)]
 pub struct SectionData {
     pub id: SectionId,
     pub blocks: Vec<BlockId>,
 }
 
-#[derive(Deserialize, Reflect, Clone)]
+#[derive(Serialize, Deserialize, Reflect, Clone, PartialEq)]
 pub struct SwitchSetting {
     pub switch_id: SwitchId,
     pub position: SwitchPosition,
 }
 
-#[derive(Deserialize, Reflect)]
+#[derive(Serialize, Deserialize, Reflect, Clone, PartialEq)]
 pub struct RouteData {
     pub id: RouteId,
     pub signal: SignalId,
@@ -104,20 +210,120 @@ pub struct RouteData {
     pub target: BlockId,
     #[serde(default)]
     pub switches: Vec<SwitchSetting>,
+    /// Speed limit for passing the route's signal, e.g. for diverging routes over switch side legs
+    #[serde(default)]
+    pub speed_kmh: Option<f64>,
 }
 
-#[derive(Deserialize, Reflect)]
+#[derive(Serialize, Deserialize, Reflect, Clone, PartialEq)]
 pub struct StationData {
     pub id: StationId,
     pub name: String,
     pub routes: Vec<RouteData>,
+    #[serde(default)]
+    pub platforms: Vec<PlatformData>,
+}
+
+/// Platform track of a station with the stopping marks for each direction,
+/// offsets are measured in the even direction
+#[derive(Serialize, Deserialize, Reflect, Clone, PartialEq)]
+pub struct PlatformData {
+    pub block_id: BlockId,
+    pub even_stop_m: f64,
+    pub odd_stop_m: f64,
+}
+
+/// A scheduled train entering the area through a spawner, times are seconds of virtual time since the shift start
+#[derive(Serialize, Deserialize, Reflect, Clone, PartialEq)]
+pub struct ServiceData {
+    pub number: String,
+    /// Consist ID in the rolling stock catalog
+    pub consist: String,
+    /// Spawner block the train enters through
+    pub entry: BlockId,
+    pub entry_time_s: f64,
+    /// Despawner block the train is planned to leave through
+    pub exit: BlockId,
+    /// Planned exit time, it can't be before the entry or a planned departure
+    pub exit_time_s: f64,
+    #[serde(default)]
+    pub stops: Vec<StopData>,
+    /// Services with higher priority get their routes set first by automatic route setting
+    #[serde(default)]
+    pub priority: u8,
+}
+
+/// Planned stop at a station, the train dwells there for `departure_s - arrival_s`
+#[derive(Serialize, Deserialize, Reflect, Clone, PartialEq)]
+pub struct StopData {
+    pub station: StationId,
+    pub arrival_s: f64,
+    pub departure_s: f64,
+}
+
+impl Level {
+    /// Reports every problem in the level data at once
+    pub fn validate(&self) -> Result<(), LevelIssues> {
+        let issues = validate_level(self);
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(LevelIssues(issues))
+        }
+    }
+
+    /// The shipped passing loop level, shared by the tests
+    #[cfg(test)]
+    pub fn test_fixture() -> Level {
+        toml::from_str(include_str!("../resources/levels/passing_loop.toml")).unwrap()
+    }
+}
+
+/// Sent when the level file has changed and the new version has been loaded
+#[derive(Message)]
+pub struct LevelReloaded;
+
+/// Sent when the changed level file could not be loaded, the previous version stays in use
+#[derive(Message)]
+pub struct LevelReloadFailed {
+    pub error: String,
 }
 
 pub struct LevelPlugin;
 
 impl Plugin for LevelPlugin {
     fn build(&self, app: &mut App) {
-        app.init_asset::<Level>().register_asset_loader(LevelLoader);
+        app.init_asset::<Level>()
+            .register_asset_loader(LevelLoader)
+            .add_message::<LevelReloaded>()
+            .add_message::<LevelReloadFailed>()
+            .add_systems(
+                PreUpdate,
+                watch_level.run_if(in_state(LoadingState::Loaded).or(in_state(LoadingState::Instantiated))),
+            );
+    }
+}
+
+/// Turns the asset events of the loaded level into reload messages. The asset server only
+/// reloads changed files when watching for changes is enabled.
+pub fn watch_level(
+    handles: Res<AssetHandles>,
+    mut asset_events: MessageReader<AssetEvent<Level>>,
+    mut failures: MessageReader<AssetLoadFailedEvent<Level>>,
+    mut reloads: MessageWriter<LevelReloaded>,
+    mut reload_failures: MessageWriter<LevelReloadFailed>,
+) {
+    let level_id = handles.level.id();
+    let modified = asset_events.read().filter(|event| event.is_modified(level_id)).count();
+    if modified > 0 {
+        info!("Level reloaded");
+        reloads.write(LevelReloaded);
+    }
+    for failure in failures.read().filter(|failure| failure.id == level_id) {
+        warn!("Level reload failed: {}", failure.error);
+        reload_failures.write(LevelReloadFailed {
+            error: failure.error.to_string(),
+        });
     }
 }
 
@@ -130,6 +336,8 @@ enum LevelLoaderError {
     Io(#[from] std::io::Error),
     #[error("Could not parse level file: {0}")]
     FileTexture(#[from] toml::de::Error),
+    #[error("Invalid level:\n{0}")]
+    Invalid(#[from] LevelIssues),
 }
 
 impl AssetLoader for LevelLoader {
@@ -145,10 +353,53 @@ impl AssetLoader for LevelLoader {
     ) -> Result<Self::Asset, Self::Error> {
         let mut contents = String::new();
         reader.read_to_string(&mut contents).await?;
-        Ok(toml::from_str(&contents)?)
+        let level: Level = toml::from_str(&contents)?;
+        level.validate()?;
+        Ok(level)
     }
 
     fn extensions(&self) -> &[&str] {
         &["toml"]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level_round_trips_through_toml() {
+        let level = Level::test_fixture();
+        let contents = toml::to_string(&level).unwrap();
+        let written: Level = toml::from_str(&contents).unwrap();
+        assert!(written.validate().is_ok());
+        assert_eq!(written.blocks.len(), level.blocks.len());
+        assert_eq!(toml::to_string(&written).unwrap(), contents);
+    }
+
+    #[test]
+    fn shipped_levels_are_listed() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("resources").join(LEVELS_DIR);
+        let levels = list_levels(&dir);
+        let names: Vec<&str> = levels.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["branch_junction", "passing_loop"]);
+        assert_eq!(levels[0].1.title, "Branch Junction");
+        assert_eq!(levels[0].1.difficulty, Difficulty::Medium);
+        for (name, _) in &levels {
+            let contents = std::fs::read_to_string(dir.join(format!("{}.toml", name))).unwrap();
+            let level: Level = toml::from_str(&contents).unwrap();
+            assert_eq!(level.validate(), Ok(()), "level {}", name);
+        }
+        assert!(list_levels(&dir.join("missing")).is_empty());
+    }
+
+    #[test]
+    fn level_names_follow_asset_paths() {
+        assert_eq!(
+            level_name(&level_asset_path("branch_junction")),
+            Some("branch_junction")
+        );
+        assert_eq!(level_name("levels/nested/level.toml"), None);
+        assert_eq!(level_name("other/passing_loop.toml"), None);
+    }
+}
@@ -3,6 +3,12 @@ pub mod audio;
 pub mod common;
 pub mod dropdown_menu;
 pub mod level;
+pub mod menu;
 pub mod panel;
+pub mod reload;
+pub mod replay;
+pub mod rolling_stock;
+pub mod save;
 pub mod simulation;
 pub mod time_controls;
+pub mod validation;
@@ -5,16 +5,41 @@ use bevy::prelude::*;
 use bevy::window::ExitCondition;
 use rail_dispatch::assets::AssetLoadingPlugin;
 use rail_dispatch::audio::AudioPlugin;
+use rail_dispatch::common::arg_value;
 use rail_dispatch::dropdown_menu::DropdownPlugin;
 use rail_dispatch::level::LevelPlugin;
+use rail_dispatch::menu::LevelMenuPlugin;
 use rail_dispatch::panel::PanelPlugin;
+use rail_dispatch::reload::ReloadPlugin;
+use rail_dispatch::replay::{ReplayPlugin, replay_options_from_args};
+use rail_dispatch::rolling_stock::RollingStockPlugin;
+use rail_dispatch::save::SavePlugin;
+use rail_dispatch::simulation::ars::ArsPlugin;
 use rail_dispatch::simulation::block::MapPlugin;
+use rail_dispatch::simulation::incident::IncidentPlugin;
+use rail_dispatch::simulation::random::{RandomPlugin, seed_from_args};
+use rail_dispatch::simulation::spad::SpadPlugin;
 use rail_dispatch::simulation::spawner::SpawnerPlugin;
 use rail_dispatch::simulation::station::StationPlugin;
+use rail_dispatch::simulation::timetable::TimetablePlugin;
 use rail_dispatch::simulation::train::TrainPlugin;
 use rail_dispatch::time_controls::TimeControlsPlugin;
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    fn exit_with(message: String) -> ! {
+        eprintln!("{}", message);
+        std::process::exit(1);
+    }
+    let level = arg_value(&args, "--level")
+        .unwrap_or_else(|message| exit_with(message))
+        .map(String::from);
+    let seed = seed_from_args(&args).unwrap_or_else(|message| exit_with(message));
+    let (record, replay) = replay_options_from_args(&args).unwrap_or_else(|message| exit_with(message));
+    // A replay only matches the recorded run on the recorded level with the recorded seed
+    let level = replay.as_ref().and_then(|r| r.level_name()).map(String::from).or(level);
+    let seed = replay.as_ref().map(|r| r.seed).or(seed);
+
     App::new()
         .add_plugins((
             DefaultPlugins
@@ -47,7 +72,8 @@ fn main() {
             MeshPickingPlugin,
             DropdownPlugin,
             LevelPlugin,
-            AssetLoadingPlugin,
+            RollingStockPlugin,
+            AssetLoadingPlugin { headless: false, level },
             TimeControlsPlugin,
             PanelPlugin,
             AudioPlugin,
@@ -55,6 +81,17 @@ fn main() {
             SpawnerPlugin,
             MapPlugin,
             StationPlugin,
+            TimetablePlugin,
+            ArsPlugin,
+            RandomPlugin { seed },
+        ))
+        .add_plugins((
+            LevelMenuPlugin,
+            SavePlugin,
+            ReloadPlugin,
+            ReplayPlugin { record, replay },
+            IncidentPlugin,
+            SpadPlugin,
         ))
         .run();
 }
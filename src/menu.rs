@@ -0,0 +1,128 @@
+use crate::assets::{LoadingState, SelectedLevel};
+use crate::level::{Difficulty, LEVELS_DIR, LevelInfo, level_asset_path, list_levels};
+use bevy::asset::io::file::FileAssetReader;
+use bevy::prelude::*;
+
+const ENTRY_BACKGROUND_DEFAULT: BackgroundColor = BackgroundColor(Color::srgb(0.15, 0.15, 0.15));
+const ENTRY_BACKGROUND_HIGHLIGHT: BackgroundColor = BackgroundColor(Color::srgb(0.31, 0.31, 0.31));
+const SECONDARY_TEXT: Color = Color::srgb(0.7, 0.7, 0.7);
+
+/// Lists the levels in the levels directory and loads the one clicked
+pub struct LevelMenuPlugin;
+
+impl Plugin for LevelMenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(LoadingState::SelectingLevel), spawn_menu);
+    }
+}
+
+/// A menu entry, holding the level name
+#[derive(Component)]
+struct LevelEntry(String);
+
+fn difficulty_label(difficulty: Difficulty) -> &'static str {
+    match difficulty {
+        Difficulty::Easy => "easy",
+        Difficulty::Medium => "medium",
+        Difficulty::Hard => "hard",
+    }
+}
+
+fn spawn_menu(mut commands: Commands) {
+    let dir = FileAssetReader::get_base_path().join("resources").join(LEVELS_DIR);
+    let levels = list_levels(&dir);
+
+    commands
+        .spawn((
+            DespawnOnExit(LoadingState::SelectingLevel),
+            Node {
+                width: percent(100),
+                height: percent(100),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                row_gap: px(8),
+                ..default()
+            },
+        ))
+        .with_children(|p| {
+            p.spawn((Text::new("Select a level"), TextFont::from_font_size(24.0)));
+            if levels.is_empty() {
+                p.spawn((
+                    Text::new(format!("No levels found in {}", dir.display())),
+                    TextFont::from_font_size(14.0),
+                    TextColor(SECONDARY_TEXT),
+                ));
+            }
+            for (name, info) in levels {
+                spawn_entry(p, name, info);
+            }
+        });
+}
+
+fn spawn_entry(parent: &mut ChildSpawnerCommands, name: String, info: LevelInfo) {
+    let title = if info.title.is_empty() {
+        name.clone()
+    } else {
+        info.title
+    };
+    let mut details = difficulty_label(info.difficulty).to_string();
+    if !info.author.is_empty() {
+        details += &format!(", by {}", info.author);
+    }
+
+    parent
+        .spawn((
+            LevelEntry(name),
+            Node {
+                width: px(480),
+                padding: UiRect::all(px(8)),
+                border: UiRect::all(px(1)),
+                border_radius: BorderRadius::all(px(3.0)),
+                flex_direction: FlexDirection::Column,
+                row_gap: px(2),
+                ..default()
+            },
+            ENTRY_BACKGROUND_DEFAULT,
+            BorderColor::all(Color::WHITE),
+        ))
+        .with_children(|entry| {
+            entry.spawn((Text::new(title), TextFont::from_font_size(18.0), Pickable::IGNORE));
+            entry.spawn((
+                Text::new(details),
+                TextFont::from_font_size(12.0),
+                TextColor(SECONDARY_TEXT),
+                Pickable::IGNORE,
+            ));
+            if !info.description.is_empty() {
+                entry.spawn((
+                    Text::new(info.description),
+                    TextFont::from_font_size(14.0),
+                    Pickable::IGNORE,
+                ));
+            }
+        })
+        .observe(|event: On<Pointer<Over>>, mut commands: Commands| {
+            commands.entity(event.entity).insert(ENTRY_BACKGROUND_HIGHLIGHT);
+        })
+        .observe(|event: On<Pointer<Out>>, mut commands: Commands| {
+            commands.entity(event.entity).insert(ENTRY_BACKGROUND_DEFAULT);
+        })
+        .observe(select_level);
+}
+
+fn select_level(
+    event: On<Pointer<Click>>,
+    entries: Query<&LevelEntry>,
+    mut next_loading_state: ResMut<NextState<LoadingState>>,
+    mut commands: Commands,
+) {
+    if event.button != PointerButton::Primary {
+        return;
+    }
+    if let Ok(entry) = entries.get(event.entity) {
+        info!("Selected level {}", entry.0);
+        commands.insert_resource(SelectedLevel(level_asset_path(&entry.0)));
+        next_loading_state.set(LoadingState::Loading);
+    }
+}
@@ -9,31 +9,60 @@
 //!   train-describer labels, plus picking/menus/tooltips.
 //!
 //! - Track is a continuous line per block (a rotated [`Rectangle`] mesh per segment).
-//!   A block is yellow when occupied, green while pending under a set route, else gray
-//!   (occupied > pending > free). The panel never polls: occupancy follows `BlockUpdate`,
+//!   A block is yellow when occupied, blue while a switch joining it is moving, green while
+//!   pending under a set route, else gray (occupied > moving switch > pending > free). The panel
+//!   never polls: occupancy follows `BlockUpdate`, moving switches follow `SwitchStateChanged`,
 //!   the pending path follows `RoutePending`, and the green path is consumed block-by-block
-//!   as occupancy arrives.
+//!   as occupancy arrives and section-by-section as `RouteSectionReleased` arrives.
+//! - Each switch has a marker at the junction of its base and legs, and a gap is cut into the
+//!   start of every leg that isn't connected (both while the points move). The marker is blue
+//!   while the switch moves, red while it is blocked and orange once a train has damaged it. Its
+//!   menu throws the switch to normal or reverse, blocks it in place and clears the damage;
+//!   refused throws come back as a warning notice.
+//! - Incidents (trailed switches, derailments, buffer stop collisions) are reported in the warning
+//!   notice. A failed train shows in its describer and is removed from the menu of its blocks.
 //! - Only manual (route-protecting) signals are drawn, as a triangle that is green when open
 //!   and subdued red when closed (driven by `SignalAspectChanged`) — closed signals stay
 //!   visible so they can be clicked to set a route. No speed plates.
+//! - A signal passed at danger (`SignalPassedAtDanger`) is reported in the warning notice and its
+//!   triangle turns magenta until the SPAD is acknowledged from the signal's menu. The tripped
+//!   train shows "SPAD" in its describer until then.
 //! - The train describer is a number label anchored near the head block's leading end; it
-//!   jumps from block to block on `TrainMove` as the head advances (it never slides).
+//!   jumps from block to block on `TrainMove` as the head advances (it never slides). While the
+//!   train stands at a platform it also shows the remaining dwell time or "ready to depart".
+//! - After a saved game is loaded (`GameLoaded`), the colours are rebuilt from the restored
+//!   simulation state and the describers come back with the re-announced occupancy.
+//! - Refused switch throws are explained in a warning notice at the bottom that hides after a few
+//!   seconds.
+//! - When the level file changes (`LevelReloaded`), the schematic is rebuilt in place; a level
+//!   that fails to load is reported in a banner and the previous schematic stays.
 
 use crate::assets::{AssetHandles, FontHandles, LoadingState};
-use crate::common::{BlockId, Direction, RouteId, SignalId, SignalType, TrainId};
+use crate::common::{
+    BlockId, CommandSource, Direction, RouteId, SignalId, SignalType, StationId, SwitchId, SwitchPosition, TrainId,
+};
 use crate::dropdown_menu::DropDownMenu;
-use crate::level::Level;
-use crate::simulation::block::{BlockMap, SignalAspectChanged, TrackState, TrackUpdate};
-use crate::simulation::signal::SignalAspect;
+use crate::level::{Level, LevelReloadFailed, LevelReloaded, watch_level};
+use crate::reload::reload_simulation;
+use crate::rolling_stock::RollingStock;
+use crate::save::GameLoaded;
+use crate::simulation::ars::{ArsToggle, AutoRouteSetting};
+use crate::simulation::block::{BlockMap, SignalAspectChanged, TemporarySpeedRestriction, TrackState, TrackUpdate};
+use crate::simulation::incident::{ClearanceRequest, Incident};
+use crate::simulation::signal::{SignalAspect, SpeedLimit};
+use crate::simulation::spad::{SignalPassedAtDanger, SpadAcknowledgement, SpadRegister};
 use crate::simulation::spawner::{SpawnRequest, SpawnTrainType};
-use crate::simulation::station::{RouteActivationRequest, RoutePending};
-use crate::simulation::train::{Train, TrainDespawnRequest};
+use crate::simulation::station::{
+    RouteActivationRequest, RouteCancellationRequest, RoutePending, RouteSectionReleased, StationMap, SwitchBlocking,
+    SwitchState, SwitchStateChanged, SwitchThrowRejected, SwitchUpdate,
+};
+use crate::simulation::train::{PlatformStop, Train, TrainDespawnRequest};
 use bevy::ecs::system::{SystemParam, SystemParamItem};
 use bevy::input::keyboard::Key;
 use bevy::input::mouse::{AccumulatedMouseMotion, AccumulatedMouseScroll, MouseScrollUnit};
 use bevy::prelude::*;
 use bevy::text::TextLayoutInfo;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ops::DerefMut;
 
 /// Pixel-space (level) → world-space scale factor.
@@ -41,6 +70,11 @@ const SCALE: f32 = 4.0;
 const TRACK_THICKNESS: f32 = 3.0;
 const SIGNAL_SIZE: f32 = 9.0;
 const SPAWNER_SIZE: f32 = 9.0;
+/// Radius of the switch marker
+const SWITCH_SIZE: f32 = 4.5;
+/// Gap cut into a disconnected switch leg, in screen pixels from the junction
+const SWITCH_GAP_OFFSET: f32 = 6.0;
+const SWITCH_GAP_LENGTH: f32 = 8.0;
 /// Describer placement: inset back from the leading block end (along the track). Expressed in
 /// screen units — scaled by the camera zoom each frame so the on-screen gap is constant. The
 /// plate sits centred on the track line (no perpendicular offset).
@@ -48,21 +82,33 @@ const DESCRIBER_INSET: f32 = 28.0;
 /// Padding (in text-logical units, i.e. "pixels") added on every side around the describer
 /// text to size its background plate.
 const DESCRIBER_PADDING: f32 = 2.0;
+/// Temporary speed restrictions the dispatcher can impose on a block from its context menu.
+const TSR_SPEEDS_KMH: [f64; 3] = [15.0, 25.0, 40.0];
 
 const TRACK_Z: f32 = 0.0;
+const SWITCH_GAP_Z: f32 = 1.0;
+const SWITCH_Z: f32 = 1.5;
 const SIGNAL_Z: f32 = 2.0;
 const SPAWNER_Z: f32 = 2.0;
 const DESCRIBER_Z: f32 = 5.0;
+const WARNING_NOTICE_SECS: f32 = 4.0;
 
 const BG_COLOR: Color = Color::srgb(0.06, 0.07, 0.09);
 const TRACK_IDLE: Color = Color::srgb(0.55, 0.57, 0.60);
 const TRACK_OCCUPIED: Color = Color::srgb(0.95, 0.82, 0.15);
 const TRACK_PENDING: Color = Color::srgb(0.15, 0.80, 0.25);
+const TRACK_SWITCH_MOVING: Color = Color::srgb(0.25, 0.60, 0.95);
+const SWITCH_COLOR: Color = Color::srgb(0.80, 0.82, 0.86);
+const SWITCH_BLOCKED: Color = Color::srgb(0.90, 0.30, 0.20);
+const SWITCH_FAILED: Color = Color::srgb(0.95, 0.55, 0.10);
 const SPAWNER_COLOR: Color = Color::srgb(0.85, 0.85, 0.88);
 const SIGNAL_GREEN: Color = Color::srgb(0.10, 0.85, 0.22);
 const SIGNAL_CLOSED: Color = Color::srgb(0.60, 0.16, 0.16);
+const SIGNAL_SPAD: Color = Color::srgb(0.90, 0.20, 0.85);
 const DESCRIBER_TEXT: Color = Color::srgb(0.95, 0.96, 1.0);
 const DESCRIBER_BG: Color = Color::srgb(0.30, 0.31, 0.33);
+const BANNER_TEXT: Color = Color::srgb(1.0, 0.85, 0.85);
+const BANNER_BG: Color = Color::srgb(0.45, 0.08, 0.08);
 
 // ----------------------------------------------------------------------------------
 // Geometry
@@ -74,44 +120,62 @@ pub struct TrackGeometry {
     polylines: HashMap<BlockId, Vec<Vec2>>,
 }
 
+/// Maps a point in level pixel space to world space
+pub fn level_to_world(point: Vec2) -> Vec2 {
+    // flip Y: level pixel space is y-down, world is y-up
+    Vec2::new(point.x * SCALE, -point.y * SCALE)
+}
+
+/// Maps a point in world space to level pixel space
+pub fn world_to_level(point: Vec2) -> Vec2 {
+    Vec2::new(point.x / SCALE, -point.y / SCALE)
+}
+
 impl TrackGeometry {
     fn from_level(level: &Level) -> Self {
+        let polylines = level
+            .geometry
+            .iter()
+            .map(|bg| (bg.id, bg.points.iter().copied().map(level_to_world).collect()))
+            .collect();
+        Self { polylines }
+    }
+
+    /// Middle of the bounding box of all polylines
+    fn center(&self) -> Vec2 {
         let mut min = Vec2::splat(f32::INFINITY);
         let mut max = Vec2::splat(f32::NEG_INFINITY);
-        for bg in &level.geometry {
-            for p in bg.points.iter().copied() {
-                min = min.min(p);
-                max = max.max(p);
-            }
+        for p in self.polylines.values().flatten().copied() {
+            min = min.min(p);
+            max = max.max(p);
         }
-        let center = if min.x.is_finite() {
+        if min.x.is_finite() {
             (min + max) / 2.0
         } else {
             Vec2::ZERO
-        };
-
-        let polylines = level
-            .geometry
-            .iter()
-            .map(|bg| {
-                let pts = bg
-                    .points
-                    .iter()
-                    // flip Y: level pixel space is y-down, world is y-up
-                    .map(|p| Vec2::new((p.x - center.x) * SCALE, (center.y - p.y) * SCALE))
-                    .collect();
-                (bg.id, pts)
-            })
-            .collect();
+        }
+    }
 
-        Self { polylines }
+    pub fn polyline(&self, id: BlockId) -> Option<&[Vec2]> {
+        self.polylines.get(&id).map(Vec::as_slice)
     }
 
-    fn endpoints(&self, id: BlockId) -> Option<(Vec2, Vec2)> {
+    pub fn endpoints(&self, id: BlockId) -> Option<(Vec2, Vec2)> {
         let pts = self.polylines.get(&id)?;
         Some((*pts.first()?, *pts.last()?))
     }
 
+    /// The block's end in the direction and the unit vector from it into the block, along the
+    /// polyline's last segment at that end.
+    fn end_inward(&self, id: BlockId, direction: Direction) -> Option<(Vec2, Vec2)> {
+        let pts = self.polylines.get(&id)?;
+        let (end, next) = match direction {
+            Direction::Even => (*pts.last()?, *pts.iter().rev().nth(1)?),
+            Direction::Odd => (*pts.first()?, *pts.get(1)?),
+        };
+        Some((end, (next - end).normalize_or_zero()))
+    }
+
     /// Unit vector along the even (forward) direction of the block.
     fn forward(&self, id: BlockId) -> Option<Vec2> {
         let (a, b) = self.endpoints(id)?;
@@ -147,6 +211,16 @@ pub struct TrackSeg(BlockId);
 #[derive(Component)]
 pub struct SignalGlyph(SignalId);
 
+#[derive(Component)]
+pub struct SwitchGlyph(SwitchId);
+
+/// Gap cut into the start of a switch leg, visible while the leg isn't connected
+#[derive(Component)]
+struct SwitchGap {
+    switch_id: SwitchId,
+    position: SwitchPosition,
+}
+
 #[derive(Component)]
 struct SpawnerMarker(BlockId);
 
@@ -170,6 +244,18 @@ struct DescriberBackground;
 #[derive(Component)]
 struct PanelTooltip;
 
+/// Shows why the changed level file could not be loaded
+#[derive(Component)]
+struct ReloadErrorBanner;
+
+/// Shows the last interlocking warning, e.g. a refused switch throw, for a few seconds
+#[derive(Component)]
+struct WarningNotice;
+
+/// Real time left until the [`WarningNotice`] is hidden, so it also goes away while the game is paused
+#[derive(Resource, Default)]
+struct WarningNoticeTimer(Timer);
+
 /// Live describer label entities keyed by train.
 #[derive(Resource, Default)]
 struct Describers(HashMap<TrainId, Entity>);
@@ -179,13 +265,37 @@ struct Describers(HashMap<TrainId, Entity>);
 #[derive(Resource, Default)]
 struct BlockMaterials(HashMap<BlockId, Handle<ColorMaterial>>);
 
-/// The two shared signal-glyph materials, one per aspect. All glyphs reference one of these
-/// (no per-entity materials), so they batch by aspect; recolouring a glyph swaps its
+/// The shared signal-glyph materials, one per aspect and one marking a SPAD. All glyphs reference
+/// one of these (no per-entity materials), so they batch by aspect; recolouring a glyph swaps its
 /// `MeshMaterial2d` handle rather than mutating a per-entity material.
 #[derive(Resource)]
 struct SignalMaterials {
     closed: Handle<ColorMaterial>,
     green: Handle<ColorMaterial>,
+    spad: Handle<ColorMaterial>,
+}
+
+impl SignalMaterials {
+    /// SPAD (magenta) > closed (red) > open (green)
+    fn get(&self, closed: bool, spad: bool) -> &Handle<ColorMaterial> {
+        if spad {
+            &self.spad
+        } else if closed {
+            &self.closed
+        } else {
+            &self.green
+        }
+    }
+}
+
+/// Shared switch materials: the marker colour for each switch state and the gap colour
+#[derive(Resource)]
+struct SwitchMaterials {
+    normal: Handle<ColorMaterial>,
+    moving: Handle<ColorMaterial>,
+    blocked: Handle<ColorMaterial>,
+    failed: Handle<ColorMaterial>,
+    gap: Handle<ColorMaterial>,
 }
 
 /// Panel-side display state per block, updated incrementally by messages (never polled).
@@ -196,13 +306,17 @@ struct BlockVisState(HashMap<BlockId, BlockVis>);
 struct BlockVis {
     occupied: bool,
     pending: bool,
+    /// A switch joining the block is moving, its legs aren't connected
+    switch_moving: bool,
 }
 
 impl BlockVis {
-    /// occupied (yellow) > pending route (green) > free (gray)
+    /// occupied (yellow) > moving switch (blue) > pending route (green) > free (gray)
     fn color(self) -> Color {
         if self.occupied {
             TRACK_OCCUPIED
+        } else if self.switch_moving {
+            TRACK_SWITCH_MOVING
         } else if self.pending {
             TRACK_PENDING
         } else {
@@ -211,6 +325,67 @@ impl BlockVis {
     }
 }
 
+/// Panel-side display state per switch, updated by messages like [`BlockVisState`]
+#[derive(Resource, Default)]
+struct SwitchVisState(HashMap<SwitchId, SwitchVis>);
+
+#[derive(Clone, Copy)]
+struct SwitchVis {
+    state: SwitchState,
+    blocked: bool,
+    failed: bool,
+}
+
+impl Default for SwitchVis {
+    /// Switches are drawn normal until the simulation reports them
+    fn default() -> Self {
+        Self {
+            state: SwitchState::Detected(SwitchPosition::Straight),
+            blocked: false,
+            failed: false,
+        }
+    }
+}
+
+/// Switch markers and leg gaps, repainted from a [`SwitchVis`]
+#[derive(SystemParam)]
+struct SwitchGlyphs<'w, 's> {
+    markers: Query<'w, 's, (Entity, &'static SwitchGlyph)>,
+    gaps: Query<'w, 's, (&'static SwitchGap, &'static mut Visibility)>,
+    switch_materials: Res<'w, SwitchMaterials>,
+    commands: Commands<'w, 's>,
+}
+
+impl SwitchGlyphs<'_, '_> {
+    /// damaged (orange) > blocked (red) > moving (blue) > normal marker; only the connected leg is
+    /// drawn without a gap
+    fn paint(&mut self, switch_id: SwitchId, vis: SwitchVis) {
+        let material = if vis.failed {
+            &self.switch_materials.failed
+        } else if vis.blocked {
+            &self.switch_materials.blocked
+        } else if matches!(vis.state, SwitchState::Moving(_)) {
+            &self.switch_materials.moving
+        } else {
+            &self.switch_materials.normal
+        };
+        for (entity, marker) in &self.markers {
+            if marker.0 == switch_id {
+                self.commands.entity(entity).insert(MeshMaterial2d(material.clone()));
+            }
+        }
+        for (gap, mut visibility) in &mut self.gaps {
+            if gap.switch_id == switch_id {
+                *visibility = if vis.state == SwitchState::Detected(gap.position) {
+                    Visibility::Hidden
+                } else {
+                    Visibility::Visible
+                };
+            }
+        }
+    }
+}
+
 fn paint_block(
     block_id: BlockId,
     vis: BlockVis,
@@ -237,7 +412,7 @@ const MAX_SCALE: f32 = 8.0;
 /// projection's `scale` (world units per pixel): on-screen size is `world / scale`, so we
 /// hold size constant by setting world size to `base * scale`.
 #[derive(Component, Clone, Copy)]
-enum ScreenScale {
+pub enum ScreenScale {
     /// Uniform glyph/label: scaled by the zoom factor on every axis.
     Uniform,
     /// A line whose length follows the world (zooms) but whose thickness stays
@@ -314,9 +489,16 @@ pub struct SchematicPlugin;
 
 impl Plugin for SchematicPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, spawn_camera)
-            .add_systems(OnExit(LoadingState::Loading), setup_schematic)
-            .add_systems(Update, apply_screen_scale);
+        app.add_systems(Startup, (spawn_camera, spawn_reload_error_banner))
+            .add_systems(OnExit(LoadingState::Loading), (setup_schematic, center_camera).chain())
+            .add_systems(
+                PreUpdate,
+                (clear_schematic, setup_schematic)
+                    .chain()
+                    .after(watch_level)
+                    .run_if(on_message::<LevelReloaded>),
+            )
+            .add_systems(Update, (apply_screen_scale, show_reload_errors));
     }
 }
 
@@ -324,6 +506,67 @@ fn spawn_camera(mut commands: Commands) {
     commands.spawn(Camera2d);
 }
 
+/// Looks at the middle of the schematic. Only done once, so that a reload doesn't move the view.
+fn center_camera(geometry: Res<TrackGeometry>, camera: Single<&mut Transform, With<Camera2d>>) {
+    let center = geometry.center();
+    let mut transform = camera.into_inner();
+    transform.translation.x = center.x;
+    transform.translation.y = center.y;
+}
+
+fn spawn_reload_error_banner(mut commands: Commands) {
+    commands.spawn((
+        ReloadErrorBanner,
+        Node {
+            position_type: PositionType::Absolute,
+            top: px(8),
+            left: px(8),
+            max_width: percent(60),
+            padding: UiRect::all(px(6)),
+            ..default()
+        },
+        Text::default(),
+        TextFont::from_font_size(13.0),
+        TextColor(BANNER_TEXT),
+        BackgroundColor(BANNER_BG),
+        GlobalZIndex(98),
+        Pickable::IGNORE,
+        Visibility::Hidden,
+    ));
+}
+
+/// The banner shows the last reload error until the level loads again
+fn show_reload_errors(
+    mut reloads: MessageReader<LevelReloaded>,
+    mut failures: MessageReader<LevelReloadFailed>,
+    banner: Single<(&mut Text, &mut Visibility), With<ReloadErrorBanner>>,
+) {
+    let (mut text, mut visibility) = banner.into_inner();
+    if reloads.read().count() > 0 {
+        *visibility = Visibility::Hidden;
+    }
+    if let Some(failure) = failures.read().last() {
+        text.0 = format!(
+            "The level could not be reloaded, the previous version stays in use.\n{}",
+            failure.error
+        );
+        *visibility = Visibility::Visible;
+    }
+}
+
+/// Despawns the static schematic before [`setup_schematic`] builds it again from the reloaded level
+fn clear_schematic(
+    tracks: Query<Entity, With<TrackSeg>>,
+    signals: Query<Entity, With<SignalGlyph>>,
+    switches: Query<Entity, With<SwitchGlyph>>,
+    gaps: Query<Entity, With<SwitchGap>>,
+    mut commands: Commands,
+) {
+    for entity in tracks.iter().chain(&signals).chain(&switches).chain(&gaps) {
+        commands.entity(entity).despawn();
+    }
+}
+
 pub fn setup_schematic(
     handles: Res<AssetHandles>,
     levels: Res<Assets<Level>>,
@@ -345,6 +588,22 @@ pub fn setup_schematic(
     let signal_materials = SignalMaterials {
         closed: materials.add(ColorMaterial::from_color(SIGNAL_CLOSED)),
         green: materials.add(ColorMaterial::from_color(SIGNAL_GREEN)),
+        spad: materials.add(ColorMaterial::from_color(SIGNAL_SPAD)),
+    };
+    let marker = meshes.add(Circle::new(SWITCH_SIZE));
+    let gap = meshes.add(
+        Mesh::from(Rectangle::new(SWITCH_GAP_LENGTH, TRACK_THICKNESS + 2.0)).translated_by(Vec3::new(
+            SWITCH_GAP_OFFSET + SWITCH_GAP_LENGTH / 2.0,
+            0.0,
+            0.0,
+        )),
+    );
+    let switch_materials = SwitchMaterials {
+        normal: materials.add(ColorMaterial::from_color(SWITCH_COLOR)),
+        moving: materials.add(ColorMaterial::from_color(TRACK_SWITCH_MOVING)),
+        blocked: materials.add(ColorMaterial::from_color(SWITCH_BLOCKED)),
+        failed: materials.add(ColorMaterial::from_color(SWITCH_FAILED)),
+        gap: materials.add(ColorMaterial::from_color(BG_COLOR)),
     };
 
     // --- track segments (all segments of a block share one material for cheap recolour) ---
@@ -423,9 +682,53 @@ pub fn setup_schematic(
         ));
     }
 
+    // --- switches: a marker at the junction of the base and leg polylines, and a gap cut into
+    // the start of each leg, hidden on the connected one. The gap's mesh starts off its origin, so
+    // it keeps its on-screen distance from the junction while zooming. Switches start normal. ---
+    for s in &level.switches {
+        let Some((junction, _)) = geometry.end_inward(s.base, s.direction) else {
+            continue;
+        };
+        for (leg, position) in [(s.straight, SwitchPosition::Straight), (s.side, SwitchPosition::Side)] {
+            let Some((_, inward)) = geometry.end_inward(leg, s.direction.reverse()) else {
+                continue;
+            };
+            let visibility = if position == SwitchPosition::Straight {
+                Visibility::Hidden
+            } else {
+                Visibility::Visible
+            };
+            commands.spawn((
+                SwitchGap {
+                    switch_id: s.id,
+                    position,
+                },
+                Mesh2d(gap.clone()),
+                MeshMaterial2d(switch_materials.gap.clone()),
+                Transform {
+                    translation: junction.extend(SWITCH_GAP_Z),
+                    rotation: Quat::from_rotation_z(inward.y.atan2(inward.x)),
+                    ..default()
+                },
+                ScreenScale::Uniform,
+                visibility,
+                Pickable::IGNORE,
+            ));
+        }
+        commands.spawn((
+            SwitchGlyph(s.id),
+            Mesh2d(marker.clone()),
+            MeshMaterial2d(switch_materials.normal.clone()),
+            Transform::from_translation(junction.extend(SWITCH_Z)),
+            ScreenScale::Uniform,
+            Pickable::default(),
+        ));
+    }
+
     commands.insert_resource(geometry);
     commands.insert_resource(BlockMaterials(block_materials));
     commands.insert_resource(signal_materials);
+    commands.insert_resource(switch_materials);
 }
 
 // ----------------------------------------------------------------------------------
@@ -439,23 +742,53 @@ impl Plugin for PanelPlugin {
         app.add_plugins((SchematicPlugin, CameraControlPlugin))
             .init_resource::<Describers>()
             .init_resource::<BlockVisState>()
+            .init_resource::<SwitchVisState>()
+            .init_resource::<WarningNoticeTimer>()
             .add_systems(Startup, startup)
             .add_systems(
                 OnExit(LoadingState::Loading),
                 attach_panel_interactions.after(setup_schematic),
             )
             .add_systems(OnEnter(LoadingState::Instantiated), setup_spawners)
+            .add_systems(
+                PreUpdate,
+                (
+                    attach_panel_interactions,
+                    (despawn_spawner_markers, setup_spawners).chain(),
+                )
+                    .after(setup_schematic)
+                    .after(reload_simulation)
+                    .run_if(on_message::<LevelReloaded>)
+                    .run_if(in_state(LoadingState::Instantiated)),
+            )
             .add_systems(
                 Update,
                 (
-                    apply_block_updates,
-                    apply_route_pending,
-                    apply_signal_aspects,
-                    apply_train_describers,
-                    position_describers,
-                    size_describer_backgrounds,
-                    despawn_describers,
+                    (
+                        repaint_after_load,
+                        reset_signals_after_load,
+                        repaint_switches_after_load,
+                        drop_describers_after_load,
+                    ),
+                    (
+                        apply_block_updates,
+                        // a release may be followed by a conflicting route set over the same blocks
+                        (apply_route_section_releases, apply_route_pending).chain(),
+                        (
+                            apply_signal_aspects,
+                            apply_spad_marks.run_if(resource_changed::<SpadRegister>),
+                        )
+                            .chain(),
+                        apply_switch_states,
+                        apply_switch_glyphs,
+                        (show_switch_rejections, show_incidents, show_spads, hide_warning_notice).chain(),
+                        (apply_train_describers, apply_describer_status).chain(),
+                        position_describers,
+                        size_describer_backgrounds,
+                        despawn_describers,
+                    ),
                 )
+                    .chain()
                     .run_if(in_state(LoadingState::Instantiated)),
             );
     }
@@ -464,6 +797,12 @@ impl Plugin for PanelPlugin {
 fn startup(mut commands: Commands) {
     commands.add_observer(on_route_menu_action);
     commands.add_observer(on_spawner_menu_action);
+    commands.add_observer(on_block_menu_action);
+    commands.add_observer(on_switch_menu_action);
+    commands.add_observer(PanelRouteMenu::on_menu_click);
+    commands.add_observer(PanelSpawnerMenu::on_menu_click);
+    commands.add_observer(PanelBlockMenu::on_menu_click);
+    commands.add_observer(PanelSwitchMenu::on_menu_click);
 
     commands
         .spawn((
@@ -483,19 +822,50 @@ fn startup(mut commands: Commands) {
         .with_children(|p| {
             p.spawn((Text::default(), TextFont::from_font_size(11.0), Pickable::IGNORE));
         });
+
+    commands.spawn((
+        WarningNotice,
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: px(8),
+            left: px(8),
+            max_width: percent(60),
+            padding: UiRect::all(px(6)),
+            ..default()
+        },
+        Text::default(),
+        TextFont::from_font_size(13.0),
+        TextColor(BANNER_TEXT),
+        BackgroundColor(BANNER_BG),
+        GlobalZIndex(98),
+        Pickable::IGNORE,
+        Visibility::Hidden,
+    ));
 }
 
 /// Wire up picking on the schematic entities spawned by [`setup_schematic`]: the route
-/// menu on signal glyphs, and hover tooltips on both track segments and signal glyphs.
+/// menu on signal glyphs, the speed restriction menu on track segments, the switch menu on
+/// switch markers, and hover tooltips on all of them. Runs again after a level reload has
+/// replaced them.
 fn attach_panel_interactions(
     tracks: Query<Entity, With<TrackSeg>>,
     signals: Query<Entity, With<SignalGlyph>>,
+    switches: Query<Entity, With<SwitchGlyph>>,
     mut commands: Commands,
 ) {
+    let track_entities: Vec<Entity> = tracks.iter().collect();
     let signal_entities: Vec<Entity> = signals.iter().collect();
-    let info_entities: Vec<Entity> = tracks.iter().chain(signal_entities.iter().copied()).collect();
-
-    PanelRouteMenu::register(&mut commands, signal_entities);
+    let switch_entities: Vec<Entity> = switches.iter().collect();
+    let info_entities: Vec<Entity> = track_entities
+        .iter()
+        .chain(signal_entities.iter())
+        .chain(switch_entities.iter())
+        .copied()
+        .collect();
+
+    PanelRouteMenu::attach(&mut commands, signal_entities);
+    PanelBlockMenu::attach(&mut commands, track_entities);
+    PanelSwitchMenu::attach(&mut commands, switch_entities);
     commands.spawn(Observer::new(on_info_over).with_entities(info_entities.iter().copied()));
     commands.spawn(Observer::new(on_info_out).with_entities(info_entities));
 }
@@ -547,7 +917,13 @@ fn setup_spawners(
         spawner_entities.push(entity);
     }
 
-    PanelSpawnerMenu::register(&mut commands, spawner_entities);
+    PanelSpawnerMenu::attach(&mut commands, spawner_entities);
+}
+
+fn despawn_spawner_markers(query: Query<Entity, With<SpawnerMarker>>, mut commands: Commands) {
+    for entity in &query {
+        commands.entity(entity).despawn();
+    }
 }
 
 // ----------------------------------------------------------------------------------
@@ -595,6 +971,23 @@ fn apply_route_pending(
     }
 }
 
+/// Sectional release: a section the train has cleared drops its green pending colour, so only
+/// the part of the route still locked ahead of the train stays green.
+fn apply_route_section_releases(
+    mut updates: MessageReader<RouteSectionReleased>,
+    mut state: ResMut<BlockVisState>,
+    block_materials: Res<BlockMaterials>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    for update in updates.read() {
+        for &block_id in &update.blocks {
+            let vis = state.0.entry(block_id).or_default();
+            vis.pending = false;
+            paint_block(block_id, *vis, &block_materials, &mut materials);
+        }
+    }
+}
+
 /// Manual signal glyphs are green when open and subdued red when closed (Forbidding), so a
 /// closed signal stays visible and clickable. Glyphs exist only for manual signals; changes
 /// for automatic signals match no glyph and are ignored.
@@ -602,14 +995,12 @@ fn apply_signal_aspects(
     mut changes: MessageReader<SignalAspectChanged>,
     query: Query<(Entity, &SignalGlyph)>,
     signal_materials: Res<SignalMaterials>,
+    register: Res<SpadRegister>,
     mut commands: Commands,
 ) {
     for change in changes.read() {
-        let material = if change.aspect == SignalAspect::Forbidding {
-            &signal_materials.closed
-        } else {
-            &signal_materials.green
-        };
+        let closed = change.aspect == SignalAspect::Forbidding;
+        let material = signal_materials.get(closed, register.is_marked(change.signal_id));
         for (entity, glyph) in &query {
             if glyph.0 == change.signal_id {
                 commands.entity(entity).insert(MeshMaterial2d(material.clone()));
@@ -618,6 +1009,261 @@ fn apply_signal_aspects(
     }
 }
 
+/// A SPAD marks its signal until the dispatcher acknowledges it, then the glyph shows the aspect again
+fn apply_spad_marks(
+    register: Res<SpadRegister>,
+    block_map: Res<BlockMap>,
+    query: Query<(Entity, &SignalGlyph)>,
+    signal_materials: Res<SignalMaterials>,
+    mut commands: Commands,
+) {
+    paint_signal_glyphs(&register, &block_map, &query, &signal_materials, &mut commands);
+}
+
+/// Paints every signal glyph with its current aspect, or the SPAD colour while the signal has an
+/// unacknowledged SPAD
+fn paint_signal_glyphs(
+    register: &SpadRegister,
+    block_map: &BlockMap,
+    query: &Query<(Entity, &SignalGlyph)>,
+    signal_materials: &SignalMaterials,
+    commands: &mut Commands,
+) {
+    for (entity, glyph) in query {
+        let closed = block_map
+            .signal(glyph.0)
+            .is_none_or(|signal| signal.speed_ctrl.aspect == SignalAspect::Forbidding);
+        let material = signal_materials.get(closed, register.is_marked(glyph.0));
+        commands.entity(entity).insert(MeshMaterial2d(material.clone()));
+    }
+}
+
+/// A refused switch throw is explained in the warning notice
+fn show_switch_rejections(
+    mut rejections: MessageReader<SwitchThrowRejected>,
+    mut timer: ResMut<WarningNoticeTimer>,
+    notice: Single<(&mut Text, &mut Visibility), With<WarningNotice>>,
+) {
+    let Some(rejection) = rejections.read().last() else {
+        return;
+    };
+    let (mut text, mut visibility) = notice.into_inner();
+    text.0 = format!("Switch {} {}", rejection.switch_id, rejection.reason);
+    *visibility = Visibility::Visible;
+    timer.0 = Timer::from_seconds(WARNING_NOTICE_SECS, TimerMode::Once);
+}
+
+/// Incidents are reported in the warning notice
+fn show_incidents(
+    mut incidents: MessageReader<Incident>,
+    trains: Query<&Train>,
+    mut timer: ResMut<WarningNoticeTimer>,
+    notice: Single<(&mut Text, &mut Visibility), With<WarningNotice>>,
+) {
+    let Some(incident) = incidents.read().last() else {
+        return;
+    };
+    let number = trains
+        .iter()
+        .find(|t| t.id == incident.train_id())
+        .map_or("?", |t| t.number.as_str());
+    let (mut text, mut visibility) = notice.into_inner();
+    text.0 = match *incident {
+        Incident::SwitchDamaged { switch_id, .. } => {
+            format!("Train {} trailed switch {} and damaged it", number, switch_id)
+        }
+        Incident::Derailment { switch_id, .. } => {
+            format!("Train {} derailed on switch {} while it was moving", number, switch_id)
+        }
+        Incident::BufferStopCollision { block_id, .. } => {
+            format!("Train {} hit the buffer stop at the end of block {}", number, block_id)
+        }
+    };
+    *visibility = Visibility::Visible;
+    timer.0 = Timer::from_seconds(WARNING_NOTICE_SECS, TimerMode::Once);
+}
+
+/// SPADs are reported in the warning notice
+fn show_spads(
+    mut spads: MessageReader<SignalPassedAtDanger>,
+    block_map: Res<BlockMap>,
+    trains: Query<&Train>,
+    mut timer: ResMut<WarningNoticeTimer>,
+    notice: Single<(&mut Text, &mut Visibility), With<WarningNotice>>,
+) {
+    let Some(spad) = spads.read().last() else {
+        return;
+    };
+    let number = trains
+        .iter()
+        .find(|t| t.id == spad.train_id)
+        .map_or("?", |t| t.number.as_str());
+    let signal = block_map
+        .signal(spad.signal_id)
+        .map_or_else(|| spad.signal_id.to_string(), |s| s.name.clone());
+    let (mut text, mut visibility) = notice.into_inner();
+    text.0 = format!(
+        "Train {} passed signal {} at danger, {:.0} km/h over the allowed speed",
+        number, signal, spad.overspeed_kmh
+    );
+    *visibility = Visibility::Visible;
+    timer.0 = Timer::from_seconds(WARNING_NOTICE_SECS, TimerMode::Once);
+}
+
+fn hide_warning_notice(
+    time: Res<Time<Real>>,
+    mut timer: ResMut<WarningNoticeTimer>,
+    mut visibility: Single<&mut Visibility, With<WarningNotice>>,
+) {
+    if timer.0.tick(time.delta()).just_finished() {
+        **visibility = Visibility::Hidden;
+    }
+}
+
+/// Moving switches: the blocks they join are drawn in their own colour until the points are detected
+fn apply_switch_states(
+    mut changes: MessageReader<SwitchStateChanged>,
+    block_map: Res<BlockMap>,
+    mut state: ResMut<BlockVisState>,
+    block_materials: Res<BlockMaterials>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    let blocks: HashSet<BlockId> = changes
+        .read()
+        .filter_map(|change| block_map.switch(change.switch_id))
+        .flat_map(|switch| switch.blocks())
+        .collect();
+    for block_id in blocks {
+        let vis = state.0.entry(block_id).or_default();
+        // the block may join another switch that is still moving
+        vis.switch_moving = is_switch_moving_at(&block_map, block_id);
+        paint_block(block_id, *vis, &block_materials, &mut materials);
+    }
+}
+
+/// Switch markers and leg gaps follow the reported switch states, the dispatcher's blocking and
+/// the damage done by trains until it is cleared
+fn apply_switch_glyphs(
+    mut changes: MessageReader<SwitchStateChanged>,
+    mut blocking: MessageReader<SwitchBlocking>,
+    mut incidents: MessageReader<Incident>,
+    mut clearances: MessageReader<ClearanceRequest>,
+    mut state: ResMut<SwitchVisState>,
+    mut glyphs: SwitchGlyphs,
+) {
+    let mut changed = HashSet::new();
+    for change in changes.read() {
+        state.0.entry(change.switch_id).or_default().state = change.state;
+        changed.insert(change.switch_id);
+    }
+    for request in blocking.read() {
+        state.0.entry(request.switch_id).or_default().blocked = request.blocked;
+        changed.insert(request.switch_id);
+    }
+    for incident in incidents.read() {
+        if let Incident::SwitchDamaged { switch_id, .. } = *incident {
+            state.0.entry(switch_id).or_default().failed = true;
+            changed.insert(switch_id);
+        }
+    }
+    for request in clearances.read() {
+        if let ClearanceRequest::Switch(switch_id) = *request {
+            state.0.entry(switch_id).or_default().failed = false;
+            changed.insert(switch_id);
+        }
+    }
+    for switch_id in changed {
+        glyphs.paint(switch_id, state.0[&switch_id]);
+    }
+}
+
+fn is_switch_moving_at(block_map: &BlockMap, block_id: BlockId) -> bool {
+    block_map
+        .switches()
+        .any(|switch| switch.throw.is_some() && switch.blocks().contains(&block_id))
+}
+
+/// A loaded game replaces the whole state: every block is repainted, pending under the restored
+/// routes and moving under the restored switches. Occupancy follows from the track updates
+/// re-announced by the load.
+fn repaint_after_load(
+    mut loads: MessageReader<GameLoaded>,
+    station_map: Res<StationMap>,
+    block_map: Res<BlockMap>,
+    mut state: ResMut<BlockVisState>,
+    block_materials: Res<BlockMaterials>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    if loads.read().count() == 0 {
+        return;
+    }
+    state.0.clear();
+    for block_id in station_map.pending_blocks() {
+        state.0.entry(block_id).or_default().pending = true;
+    }
+    for switch in block_map.switches().filter(|switch| switch.throw.is_some()) {
+        for block_id in switch.blocks() {
+            state.0.entry(block_id).or_default().switch_moving = true;
+        }
+    }
+    for &block_id in block_materials.0.keys() {
+        let vis = state.0.get(&block_id).copied().unwrap_or_default();
+        paint_block(block_id, vis, &block_materials, &mut materials);
+    }
+}
+
+fn repaint_switches_after_load(
+    mut loads: MessageReader<GameLoaded>,
+    block_map: Res<BlockMap>,
+    mut state: ResMut<SwitchVisState>,
+    mut glyphs: SwitchGlyphs,
+) {
+    if loads.read().count() == 0 {
+        return;
+    }
+    state.0 = block_map
+        .switches()
+        .map(|switch| {
+            let vis = SwitchVis {
+                state: switch.state(),
+                blocked: switch.blocked,
+                failed: switch.failed,
+            };
+            (switch.id, vis)
+        })
+        .collect();
+    for (&switch_id, &vis) in &state.0 {
+        glyphs.paint(switch_id, vis);
+    }
+}
+
+fn reset_signals_after_load(
+    mut loads: MessageReader<GameLoaded>,
+    block_map: Res<BlockMap>,
+    register: Res<SpadRegister>,
+    query: Query<(Entity, &SignalGlyph)>,
+    signal_materials: Res<SignalMaterials>,
+    mut commands: Commands,
+) {
+    if loads.read().count() == 0 {
+        return;
+    }
+    paint_signal_glyphs(&register, &block_map, &query, &signal_materials, &mut commands);
+}
+
+fn drop_describers_after_load(
+    mut loads: MessageReader<GameLoaded>,
+    mut describers: ResMut<Describers>,
+    mut commands: Commands,
+) {
+    if loads.read().count() == 0 {
+        return;
+    }
+    for (_, entity) in describers.0.drain() {
+        commands.entity(entity).despawn();
+    }
+}
+
 /// Given a section update, return a block for a describer label. The block is always on the
 /// end of the section which is opposite from where the train had entered.
 /// For regular single block updates, returns the updated block id.
@@ -686,6 +1332,31 @@ fn apply_train_describers(
     }
 }
 
+/// Shows the platform stop state next to the train number: the remaining dwell time,
+/// or "ready to depart" once the train only waits for the exit signal. A failed train or one
+/// held after a SPAD shows that instead.
+fn apply_describer_status(
+    trains: Query<&Train>,
+    describers: Res<Describers>,
+    mut labels: Query<&mut Text2d, With<DescriberLabel>>,
+) {
+    for train in &trains {
+        let Some(mut text) = describers.0.get(&train.id).and_then(|&e| labels.get_mut(e).ok()) else {
+            continue;
+        };
+        let content = match train.platform_stop() {
+            _ if train.failed() => format!("{} · failed", train.number),
+            _ if train.tripped() => format!("{} · SPAD", train.number),
+            Some(PlatformStop::Dwelling(remaining_s)) => format!("{} · {:.0} s", train.number, remaining_s.ceil()),
+            Some(PlatformStop::ReadyToDepart) => format!("{} · ready to depart", train.number),
+            None => train.number.clone(),
+        };
+        if text.0 != content {
+            text.0 = content;
+        }
+    }
+}
+
 /// Places each describer at `leading + interior * DESCRIBER_INSET * zoom` so the gap between the
 /// label and the block end stays constant on screen as the camera zooms (the label itself is
 /// screen-scaled too). Runs every frame because the position depends on the camera scale.
@@ -736,18 +1407,26 @@ fn despawn_describers(
 // Hover tooltip
 // ----------------------------------------------------------------------------------
 
+/// Schematic entities that show a tooltip
+#[derive(SystemParam)]
+struct InfoTargets<'w, 's> {
+    tracks: Query<'w, 's, &'static TrackSeg>,
+    signals: Query<'w, 's, &'static SignalGlyph>,
+    switches: Query<'w, 's, &'static SwitchGlyph>,
+}
+
 fn on_info_over(
     event: On<Pointer<Over>>,
     block_map: Res<BlockMap>,
+    spads: Res<SpadRegister>,
     trains: Query<&Train>,
-    tracks: Query<&TrackSeg>,
-    signals: Query<&SignalGlyph>,
+    targets: InfoTargets,
     mut info: Single<(&Children, &mut Visibility, &mut Node), With<PanelTooltip>>,
     mut writer: TextUiWriter,
 ) {
     let target = event.entity;
-    let text = if let Ok(seg) = tracks.get(target) {
-        match block_map.block_trains(seg.0).and_then(|t| t.first()).copied() {
+    let text = if let Ok(seg) = targets.tracks.get(target) {
+        let state = match block_map.block_trains(seg.0).and_then(|t| t.first()).copied() {
             Some(first) => match trains.iter().find(|t| t.id == first) {
                 Some(train) => format!(
                     "Block {} — train {} ({:.0} km/h)",
@@ -758,12 +1437,41 @@ fn on_info_over(
                 None => format!("Block {} — occupied", seg.0),
             },
             None => format!("Block {} — free", seg.0),
+        };
+        match block_map.temporary_speed_restriction(seg.0) {
+            Some(speed_kmh) => format!("{}, restricted to {:.0} km/h", state, speed_kmh),
+            None => state,
         }
-    } else if let Ok(glyph) = signals.get(target) {
-        match block_map.signal(glyph.0) {
-            Some(signal) => format!("Signal {} ({})", signal.name, signal.id),
+    } else if let Ok(glyph) = targets.signals.get(target) {
+        let state = match block_map.signal(glyph.0) {
+            Some(signal) if signal.speed_ctrl.aspect == SignalAspect::Forbidding => {
+                format!("Signal {} ({}) — closed", signal.name, signal.id)
+            }
+            Some(signal) => format!(
+                "Signal {} ({}) — open, {}",
+                signal.name, signal.id, signal.speed_ctrl.passing_kmh
+            ),
             None => return,
-        }
+        };
+        let spad = if spads.is_marked(glyph.0) {
+            ", passed at danger"
+        } else {
+            ""
+        };
+        format!("{}{}", state, spad)
+    } else if let Ok(glyph) = targets.switches.get(target) {
+        let Some(switch) = block_map.switch(glyph.0) else {
+            return;
+        };
+        let state = match switch.state() {
+            SwitchState::Detected(SwitchPosition::Straight) => "normal",
+            SwitchState::Detected(SwitchPosition::Side) => "reverse",
+            SwitchState::Moving(SwitchPosition::Straight) => "moving to normal",
+            SwitchState::Moving(SwitchPosition::Side) => "moving to reverse",
+        };
+        let blocked = if switch.blocked { ", blocked" } else { "" };
+        let failed = if switch.failed { ", damaged" } else { "" };
+        format!("Switch {} — {}{}{}", switch.id, state, blocked, failed)
     } else {
         return;
     };
@@ -786,16 +1494,30 @@ fn on_info_out(_: On<Pointer<Out>>, mut vis: Single<&mut Visibility, With<PanelT
 #[derive(EntityEvent)]
 struct PanelRouteMenuEvent {
     entity: Entity,
-    route_id: RouteId,
+    action: PanelRouteMenu,
 }
 
 #[derive(Component, Clone, Copy)]
-struct PanelRouteMenu(RouteId);
+enum PanelRouteMenu {
+    Open(RouteId),
+    Cancel(RouteId),
+    /// Cancelled with a train approaching, informational until the lock expires
+    ApproachLocked(RouteId, u32),
+    /// Enable or disable automatic route setting at the signal
+    SignalArs(SignalId, bool),
+    /// Enable or disable automatic route setting at every signal of the station
+    StationArs(StationId, bool),
+    /// Acknowledge the SPADs at the signal, releasing the trains held by the train protection
+    AcknowledgeSpad(SignalId),
+}
 
 #[derive(SystemParam)]
 struct RouteMenuContext<'w, 's> {
     handles: Res<'w, AssetHandles>,
     levels: Res<'w, Assets<Level>>,
+    station_map: Option<Res<'w, StationMap>>,
+    ars: Option<Res<'w, AutoRouteSetting>>,
+    spads: Option<Res<'w, SpadRegister>>,
     glyphs: Query<'w, 's, &'static SignalGlyph>,
 }
 
@@ -804,14 +1526,22 @@ impl DropDownMenu for PanelRouteMenu {
     type Context = RouteMenuContext<'static, 'static>;
 
     fn create_event(&self, entity: Entity) -> Self::Event<'_> {
-        PanelRouteMenuEvent {
-            entity,
-            route_id: self.0,
-        }
+        PanelRouteMenuEvent { entity, action: *self }
     }
 
     fn get_label(&self) -> impl Into<String> {
-        format!("Open route {}", self.0)
+        match self {
+            PanelRouteMenu::Open(route_id) => format!("Open route {}", route_id),
+            PanelRouteMenu::Cancel(route_id) => format!("Cancel route {}", route_id),
+            PanelRouteMenu::ApproachLocked(route_id, remaining_s) => {
+                format!("Route {} approach locked ({} s)", route_id, remaining_s)
+            }
+            PanelRouteMenu::SignalArs(_, true) => "Enable ARS at this signal".to_string(),
+            PanelRouteMenu::SignalArs(_, false) => "Disable ARS at this signal".to_string(),
+            PanelRouteMenu::StationArs(_, true) => "Enable ARS for the station".to_string(),
+            PanelRouteMenu::StationArs(_, false) => "Disable ARS for the station".to_string(),
+            PanelRouteMenu::AcknowledgeSpad(_) => "Acknowledge SPAD".to_string(),
+        }
     }
 
     fn list_available_items(
@@ -827,9 +1557,30 @@ impl DropDownMenu for PanelRouteMenu {
         };
         for route in level.stations.iter().flat_map(|s| s.routes.iter()) {
             if route.signal == glyph.0 {
-                items.push(PanelRouteMenu(route.id));
+                let station_map = ctx.station_map.as_ref();
+                let active = station_map.is_some_and(|m| m.is_route_active(route.id));
+                let locked = station_map.and_then(|m| m.approach_lock_remaining(route.id));
+                items.push(match locked {
+                    Some(remaining_s) => PanelRouteMenu::ApproachLocked(route.id, remaining_s.ceil() as u32),
+                    None if active => PanelRouteMenu::Cancel(route.id),
+                    None => PanelRouteMenu::Open(route.id),
+                });
             }
         }
+        if let Some(ars) = ctx.ars.as_ref() {
+            items.push(PanelRouteMenu::SignalArs(glyph.0, !ars.is_enabled(glyph.0)));
+            if let Some(station) = level
+                .stations
+                .iter()
+                .find(|s| s.routes.iter().any(|r| r.signal == glyph.0))
+            {
+                let all_enabled = station.routes.iter().all(|r| ars.is_enabled(r.signal));
+                items.push(PanelRouteMenu::StationArs(station.id, !all_enabled));
+            }
+        }
+        if ctx.spads.as_ref().is_some_and(|spads| spads.is_marked(glyph.0)) {
+            items.push(PanelRouteMenu::AcknowledgeSpad(glyph.0));
+        }
         items
     }
 
@@ -838,10 +1589,48 @@ impl DropDownMenu for PanelRouteMenu {
     }
 }
 
-fn on_route_menu_action(event: On<PanelRouteMenuEvent>, mut requests: MessageWriter<RouteActivationRequest>) {
-    requests.write(RouteActivationRequest {
-        route_id: event.route_id,
-    });
+fn on_route_menu_action(
+    event: On<PanelRouteMenuEvent>,
+    handles: Res<AssetHandles>,
+    levels: Res<Assets<Level>>,
+    mut activations: MessageWriter<RouteActivationRequest>,
+    mut cancellations: MessageWriter<RouteCancellationRequest>,
+    mut ars_toggles: MessageWriter<ArsToggle>,
+    mut spad_acknowledgements: MessageWriter<SpadAcknowledgement>,
+) {
+    match event.action {
+        PanelRouteMenu::Open(route_id) => {
+            activations.write(RouteActivationRequest {
+                route_id,
+                source: CommandSource::Dispatcher,
+            });
+        }
+        PanelRouteMenu::Cancel(route_id) => {
+            cancellations.write(RouteCancellationRequest {
+                route_id,
+                source: CommandSource::Dispatcher,
+            });
+        }
+        PanelRouteMenu::ApproachLocked(..) => {}
+        PanelRouteMenu::SignalArs(signal_id, enabled) => {
+            ars_toggles.write(ArsToggle {
+                signals: vec![signal_id],
+                enabled,
+            });
+        }
+        PanelRouteMenu::StationArs(station_id, enabled) => {
+            let level = levels.get(&handles.level).expect("level had been loaded");
+            if let Some(station) = level.stations.iter().find(|s| s.id == station_id) {
+                let mut signals: Vec<SignalId> = station.routes.iter().map(|r| r.signal).collect();
+                signals.sort_unstable();
+                signals.dedup();
+                ars_toggles.write(ArsToggle { signals, enabled });
+            }
+        }
+        PanelRouteMenu::AcknowledgeSpad(signal_id) => {
+            spad_acknowledgements.write(SpadAcknowledgement { signal_id });
+        }
+    }
 }
 
 #[derive(EntityEvent)]
@@ -850,48 +1639,257 @@ struct PanelSpawnerMenuEvent {
     action: PanelSpawnerMenu,
 }
 
-#[derive(Component, Clone, Copy)]
-enum PanelSpawnerMenu {
-    Cargo,
-    Passenger,
-    Locomotive,
+#[derive(Component, Clone)]
+struct PanelSpawnerMenu {
+    train_type: SpawnTrainType,
+    name: String,
+}
+
+#[derive(SystemParam)]
+struct SpawnerMenuContext<'w> {
+    handles: Res<'w, AssetHandles>,
+    catalogs: Res<'w, Assets<RollingStock>>,
 }
 
 impl DropDownMenu for PanelSpawnerMenu {
     type Event<'a> = PanelSpawnerMenuEvent;
-    type Context = ();
+    type Context = SpawnerMenuContext<'static>;
 
     fn create_event(&self, entity: Entity) -> Self::Event<'_> {
-        PanelSpawnerMenuEvent { entity, action: *self }
+        PanelSpawnerMenuEvent {
+            entity,
+            action: self.clone(),
+        }
     }
 
     fn get_label(&self) -> impl Into<String> {
-        match self {
-            PanelSpawnerMenu::Cargo => "Spawn Cargo Train",
-            PanelSpawnerMenu::Passenger => "Spawn Passenger Train",
-            PanelSpawnerMenu::Locomotive => "Spawn Locomotive Only",
-        }
+        format!("Spawn {}", self.name)
     }
 
-    fn list_available_items(_: Entity, _: &mut SystemParamItem<Self::Context>) -> impl IntoIterator<Item = Self> {
-        [
-            PanelSpawnerMenu::Cargo,
-            PanelSpawnerMenu::Passenger,
-            PanelSpawnerMenu::Locomotive,
-        ]
+    fn list_available_items(_: Entity, ctx: &mut SystemParamItem<Self::Context>) -> impl IntoIterator<Item = Self> {
+        let mut items = Vec::new();
+        if let Some(catalog) = ctx.catalogs.get(&ctx.handles.rolling_stock) {
+            items.extend(catalog.consists.iter().map(|consist| PanelSpawnerMenu {
+                train_type: SpawnTrainType(consist.id.clone()),
+                name: consist.name.clone(),
+            }));
+        }
+        items
     }
 }
 
 fn on_spawner_menu_action(event: On<PanelSpawnerMenuEvent>, query: Query<&SpawnerMarker>, mut commands: Commands) {
     if let Ok(spawner) = query.get(event.entity) {
-        let train_type = match event.action {
-            PanelSpawnerMenu::Cargo => SpawnTrainType::Cargo,
-            PanelSpawnerMenu::Passenger => SpawnTrainType::Passenger,
-            PanelSpawnerMenu::Locomotive => SpawnTrainType::Locomotive,
-        };
         commands.trigger(SpawnRequest {
             block_id: spawner.0,
-            train_type,
+            train_type: event.action.train_type.clone(),
+            number: None,
+            destination: None,
+            priority: 0,
+            calling_at: Vec::new(),
+            exit_time_s: None,
+            source: CommandSource::Dispatcher,
+        });
+    }
+}
+
+#[derive(EntityEvent)]
+struct PanelBlockMenuEvent {
+    entity: Entity,
+    action: PanelBlockMenu,
+}
+
+#[derive(Component, Clone, Copy)]
+enum PanelBlockMenu {
+    Restrict(f64),
+    Lift,
+    /// Remove a failed train standing in the block
+    ClearTrain(TrainId),
+}
+
+#[derive(SystemParam)]
+struct BlockMenuContext<'w, 's> {
+    block_map: Option<Res<'w, BlockMap>>,
+    segments: Query<'w, 's, &'static TrackSeg>,
+    trains: Query<'w, 's, &'static Train>,
+}
+
+impl DropDownMenu for PanelBlockMenu {
+    type Event<'a> = PanelBlockMenuEvent;
+    type Context = BlockMenuContext<'static, 'static>;
+
+    fn create_event(&self, entity: Entity) -> Self::Event<'_> {
+        PanelBlockMenuEvent { entity, action: *self }
+    }
+
+    fn get_label(&self) -> impl Into<String> {
+        match self {
+            PanelBlockMenu::Restrict(speed_kmh) => format!("Restrict to {:.0} km/h", speed_kmh),
+            PanelBlockMenu::Lift => "Lift speed restriction".to_string(),
+            PanelBlockMenu::ClearTrain(_) => "Remove failed train".to_string(),
+        }
+    }
+
+    fn list_available_items(
+        target: Entity,
+        ctx: &mut SystemParamItem<Self::Context>,
+    ) -> impl IntoIterator<Item = Self> {
+        let mut items = Vec::new();
+        let (Ok(seg), Some(block_map)) = (ctx.segments.get(target), ctx.block_map.as_ref()) else {
+            return items;
+        };
+        let current = block_map.temporary_speed_restriction(seg.0);
+        items.extend(
+            TSR_SPEEDS_KMH
+                .into_iter()
+                .filter(|&speed_kmh| current != Some(speed_kmh))
+                .map(PanelBlockMenu::Restrict),
+        );
+        if current.is_some() {
+            items.push(PanelBlockMenu::Lift);
+        }
+        let occupants = block_map.block_trains(seg.0).cloned().unwrap_or_default();
+        items.extend(
+            ctx.trains
+                .iter()
+                .filter(|train| train.failed() && occupants.contains(&train.id))
+                .map(|train| PanelBlockMenu::ClearTrain(train.id)),
+        );
+        items
+    }
+}
+
+fn on_block_menu_action(
+    event: On<PanelBlockMenuEvent>,
+    query: Query<&TrackSeg>,
+    mut restrictions: MessageWriter<TemporarySpeedRestriction>,
+    mut clearances: MessageWriter<ClearanceRequest>,
+) {
+    if let Ok(seg) = query.get(event.entity) {
+        let speed_limit = match event.action {
+            PanelBlockMenu::Restrict(speed_kmh) => SpeedLimit::Restricted(speed_kmh),
+            PanelBlockMenu::Lift => SpeedLimit::Unrestricted,
+            PanelBlockMenu::ClearTrain(train_id) => {
+                clearances.write(ClearanceRequest::Train(train_id));
+                return;
+            }
+        };
+        restrictions.write(TemporarySpeedRestriction {
+            block_id: seg.0,
+            speed_limit,
+            source: CommandSource::Dispatcher,
+        });
+    }
+}
+
+#[derive(EntityEvent)]
+struct PanelSwitchMenuEvent {
+    entity: Entity,
+    action: PanelSwitchMenu,
+}
+
+#[derive(Component, Clone, Copy)]
+enum PanelSwitchMenu {
+    Normal,
+    Reverse,
+    Block,
+    Unblock,
+    Repair,
+}
+
+#[derive(SystemParam)]
+struct SwitchMenuContext<'w, 's> {
+    block_map: Option<Res<'w, BlockMap>>,
+    glyphs: Query<'w, 's, &'static SwitchGlyph>,
+}
+
+impl DropDownMenu for PanelSwitchMenu {
+    type Event<'a> = PanelSwitchMenuEvent;
+    type Context = SwitchMenuContext<'static, 'static>;
+
+    fn create_event(&self, entity: Entity) -> Self::Event<'_> {
+        PanelSwitchMenuEvent { entity, action: *self }
+    }
+
+    fn get_label(&self) -> impl Into<String> {
+        match self {
+            PanelSwitchMenu::Normal => "Normal",
+            PanelSwitchMenu::Reverse => "Reverse",
+            PanelSwitchMenu::Block => "Block",
+            PanelSwitchMenu::Unblock => "Unblock",
+            PanelSwitchMenu::Repair => "Clear damage",
+        }
+    }
+
+    /// The position the switch isn't set to, blocking, and clearing the damage. A throw may still
+    /// be refused by the simulation when the switch is locked.
+    fn list_available_items(
+        target: Entity,
+        ctx: &mut SystemParamItem<Self::Context>,
+    ) -> impl IntoIterator<Item = Self> {
+        let mut items = Vec::new();
+        let (Ok(glyph), Some(block_map)) = (ctx.glyphs.get(target), ctx.block_map.as_ref()) else {
+            return items;
+        };
+        let Some(switch) = block_map.switch(glyph.0) else {
+            return items;
+        };
+        items.push(match switch.position {
+            SwitchPosition::Straight => PanelSwitchMenu::Reverse,
+            SwitchPosition::Side => PanelSwitchMenu::Normal,
         });
+        items.push(if switch.blocked {
+            PanelSwitchMenu::Unblock
+        } else {
+            PanelSwitchMenu::Block
+        });
+        if switch.failed {
+            items.push(PanelSwitchMenu::Repair);
+        }
+        items
+    }
+}
+
+fn on_switch_menu_action(
+    event: On<PanelSwitchMenuEvent>,
+    query: Query<&SwitchGlyph>,
+    mut switch_updates: MessageWriter<SwitchUpdate>,
+    mut blocking: MessageWriter<SwitchBlocking>,
+    mut clearances: MessageWriter<ClearanceRequest>,
+) {
+    let Ok(glyph) = query.get(event.entity) else {
+        return;
+    };
+    let switch_id = glyph.0;
+    match event.action {
+        PanelSwitchMenu::Normal => {
+            switch_updates.write(SwitchUpdate::new(
+                switch_id,
+                SwitchPosition::Straight,
+                CommandSource::Dispatcher,
+            ));
+        }
+        PanelSwitchMenu::Reverse => {
+            switch_updates.write(SwitchUpdate::new(
+                switch_id,
+                SwitchPosition::Side,
+                CommandSource::Dispatcher,
+            ));
+        }
+        PanelSwitchMenu::Block => {
+            blocking.write(SwitchBlocking {
+                switch_id,
+                blocked: true,
+            });
+        }
+        PanelSwitchMenu::Unblock => {
+            blocking.write(SwitchBlocking {
+                switch_id,
+                blocked: false,
+            });
+        }
+        PanelSwitchMenu::Repair => {
+            clearances.write(ClearanceRequest::Switch(switch_id));
+        }
     }
 }
@@ -0,0 +1,131 @@
+use crate::assets::{AssetHandles, LoadingState};
+use crate::common::TrainId;
+use crate::level::{Level, LevelReloaded, watch_level};
+use crate::save::{GameLoaded, clear_simulation_messages};
+use crate::simulation::block::BlockMap;
+use crate::simulation::spad::SpadRegister;
+use crate::simulation::spawner::{rebuild_spawners, restore_spawners, save_spawners};
+use crate::simulation::station::StationMap;
+use crate::simulation::train::{Train, retain_trains};
+use bevy::prelude::*;
+use std::collections::HashSet;
+
+/// Rebuilds the simulation from the level when its file changes while the game is running.
+/// Switch positions and speed restrictions carry over, routes are dropped. Trains stay when every
+/// block they occupy still exists with the same length, the others are removed along with their
+/// SPADs. The timetable and the automatic route setting carry on unchanged.
+pub struct ReloadPlugin;
+
+impl Plugin for ReloadPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PreUpdate,
+            reload_simulation
+                .after(watch_level)
+                .run_if(on_message::<LevelReloaded>)
+                .run_if(in_state(LoadingState::Instantiated)),
+        );
+    }
+}
+
+/// Drops the SPADs of the removed trains and at the removed signals, as nobody can acknowledge them
+/// any more. Trains left with no pending SPAD are released.
+fn forget_spads(world: &mut World, removed_ids: &HashSet<TrainId>) {
+    world.resource_scope(|world, mut register: Mut<SpadRegister>| {
+        let block_map = world.resource::<BlockMap>();
+        register.retain(|spad| !removed_ids.contains(&spad.train_id) && block_map.signal(spad.signal_id).is_some());
+        for mut train in world.query::<&mut Train>().iter_mut(world) {
+            if train.tripped() && !register.unacknowledged().any(|spad| spad.train_id == train.id) {
+                train.release_train_protection();
+            }
+        }
+    });
+}
+
+/// Trains that can stay on the map built from the new level
+fn kept_trains(trains: impl IntoIterator<Item = TrainId>, previous: &BlockMap, next: &BlockMap) -> HashSet<TrainId> {
+    let same_block = |block_id| match (previous.get_block(block_id), next.get_block(block_id)) {
+        (Some(old), Some(new)) => old.length_m == new.length_m,
+        _ => false,
+    };
+    trains
+        .into_iter()
+        .filter(|&train_id| {
+            previous
+                .get_train_blocks(train_id)
+                .is_some_and(|blocks| blocks.iter().all(|&block_id| same_block(block_id)))
+        })
+        .collect()
+}
+
+pub fn reload_simulation(world: &mut World) {
+    let handles = world.resource::<AssetHandles>();
+    let Some(level) = world.resource::<Assets<Level>>().get(&handles.level) else {
+        return;
+    };
+    let mut block_map = BlockMap::from_level(level);
+    let station_map = StationMap::from_level(level);
+    let rail_condition = level.rail_condition;
+
+    let previous = world.remove_resource::<BlockMap>().expect("block map had been built");
+    let train_ids: Vec<TrainId> = world.query::<&Train>().iter(world).map(|train| train.id).collect();
+    let kept = kept_trains(train_ids.iter().copied(), &previous, &block_map);
+    let removed_ids: HashSet<TrainId> = train_ids.into_iter().filter(|id| !kept.contains(id)).collect();
+    let removed = retain_trains(world, |train| kept.contains(&train.id));
+
+    // Messages still in flight refer to the previous layout
+    clear_simulation_messages(world);
+
+    block_map.carry_over(&previous, &kept);
+    world.insert_resource(block_map);
+    world.insert_resource(station_map);
+    world.insert_resource(rail_condition);
+    forget_spads(world, &removed_ids);
+
+    let mut spawners = save_spawners(world);
+    spawners.retain_trains(&kept);
+    rebuild_spawners(world);
+    restore_spawners(world, &spawners);
+
+    // Announce the new layout like on startup, then the occupation by the trains that stayed
+    let trains: Vec<Train> = world.query::<&Train>().iter(world).cloned().collect();
+    let block_map = world.resource::<BlockMap>();
+    let (track_updates, switch_updates) = block_map.initial_updates();
+    let occupation = block_map.occupation_updates(&trains);
+    world.write_message_batch(switch_updates);
+    world.write_message_batch(track_updates);
+    world.write_message_batch(occupation);
+    world.write_message(GameLoaded);
+    info!("Simulation rebuilt, {} trains kept, {} removed", kept.len(), removed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trains_on_changed_blocks_are_removed() {
+        let level = Level::test_fixture();
+        let mut previous = BlockMap::from_level(&level);
+        let state = toml::from_str(
+            r#"
+            occupied = [[2, [1]], [3, [1]], [9, [2]], [10, [2]]]
+            switches = []
+            signals = []
+            restrictions = []
+            "#,
+        )
+        .unwrap();
+        previous.restore_state(&state);
+
+        let mut changed = level.clone();
+        changed.blocks.iter_mut().find(|block| block.id == 9).unwrap().length = 900.0;
+        let next = BlockMap::from_level(&changed);
+        // train 3 occupies no block and can't be placed on the new map either
+        assert_eq!(kept_trains([1, 2, 3], &previous, &next), HashSet::from([1]));
+        assert_eq!(
+            kept_trains([1, 2, 3], &previous, &BlockMap::from_level(&level)),
+            HashSet::from([1, 2])
+        );
+    }
+}
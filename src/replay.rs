@@ -0,0 +1,535 @@
+use crate::assets::{AssetHandles, LoadingState};
+use crate::common::{BlockId, CommandSource, RouteId, SignalId, SwitchId, SwitchPosition, TrainId, arg_value};
+use crate::level::level_name;
+use crate::simulation::ars::ArsToggle;
+use crate::simulation::block::TemporarySpeedRestriction;
+use crate::simulation::incident::ClearanceRequest;
+use crate::simulation::random::SimulationRng;
+use crate::simulation::signal::SpeedLimit;
+use crate::simulation::spad::SpadAcknowledgement;
+use crate::simulation::spawner::{SpawnRequest, SpawnTrainType};
+use crate::simulation::station::{RouteActivationRequest, RouteCancellationRequest, SwitchBlocking, SwitchUpdate};
+use crate::simulation::train::CallingStation;
+use crate::time_controls::{PauseToggled, TimeScaleChanged};
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Recorded commands are written out at most this often in real time, and when the app exits
+const RECORDING_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A dispatcher command as stored in a recording
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum RecordedCommand {
+    SetRoute {
+        route_id: RouteId,
+    },
+    CancelRoute {
+        route_id: RouteId,
+    },
+    Spawn {
+        block_id: BlockId,
+        train_type: SpawnTrainType,
+        number: Option<String>,
+        destination: Option<BlockId>,
+        #[serde(default)]
+        priority: u8,
+        #[serde(default)]
+        calling_at: Vec<CallingStation>,
+    },
+    Switch {
+        switch_id: SwitchId,
+        position: SwitchPosition,
+    },
+    SwitchBlocking {
+        switch_id: SwitchId,
+        blocked: bool,
+    },
+    ClearSwitch {
+        switch_id: SwitchId,
+    },
+    ClearTrain {
+        train_id: TrainId,
+    },
+    SpadAcknowledgement {
+        signal_id: SignalId,
+    },
+    SpeedRestriction {
+        block_id: BlockId,
+        speed_limit: SpeedLimit,
+    },
+    Ars {
+        signals: Vec<SignalId>,
+        enabled: bool,
+    },
+    TimeScale {
+        time_scale: f64,
+    },
+    Pause {
+        paused: bool,
+    },
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct TimedCommand {
+    /// Fixed simulation steps run since the level was instantiated when the command took effect.
+    /// Trains move in the fixed steps, so the command is replayed once as many steps have run.
+    pub tick: u64,
+    /// Virtual time since the level was instantiated, for reading the recording
+    pub time_s: f64,
+    #[serde(flatten)]
+    pub command: RecordedCommand,
+}
+
+/// Dispatcher commands of a run, replaying them on the same level with the same seed reproduces it
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct Recording {
+    /// Asset path of the level the commands were recorded on
+    pub level: String,
+    pub seed: u64,
+    #[serde(default)]
+    pub commands: Vec<TimedCommand>,
+}
+
+impl Recording {
+    /// Name of the recorded level, `None` if it isn't in the levels directory
+    pub fn level_name(&self) -> Option<&str> {
+        level_name(&self.level)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+        toml::from_str(&contents).map_err(|e| format!("can't parse {}: {}", path.display(), e))
+    }
+}
+
+/// Reads the `--record <path>` and `--replay <path>` command line options
+pub fn replay_options_from_args(args: &[String]) -> Result<(Option<PathBuf>, Option<Recording>), String> {
+    let record = arg_value(args, "--record")?.map(PathBuf::from);
+    let replay = arg_value(args, "--replay")?
+        .map(|path| Recording::load(Path::new(path)))
+        .transpose()?;
+    Ok((record, replay))
+}
+
+/// Records the dispatcher's commands to a file and replays a recording. The recording file is
+/// rewritten every few seconds while there are new commands and on exit, so only the last few
+/// seconds are lost if the game is not closed cleanly. Commands issued by the simulation itself are
+/// not recorded, the replayed game issues them again. The RNG has to be seeded with the
+/// recording's seed for the replay to match.
+#[derive(Default)]
+pub struct ReplayPlugin {
+    pub record: Option<PathBuf>,
+    pub replay: Option<Recording>,
+}
+
+/// Fixed simulation steps run so far, commands are recorded and replayed by it
+#[derive(Resource, Default)]
+struct FixedTicks(u64);
+
+#[derive(Resource)]
+struct Recorder {
+    path: PathBuf,
+    start_s: f64,
+    start_tick: u64,
+    commands: Vec<TimedCommand>,
+    dirty: bool,
+    flush_timer: Timer,
+}
+
+impl Recorder {
+    fn push(&mut self, time: &Time, ticks: &FixedTicks, command: RecordedCommand) {
+        self.commands.push(TimedCommand {
+            tick: ticks.0 - self.start_tick,
+            time_s: time.elapsed_secs_f64() - self.start_s,
+            command,
+        });
+        self.dirty = true;
+    }
+}
+
+#[derive(Resource)]
+struct Replay {
+    level: String,
+    start_tick: u64,
+    /// Remaining commands in reverse order, so the next one is popped from the end
+    commands: Vec<TimedCommand>,
+}
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        if self.record.is_some() || self.replay.is_some() {
+            app.init_resource::<FixedTicks>().add_systems(FixedFirst, count_ticks);
+        }
+        if let Some(path) = &self.record {
+            app.insert_resource(Recorder {
+                path: path.clone(),
+                start_s: 0.0,
+                start_tick: 0,
+                commands: Vec::new(),
+                dirty: true,
+                flush_timer: Timer::new(RECORDING_FLUSH_INTERVAL, TimerMode::Repeating),
+            })
+            .add_systems(OnEnter(LoadingState::Instantiated), start_recording)
+            .add_systems(
+                Last,
+                (record_commands, write_recording)
+                    .chain()
+                    .run_if(in_state(LoadingState::Instantiated)),
+            )
+            .add_observer(record_spawn)
+            .add_observer(record_time_scale)
+            .add_observer(record_pause);
+        }
+        if let Some(recording) = &self.replay {
+            let mut commands = recording.commands.clone();
+            commands.sort_by_key(|c| c.tick);
+            commands.reverse();
+            app.insert_resource(Replay {
+                level: recording.level.clone(),
+                start_tick: 0,
+                commands,
+            })
+            .add_systems(OnEnter(LoadingState::Instantiated), start_replay)
+            // Once the frame's fixed steps have run, so a command is handled after the same step as
+            // when it was recorded, whatever the frame rate
+            .add_systems(
+                RunFixedMainLoop,
+                replay_commands
+                    .in_set(RunFixedMainLoopSystems::AfterFixedMainLoop)
+                    .run_if(in_state(LoadingState::Instantiated)),
+            );
+        }
+    }
+}
+
+fn count_ticks(mut ticks: ResMut<FixedTicks>) {
+    ticks.0 += 1;
+}
+
+fn start_recording(time: Res<Time>, ticks: Res<FixedTicks>, mut recorder: ResMut<Recorder>) {
+    recorder.start_s = time.elapsed_secs_f64();
+    recorder.start_tick = ticks.0;
+    info!("Recording commands to {}", recorder.path.display());
+}
+
+#[derive(SystemParam)]
+struct RecordedReaders<'w, 's> {
+    route_activations: MessageReader<'w, 's, RouteActivationRequest>,
+    route_cancellations: MessageReader<'w, 's, RouteCancellationRequest>,
+    switch_updates: MessageReader<'w, 's, SwitchUpdate>,
+    switch_blocking: MessageReader<'w, 's, SwitchBlocking>,
+    clearances: MessageReader<'w, 's, ClearanceRequest>,
+    spad_acknowledgements: MessageReader<'w, 's, SpadAcknowledgement>,
+    restrictions: MessageReader<'w, 's, TemporarySpeedRestriction>,
+    ars_toggles: MessageReader<'w, 's, ArsToggle>,
+}
+
+fn record_commands(
+    time: Res<Time>,
+    ticks: Res<FixedTicks>,
+    mut recorder: ResMut<Recorder>,
+    mut readers: RecordedReaders,
+) {
+    for request in readers
+        .route_activations
+        .read()
+        .filter(|r| r.source == CommandSource::Dispatcher)
+    {
+        recorder.push(
+            &time,
+            &ticks,
+            RecordedCommand::SetRoute {
+                route_id: request.route_id,
+            },
+        );
+    }
+    for request in readers
+        .route_cancellations
+        .read()
+        .filter(|r| r.source == CommandSource::Dispatcher)
+    {
+        recorder.push(
+            &time,
+            &ticks,
+            RecordedCommand::CancelRoute {
+                route_id: request.route_id,
+            },
+        );
+    }
+    for update in readers
+        .switch_updates
+        .read()
+        .filter(|u| u.source == CommandSource::Dispatcher)
+    {
+        recorder.push(
+            &time,
+            &ticks,
+            RecordedCommand::Switch {
+                switch_id: update.switch_id,
+                position: update.position,
+            },
+        );
+    }
+    for request in readers.switch_blocking.read() {
+        recorder.push(
+            &time,
+            &ticks,
+            RecordedCommand::SwitchBlocking {
+                switch_id: request.switch_id,
+                blocked: request.blocked,
+            },
+        );
+    }
+    for request in readers.clearances.read() {
+        let command = match *request {
+            ClearanceRequest::Switch(switch_id) => RecordedCommand::ClearSwitch { switch_id },
+            ClearanceRequest::Train(train_id) => RecordedCommand::ClearTrain { train_id },
+        };
+        recorder.push(&time, &ticks, command);
+    }
+    for acknowledgement in readers.spad_acknowledgements.read() {
+        recorder.push(
+            &time,
+            &ticks,
+            RecordedCommand::SpadAcknowledgement {
+                signal_id: acknowledgement.signal_id,
+            },
+        );
+    }
+    for restriction in readers
+        .restrictions
+        .read()
+        .filter(|r| r.source == CommandSource::Dispatcher)
+    {
+        recorder.push(
+            &time,
+            &ticks,
+            RecordedCommand::SpeedRestriction {
+                block_id: restriction.block_id,
+                speed_limit: restriction.speed_limit,
+            },
+        );
+    }
+    for toggle in readers.ars_toggles.read() {
+        recorder.push(
+            &time,
+            &ticks,
+            RecordedCommand::Ars {
+                signals: toggle.signals.clone(),
+                enabled: toggle.enabled,
+            },
+        );
+    }
+}
+
+fn record_spawn(request: On<SpawnRequest>, time: Res<Time>, ticks: Res<FixedTicks>, mut recorder: ResMut<Recorder>) {
+    if request.source != CommandSource::Dispatcher {
+        return;
+    }
+    recorder.push(
+        &time,
+        &ticks,
+        RecordedCommand::Spawn {
+            block_id: request.block_id,
+            train_type: request.train_type.clone(),
+            number: request.number.clone(),
+            destination: request.destination,
+            priority: request.priority,
+            calling_at: request.calling_at.clone(),
+        },
+    );
+}
+
+fn record_time_scale(
+    change: On<TimeScaleChanged>,
+    time: Res<Time>,
+    ticks: Res<FixedTicks>,
+    mut recorder: ResMut<Recorder>,
+) {
+    recorder.push(
+        &time,
+        &ticks,
+        RecordedCommand::TimeScale {
+            time_scale: change.time_scale,
+        },
+    );
+}
+
+fn record_pause(toggle: On<PauseToggled>, time: Res<Time>, ticks: Res<FixedTicks>, mut recorder: ResMut<Recorder>) {
+    recorder.push(&time, &ticks, RecordedCommand::Pause { paused: toggle.paused });
+}
+
+/// Runs on real time, so the commands given while the game is paused are written too
+fn write_recording(
+    real_time: Res<Time<Real>>,
+    handles: Res<AssetHandles>,
+    rng: Res<SimulationRng>,
+    mut recorder: ResMut<Recorder>,
+    mut exits: MessageReader<AppExit>,
+) {
+    let flush_due = recorder.flush_timer.tick(real_time.delta()).just_finished();
+    let exiting = exits.read().count() > 0;
+    if !recorder.dirty || !(flush_due || exiting) {
+        return;
+    }
+    recorder.dirty = false;
+    let recording = Recording {
+        level: handles.level.path().map(ToString::to_string).unwrap_or_default(),
+        seed: rng.seed(),
+        commands: recorder.commands.clone(),
+    };
+    let result = toml::to_string(&recording)
+        .map_err(|e| e.to_string())
+        .and_then(|contents| {
+            if let Some(dir) = recorder.path.parent() {
+                std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            }
+            std::fs::write(&recorder.path, contents).map_err(|e| e.to_string())
+        });
+    if let Err(e) = result {
+        warn!("Failed to write the recording to {}: {}", recorder.path.display(), e);
+    }
+}
+
+fn start_replay(ticks: Res<FixedTicks>, handles: Res<AssetHandles>, mut replay: ResMut<Replay>) {
+    replay.start_tick = ticks.0;
+    let level = handles.level.path().map(ToString::to_string).unwrap_or_default();
+    // The level is picked from the recording, unless it isn't in the levels directory
+    if replay.level != level {
+        warn!(
+            "The recording was made on level '{}', replaying it on '{}' will diverge",
+            replay.level, level
+        );
+    }
+    info!("Replaying {} commands", replay.commands.len());
+}
+
+#[derive(SystemParam)]
+struct ReplayWriters<'w> {
+    route_activations: MessageWriter<'w, RouteActivationRequest>,
+    route_cancellations: MessageWriter<'w, RouteCancellationRequest>,
+    switch_updates: MessageWriter<'w, SwitchUpdate>,
+    switch_blocking: MessageWriter<'w, SwitchBlocking>,
+    clearances: MessageWriter<'w, ClearanceRequest>,
+    spad_acknowledgements: MessageWriter<'w, SpadAcknowledgement>,
+    restrictions: MessageWriter<'w, TemporarySpeedRestriction>,
+    ars_toggles: MessageWriter<'w, ArsToggle>,
+}
+
+fn replay_commands(
+    ticks: Res<FixedTicks>,
+    mut replay: ResMut<Replay>,
+    mut writers: ReplayWriters,
+    mut commands: Commands,
+) {
+    let tick = ticks.0 - replay.start_tick;
+    while replay.commands.last().is_some_and(|c| c.tick <= tick) {
+        let command = replay.commands.pop().expect("checked above").command;
+        match command {
+            RecordedCommand::SetRoute { route_id } => {
+                writers.route_activations.write(RouteActivationRequest {
+                    route_id,
+                    source: CommandSource::Dispatcher,
+                });
+            }
+            RecordedCommand::CancelRoute { route_id } => {
+                writers.route_cancellations.write(RouteCancellationRequest {
+                    route_id,
+                    source: CommandSource::Dispatcher,
+                });
+            }
+            RecordedCommand::Spawn {
+                block_id,
+                train_type,
+                number,
+                destination,
+                priority,
+                calling_at,
+            } => commands.trigger(SpawnRequest {
+                block_id,
+                train_type,
+                number,
+                destination,
+                priority,
+                calling_at,
+                exit_time_s: None,
+                source: CommandSource::Dispatcher,
+            }),
+            RecordedCommand::Switch { switch_id, position } => {
+                writers
+                    .switch_updates
+                    .write(SwitchUpdate::new(switch_id, position, CommandSource::Dispatcher));
+            }
+            RecordedCommand::SwitchBlocking { switch_id, blocked } => {
+                writers.switch_blocking.write(SwitchBlocking { switch_id, blocked });
+            }
+            RecordedCommand::ClearSwitch { switch_id } => {
+                writers.clearances.write(ClearanceRequest::Switch(switch_id));
+            }
+            RecordedCommand::ClearTrain { train_id } => {
+                writers.clearances.write(ClearanceRequest::Train(train_id));
+            }
+            RecordedCommand::SpadAcknowledgement { signal_id } => {
+                writers.spad_acknowledgements.write(SpadAcknowledgement { signal_id });
+            }
+            RecordedCommand::SpeedRestriction { block_id, speed_limit } => {
+                writers.restrictions.write(TemporarySpeedRestriction {
+                    block_id,
+                    speed_limit,
+                    source: CommandSource::Dispatcher,
+                });
+            }
+            RecordedCommand::Ars { signals, enabled } => {
+                writers.ars_toggles.write(ArsToggle { signals, enabled });
+            }
+            RecordedCommand::TimeScale { time_scale } => commands.trigger(TimeScaleChanged { time_scale }),
+            RecordedCommand::Pause { paused } => commands.trigger(PauseToggled { paused }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recording_round_trips_through_toml() {
+        let recording = Recording {
+            level: "levels/passing_loop.toml".to_string(),
+            seed: 7,
+            commands: vec![
+                TimedCommand {
+                    tick: 352,
+                    time_s: 5.5,
+                    command: RecordedCommand::Spawn {
+                        block_id: 1,
+                        train_type: SpawnTrainType("cargo".to_string()),
+                        number: Some("2402".to_string()),
+                        destination: None,
+                        priority: 0,
+                        calling_at: Vec::new(),
+                    },
+                },
+                TimedCommand {
+                    tick: 1920,
+                    time_s: 30.0,
+                    command: RecordedCommand::SpeedRestriction {
+                        block_id: 6,
+                        speed_limit: SpeedLimit::Restricted(40.0),
+                    },
+                },
+                TimedCommand {
+                    tick: 2000,
+                    time_s: 31.25,
+                    command: RecordedCommand::Pause { paused: true },
+                },
+            ],
+        };
+        let contents = toml::to_string(&recording).unwrap();
+        assert_eq!(toml::from_str::<Recording>(&contents).unwrap(), recording);
+    }
+}
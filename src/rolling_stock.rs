@@ -0,0 +1,153 @@
+use bevy::{asset::AssetLoader, asset::LoadContext, asset::io::Reader, prelude::*};
+use futures_lite::AsyncReadExt;
+use serde::Deserialize;
+use thiserror::Error;
+
+/// Catalog of vehicle types and the named consists built from them
+#[derive(Deserialize, Asset, Reflect)]
+pub struct RollingStock {
+    pub vehicles: Vec<VehicleData>,
+    pub consists: Vec<ConsistData>,
+}
+
+impl RollingStock {
+    pub fn get_vehicle(&self, id: &str) -> Option<&VehicleData> {
+        self.vehicles.iter().find(|v| v.id == id)
+    }
+
+    pub fn get_consist(&self, id: &str) -> Option<&ConsistData> {
+        self.consists.iter().find(|c| c.id == id)
+    }
+
+    fn validate(&self) -> Result<(), RollingStockLoaderError> {
+        for consist in &self.consists {
+            if consist.vehicles.is_empty() {
+                return Err(RollingStockLoaderError::EmptyConsist(consist.id.clone()));
+            }
+            if let Some(entry) = consist.vehicles.iter().find(|e| self.get_vehicle(&e.vehicle).is_none()) {
+                return Err(RollingStockLoaderError::UnknownVehicle(
+                    consist.id.clone(),
+                    entry.vehicle.clone(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Deserialize, Reflect)]
+#[serde(rename_all = "lowercase")]
+pub enum VehicleKind {
+    Locomotive,
+    /// Powered passenger car of a multiple unit
+    Emu,
+    Wagon,
+}
+
+#[derive(Deserialize, Reflect)]
+pub struct VehicleData {
+    pub id: String,
+    pub kind: VehicleKind,
+    pub mass_kg: f64,
+    pub length_m: f64,
+    #[serde(default)]
+    pub cargo_mass_kg: f64,
+    #[serde(default)]
+    pub power_kw: f64,
+    #[serde(default)]
+    pub max_tractive_effort_kn: f64,
+    /// Overrides the default braking force for the vehicle kind
+    #[serde(default)]
+    pub max_braking_force_kn: Option<f64>,
+    /// Davis coefficients A (N), B (N·s/m), C (N·s²/m²), typical values are used when omitted
+    #[serde(default)]
+    pub resistance: Option<[f64; 3]>,
+}
+
+#[derive(Deserialize, Reflect)]
+pub struct ConsistEntry {
+    pub vehicle: String,
+    pub count: usize,
+}
+
+#[derive(Deserialize, Reflect)]
+pub struct ConsistData {
+    pub id: String,
+    pub name: String,
+    pub top_speed_kmh: f64,
+    pub vehicles: Vec<ConsistEntry>,
+}
+
+pub struct RollingStockPlugin;
+
+impl Plugin for RollingStockPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<RollingStock>()
+            .register_asset_loader(RollingStockLoader);
+    }
+}
+
+#[derive(TypePath)]
+struct RollingStockLoader;
+
+#[derive(Debug, Error)]
+enum RollingStockLoaderError {
+    #[error("Failed to load rolling stock file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Could not parse rolling stock file: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Consist '{0}' has no vehicles")]
+    EmptyConsist(String),
+    #[error("Consist '{0}' references unknown vehicle '{1}'")]
+    UnknownVehicle(String, String),
+}
+
+impl AssetLoader for RollingStockLoader {
+    type Asset = RollingStock;
+    type Settings = ();
+    type Error = RollingStockLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &Self::Settings,
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents).await?;
+        let rolling_stock: RollingStock = toml::from_str(&contents)?;
+        rolling_stock.validate()?;
+        Ok(rolling_stock)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["toml"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_catalog_is_valid() {
+        let rolling_stock: RollingStock = toml::from_str(include_str!("../resources/rolling_stock.toml")).unwrap();
+        rolling_stock.validate().unwrap();
+        assert!(rolling_stock.get_consist("cargo").is_some());
+    }
+
+    #[test]
+    fn unknown_vehicle_is_rejected() {
+        let rolling_stock: RollingStock = toml::from_str(
+            r#"
+            vehicles = [{ id = "loco", kind = "locomotive", mass_kg = 80000, length_m = 16 }]
+            consists = [{ id = "x", name = "X", top_speed_kmh = 80, vehicles = [{ vehicle = "wagon", count = 2 }] }]
+            "#,
+        )
+        .unwrap();
+        assert!(matches!(
+            rolling_stock.validate(),
+            Err(RollingStockLoaderError::UnknownVehicle(consist, vehicle)) if consist == "x" && vehicle == "wagon"
+        ));
+    }
+}
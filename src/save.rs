@@ -0,0 +1,267 @@
+use crate::assets::{AssetHandles, LoadingState};
+use crate::audio::AudioEvent;
+use crate::simulation::ars::{ArsState, ArsToggle, AutoRouteSetting};
+use crate::simulation::block::{
+    BlockMap, BlockMapState, SignalAspectChanged, SignalUpdate, TemporarySpeedRestriction, TrackUpdate,
+};
+use crate::simulation::incident::{ClearanceRequest, Incident};
+use crate::simulation::random::{RngState, SimulationRng};
+use crate::simulation::spad::{SignalPassedAtDanger, SpadAcknowledgement, SpadRegister, SpadState};
+use crate::simulation::spawner::{SpawnersState, restore_spawners, save_spawners};
+use crate::simulation::station::{
+    RouteActivationRequest, RouteCancellationRequest, RoutePending, RouteSectionReleased, StationMap, StationMapState,
+    SwitchBlocking, SwitchStateChanged, SwitchThrowRejected, SwitchUpdate,
+};
+use crate::simulation::timetable::{Timetable, TimetableState};
+use crate::simulation::train::{
+    Train, TrainDespawnRequest, TrainMove, TrainSpawnRequest, TrainsState, restore_trains, save_trains,
+};
+use crate::time_controls::{PauseToggled, TimeScaleChanged};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Saves of other format versions are rejected
+const SAVE_VERSION: u32 = 2;
+/// File written and read by the quick save and quick load keys
+const QUICKSAVE_PATH: &str = "saves/quicksave.toml";
+
+#[derive(Message)]
+pub struct SaveRequest {
+    pub path: PathBuf,
+}
+
+#[derive(Message)]
+pub struct LoadRequest {
+    pub path: PathBuf,
+}
+
+/// Sent after a saved game has been restored or the level has been reloaded, the presentation
+/// rebuilds its state from the simulation
+#[derive(Message)]
+pub struct GameLoaded;
+
+/// Virtual clock of the game, the time scale and pause are restored with the game
+#[derive(Serialize, Deserialize)]
+struct ClockState {
+    elapsed_s: f64,
+    time_scale: f64,
+    paused: bool,
+}
+
+impl ClockState {
+    fn save(time: &Time<Virtual>) -> Self {
+        ClockState {
+            elapsed_s: time.elapsed_secs_f64(),
+            time_scale: time.relative_speed_f64(),
+            paused: time.is_paused(),
+        }
+    }
+}
+
+/// Dynamic state of a game, the static layout is loaded from the level
+#[derive(Serialize, Deserialize)]
+struct SavedGame {
+    version: u32,
+    /// Asset path of the level the game was played on
+    level: String,
+    clock: ClockState,
+    rng: RngState,
+    timetable: TimetableState,
+    ars: ArsState,
+    block_map: BlockMapState,
+    stations: StationMapState,
+    spawners: SpawnersState,
+    trains: TrainsState,
+    #[serde(default)]
+    spads: SpadState,
+}
+
+#[derive(Debug, Error)]
+enum SaveError {
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not write the saved game: {0}")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("could not parse the saved game: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("saved game version {0} is not supported")]
+    Version(u32),
+    #[error("the game was saved on level '{0}'")]
+    Level(String),
+}
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_message::<SaveRequest>()
+            .add_message::<LoadRequest>()
+            .add_message::<GameLoaded>()
+            .add_systems(
+                Update,
+                quicksave_keys
+                    .run_if(resource_exists::<ButtonInput<KeyCode>>)
+                    .run_if(in_state(LoadingState::Instantiated)),
+            )
+            // At the end of the frame all systems have handled the simulation messages written in it
+            .add_systems(Last, handle_requests.run_if(in_state(LoadingState::Instantiated)));
+    }
+}
+
+fn quicksave_keys(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut saves: MessageWriter<SaveRequest>,
+    mut loads: MessageWriter<LoadRequest>,
+) {
+    if keyboard_input.just_pressed(KeyCode::F5) {
+        saves.write(SaveRequest {
+            path: QUICKSAVE_PATH.into(),
+        });
+    }
+    if keyboard_input.just_pressed(KeyCode::F9) {
+        loads.write(LoadRequest {
+            path: QUICKSAVE_PATH.into(),
+        });
+    }
+}
+
+fn handle_requests(
+    mut saves: MessageReader<SaveRequest>,
+    mut loads: MessageReader<LoadRequest>,
+    mut commands: Commands,
+) {
+    for request in saves.read() {
+        let path = request.path.clone();
+        commands.queue(move |world: &mut World| match save_game(world, &path) {
+            Ok(()) => {
+                info!("Game saved to {}", path.display());
+                world.trigger(AudioEvent::beep());
+            }
+            Err(e) => {
+                warn!("Failed to save the game to {}: {}", path.display(), e);
+                world.trigger(AudioEvent::error());
+            }
+        });
+    }
+    for request in loads.read() {
+        let path = request.path.clone();
+        commands.queue(move |world: &mut World| match load_game(world, &path) {
+            Ok(()) => {
+                info!("Game loaded from {}", path.display());
+                world.trigger(AudioEvent::beep());
+            }
+            Err(e) => {
+                warn!("Failed to load the game from {}: {}", path.display(), e);
+                world.trigger(AudioEvent::error());
+            }
+        });
+    }
+}
+
+fn level_path(world: &World) -> String {
+    let handles = world.resource::<AssetHandles>();
+    handles.level.path().map(ToString::to_string).unwrap_or_default()
+}
+
+fn save_game(world: &mut World, path: &Path) -> Result<(), SaveError> {
+    let saved = SavedGame {
+        version: SAVE_VERSION,
+        level: level_path(world),
+        clock: ClockState::save(world.resource::<Time<Virtual>>()),
+        rng: world.resource::<SimulationRng>().save_state(),
+        timetable: world.resource::<Timetable>().save_state(),
+        ars: world.resource::<AutoRouteSetting>().save_state(),
+        block_map: world.resource::<BlockMap>().save_state(),
+        stations: world.resource::<StationMap>().save_state(),
+        spawners: save_spawners(world),
+        trains: save_trains(world),
+        spads: world.resource::<SpadRegister>().save_state(),
+    };
+    let contents = toml::to_string(&saved)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, contents)?;
+    Ok(())
+}
+
+fn load_game(world: &mut World, path: &Path) -> Result<(), SaveError> {
+    let saved: SavedGame = toml::from_str(&std::fs::read_to_string(path)?)?;
+    if saved.version != SAVE_VERSION {
+        return Err(SaveError::Version(saved.version));
+    }
+    if saved.level != level_path(world) {
+        return Err(SaveError::Level(saved.level));
+    }
+
+    // Messages still in flight refer to the replaced state
+    clear_simulation_messages(world);
+
+    // The virtual clock only runs forward, times saved on it are shifted to the current one
+    let clock_offset_s = world.resource::<Time<Virtual>>().elapsed_secs_f64() - saved.clock.elapsed_s;
+    restore_clock(world, &saved.clock);
+    world.insert_resource(SimulationRng::from_state(&saved.rng));
+    world
+        .resource_mut::<Timetable>()
+        .restore_state(&saved.timetable, clock_offset_s);
+    world.resource_mut::<AutoRouteSetting>().restore_state(&saved.ars);
+    world.resource_mut::<BlockMap>().restore_state(&saved.block_map);
+    world.resource_mut::<StationMap>().restore_state(&saved.stations);
+    restore_spawners(world, &saved.spawners);
+    restore_trains(world, saved.trains);
+    world.resource_mut::<SpadRegister>().restore_state(&saved.spads);
+
+    // Re-announce the occupation, its consumers bring signals, routes and describers in line with it
+    let trains: Vec<Train> = world.query::<&Train>().iter(world).cloned().collect();
+    let updates = world.resource::<BlockMap>().occupation_updates(&trains);
+    world.write_message_batch(updates);
+    world.write_message(GameLoaded);
+    Ok(())
+}
+
+/// Applies the saved time scale and pause, the time controls follow when they are present
+fn restore_clock(world: &mut World, clock: &ClockState) {
+    let mut time = world.resource_mut::<Time<Virtual>>();
+    time.set_relative_speed_f64(clock.time_scale);
+    if clock.paused {
+        time.pause();
+    } else {
+        time.unpause();
+    }
+    world.trigger(TimeScaleChanged {
+        time_scale: clock.time_scale,
+    });
+    world.trigger(PauseToggled { paused: clock.paused });
+}
+
+/// Drops the simulation messages not handled yet, used when the simulation state is replaced
+pub fn clear_simulation_messages(world: &mut World) {
+    clear_messages::<TrainMove>(world);
+    clear_messages::<TrainSpawnRequest>(world);
+    clear_messages::<TrainDespawnRequest>(world);
+    clear_messages::<TrackUpdate>(world);
+    clear_messages::<SignalUpdate>(world);
+    clear_messages::<SignalAspectChanged>(world);
+    clear_messages::<SwitchUpdate>(world);
+    clear_messages::<SwitchStateChanged>(world);
+    clear_messages::<SwitchThrowRejected>(world);
+    clear_messages::<SwitchBlocking>(world);
+    clear_messages::<Incident>(world);
+    clear_messages::<ClearanceRequest>(world);
+    clear_messages::<SignalPassedAtDanger>(world);
+    clear_messages::<SpadAcknowledgement>(world);
+    clear_messages::<TemporarySpeedRestriction>(world);
+    clear_messages::<RouteActivationRequest>(world);
+    clear_messages::<RouteCancellationRequest>(world);
+    clear_messages::<RoutePending>(world);
+    clear_messages::<RouteSectionReleased>(world);
+    clear_messages::<ArsToggle>(world);
+}
+
+fn clear_messages<M: Message>(world: &mut World) {
+    if let Some(mut messages) = world.get_resource_mut::<Messages<M>>() {
+        messages.clear();
+    }
+}
@@ -0,0 +1,272 @@
+use crate::assets::LoadingState;
+use crate::common::{CommandSource, RouteId, SignalId, SignalType};
+use crate::simulation::block::{BlockMap, TrackUpdate};
+use crate::simulation::station::{RouteActivationRequest, StationMap};
+use crate::simulation::train::Train;
+use bevy::prelude::*;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// Trains further than this on top of their stopping distance from a signal are not approaching it yet
+const ARS_APPROACH_M: f64 = 1500.0;
+/// Approaching trains are re-evaluated at least this often, on top of every track update
+const ARS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Enables or disables automatic route setting for the given manual signals
+#[derive(Message)]
+pub struct ArsToggle {
+    pub signals: Vec<SignalId>,
+    pub enabled: bool,
+}
+
+/// Automatic route setting: requests routes for trains approaching manual signals,
+/// towards the train's destination despawner
+#[derive(Resource)]
+pub struct AutoRouteSetting {
+    enabled_signals: HashSet<SignalId>,
+    timer: Timer,
+    /// Enabled signals changed since the last evaluation
+    toggled: bool,
+}
+
+impl Default for AutoRouteSetting {
+    fn default() -> Self {
+        AutoRouteSetting {
+            enabled_signals: HashSet::new(),
+            timer: Timer::new(ARS_INTERVAL, TimerMode::Repeating),
+            toggled: false,
+        }
+    }
+}
+
+/// Signals with automatic route setting enabled, as stored in a saved game
+#[derive(Serialize, Deserialize)]
+pub struct ArsState {
+    enabled_signals: Vec<SignalId>,
+}
+
+/// A train approaching an ARS-enabled signal that is not routed yet
+struct Candidate<'a> {
+    train: &'a Train,
+    signal_id: SignalId,
+    distance_m: f64,
+}
+
+/// A route claimed during one evaluation, either requested or wanted by a train that has to wait for it
+struct Claim {
+    route_id: RouteId,
+    priority: u8,
+    requested: bool,
+}
+
+impl AutoRouteSetting {
+    pub fn is_enabled(&self, signal_id: SignalId) -> bool {
+        self.enabled_signals.contains(&signal_id)
+    }
+
+    pub fn save_state(&self) -> ArsState {
+        ArsState {
+            enabled_signals: self.enabled_signals.iter().copied().sorted().collect(),
+        }
+    }
+
+    pub fn restore_state(&mut self, state: &ArsState) {
+        self.enabled_signals = state.enabled_signals.iter().copied().collect();
+        self.toggled = true;
+    }
+
+    fn handle_toggles(&mut self, toggles: &mut MessageReader<ArsToggle>) {
+        for toggle in toggles.read() {
+            for &signal_id in &toggle.signals {
+                self.toggled |= if toggle.enabled {
+                    self.enabled_signals.insert(signal_id)
+                } else {
+                    self.enabled_signals.remove(&signal_id)
+                };
+            }
+            info!(
+                "ARS {} for signals {:?}",
+                if toggle.enabled { "enabled" } else { "disabled" },
+                toggle.signals
+            );
+        }
+    }
+
+    fn find_candidates<'a>(
+        &self,
+        trains: impl Iterator<Item = &'a Train>,
+        block_map: &BlockMap,
+        station_map: &StationMap,
+    ) -> Vec<Candidate<'a>> {
+        let mut candidates: Vec<Candidate> = trains
+            .filter_map(|train| {
+                let (signal, distance_m) = train.signal_ahead(block_map)?;
+                let approaching = distance_m <= train.stopping_distance_m() + ARS_APPROACH_M;
+                (approaching
+                    && signal.signal_type == SignalType::Manual
+                    && self.is_enabled(signal.id)
+                    && !station_map.is_signal_routed(signal.id))
+                .then_some(Candidate {
+                    train,
+                    signal_id: signal.id,
+                    distance_m,
+                })
+            })
+            .collect();
+        // Higher priority first, then the train closest to its signal
+        candidates.sort_by(|a, b| {
+            Reverse(a.train.priority())
+                .cmp(&Reverse(b.train.priority()))
+                .then(a.distance_m.total_cmp(&b.distance_m))
+        });
+        candidates
+    }
+
+    /// Picks a route for every approaching train, returning the routes to request. A train's routes are
+    /// limited to those leading to its destination, and it can't take a route conflicting with one wanted
+    /// by a train of higher priority.
+    fn set_routes(&self, candidates: &[Candidate], block_map: &BlockMap, station_map: &StationMap) -> Vec<RouteId> {
+        let mut claims: Vec<Claim> = Vec::new();
+        for candidate in candidates {
+            let priority = candidate.train.priority();
+            let Some(signal) = block_map.signal(candidate.signal_id) else {
+                continue;
+            };
+            let routes: Vec<RouteId> = station_map
+                .routes_from_signal(candidate.signal_id)
+                .filter(|&(_, target)| {
+                    candidate
+                        .train
+                        .destination()
+                        .is_none_or(|destination| block_map.is_reachable(target, destination, signal.direction))
+                })
+                .map(|(route_id, _)| route_id)
+                .collect();
+            let blocked = |route_id: RouteId| {
+                claims.iter().any(|claim| {
+                    (claim.requested || claim.priority > priority)
+                        && station_map.routes_conflict(claim.route_id, route_id)
+                })
+            };
+            let available = routes
+                .iter()
+                .copied()
+                .find(|&route_id| !blocked(route_id) && station_map.can_activate(route_id, block_map));
+            match available {
+                Some(route_id) => {
+                    info!(
+                        "ARS setting route {} for train {} at signal {}",
+                        route_id, candidate.train.number, signal.name
+                    );
+                    claims.push(Claim {
+                        route_id,
+                        priority,
+                        requested: true,
+                    });
+                }
+                // Hold the preferred route so that lower priority trains don't take it over
+                None => claims.extend(routes.first().map(|&route_id| Claim {
+                    route_id,
+                    priority,
+                    requested: false,
+                })),
+            }
+        }
+        claims
+            .into_iter()
+            .filter(|claim| claim.requested)
+            .map(|claim| claim.route_id)
+            .collect()
+    }
+}
+
+pub struct ArsPlugin;
+
+impl Plugin for ArsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<AutoRouteSetting>()
+            .add_message::<ArsToggle>()
+            // Runs after the route states are updated, the requests are handled in the next frame
+            .add_systems(
+                PostUpdate,
+                (handle_ars_toggles, auto_route_setting)
+                    .chain()
+                    .run_if(in_state(LoadingState::Instantiated)),
+            );
+    }
+}
+
+fn handle_ars_toggles(mut ars: ResMut<AutoRouteSetting>, mut toggles: MessageReader<ArsToggle>) {
+    ars.handle_toggles(&mut toggles);
+}
+
+fn auto_route_setting(
+    time: Res<Time>,
+    mut ars: ResMut<AutoRouteSetting>,
+    block_map: Res<BlockMap>,
+    station_map: Res<StationMap>,
+    trains: Query<&Train>,
+    mut track_updates: MessageReader<TrackUpdate>,
+    mut activations: MessageWriter<RouteActivationRequest>,
+) {
+    let toggled = std::mem::take(&mut ars.toggled);
+    let tracks_changed = track_updates.read().count() > 0;
+    let timer_fired = ars.timer.tick(time.delta()).just_finished();
+    if ars.enabled_signals.is_empty() || !(toggled || tracks_changed || timer_fired) {
+        return;
+    }
+
+    let candidates = ars.find_candidates(trains.iter(), &block_map, &station_map);
+    let routes = ars.set_routes(&candidates, &block_map, &station_map);
+    activations.write_batch(routes.into_iter().map(|route_id| RouteActivationRequest {
+        route_id,
+        source: CommandSource::Automatic,
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{Direction, SwitchPosition};
+    use crate::level::Level;
+    use crate::simulation::block::TrackPoint;
+
+    /// Trains approaching the station of the passing loop from both ends, both 380 m short of the
+    /// entry signals 50 (even) and 51 (odd)
+    fn approaching_trains(even_priority: u8, odd_priority: u8) -> [Train; 2] {
+        [
+            Train::test_fixture(TrackPoint::new(2, 1000.0), Direction::Even, Some(11), even_priority),
+            Train::test_fixture(TrackPoint::new(10, 400.0), Direction::Odd, Some(1), odd_priority),
+        ]
+    }
+
+    fn ars_routes(trains: &[Train], block_map: &BlockMap) -> Vec<RouteId> {
+        let level = Level::test_fixture();
+        let station_map = StationMap::from_level(&level);
+        let ars = AutoRouteSetting {
+            enabled_signals: HashSet::from([50, 51]),
+            ..default()
+        };
+        let candidates = ars.find_candidates(trains.iter(), block_map, &station_map);
+        ars.set_routes(&candidates, block_map, &station_map)
+    }
+
+    #[test]
+    fn higher_priority_train_is_routed_first() {
+        let block_map = BlockMap::from_level(&Level::test_fixture());
+        // routes 1 and 3 both lead into the main platform track, the train routed second takes the loop
+        assert_eq!(ars_routes(&approaching_trains(0, 2), &block_map), [3, 2]);
+        assert_eq!(ars_routes(&approaching_trains(2, 0), &block_map), [1, 4]);
+    }
+
+    #[test]
+    fn waiting_train_claims_its_preferred_route() {
+        let mut block_map = BlockMap::from_level(&Level::test_fixture());
+        // the odd train can't get a route over the damaged switch 2, but holds route 3
+        block_map.damage_switch(2, SwitchPosition::Straight);
+        assert_eq!(ars_routes(&approaching_trains(0, 2), &block_map), [2]);
+    }
+}
@@ -1,18 +1,25 @@
 use crate::assets::{AssetHandles, LoadingState};
-use crate::common::{BlockId, Direction, SectionId, SignalId, SwitchPosition, TrainId};
-use crate::level::{BlockData, Level, SectionData};
-use crate::simulation::signal::{SignalAspect, SignalMap, TrackSignal};
+use crate::audio::AudioEvent;
+use crate::common::{
+    BlockId, CommandSource, Direction, SectionId, SignalId, StationId, SwitchId, SwitchPosition, TrainId,
+};
+use crate::level::{BlockData, Level, SectionData, SpeedLimitData, SwitchSetting};
+use crate::simulation::signal::{SignalAspect, SignalMap, SpeedLimit, TrackSignal};
 use crate::simulation::sparse_vec::{Chunkable, SparseVec};
-use crate::simulation::station::{Switch, SwitchUpdate};
-use crate::simulation::train::{TrainMove, TrainMoveKind};
+use crate::simulation::station::{
+    StationMap, Switch, SwitchBlocking, SwitchState, SwitchStateChanged, SwitchThrowRejected, SwitchUpdate,
+};
+use crate::simulation::train::{Train, TrainMove, TrainMoveKind};
 use arrayvec::ArrayVec;
 use bevy::prelude::*;
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::Formatter;
 use std::ops::Not;
+use std::time::Duration;
 
-#[derive(Default, Copy, Clone, PartialEq)]
+#[derive(Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum TrackState {
     #[default]
     Freed,
@@ -111,8 +118,10 @@ pub enum SignalUpdateSource {
     BlockChange(TrackState),
     /// Update caused by the change of the next signal state
     SignalPropagation(SignalAspect),
-    /// Manual override, e.g. from route activation
+    /// Manual override, e.g. from route cancellation
     Manual(SignalAspect),
+    /// Manual opening for a route, with the route's speed limit
+    Route(SpeedLimit),
 }
 
 /// A request to update the signal. Contains a target signal ID and a source of the request.
@@ -140,6 +149,54 @@ pub struct SignalAspectChanged {
     pub aspect: SignalAspect,
 }
 
+/// A request to impose a temporary speed restriction over the whole block, or to lift it with
+/// `SpeedLimit::Unrestricted`
+#[derive(Message)]
+pub struct TemporarySpeedRestriction {
+    pub block_id: BlockId,
+    pub speed_limit: SpeedLimit,
+    pub source: CommandSource,
+}
+
+/// Line speed limit over a range of a block, offsets are measured in the even direction
+struct LineSpeedLimit {
+    from_offset_m: f64,
+    to_offset_m: f64,
+    speed_kmh: f64,
+    temporary: bool,
+}
+
+impl From<&SpeedLimitData> for LineSpeedLimit {
+    fn from(value: &SpeedLimitData) -> Self {
+        LineSpeedLimit {
+            from_offset_m: value.from_offset_m,
+            to_offset_m: value.to_offset_m,
+            speed_kmh: value.speed_kmh,
+            temporary: false,
+        }
+    }
+}
+
+/// Platform track of a station, stopping marks are measured in the even direction
+struct Platform {
+    station_id: StationId,
+    even_stop_m: f64,
+    odd_stop_m: f64,
+}
+
+/// Line speed limit found along the track, with distances to its start and end
+/// relative to the lookup point (negative if already passed)
+#[derive(Debug, PartialEq)]
+pub struct SpeedLimitAhead {
+    /// Block the limit is placed on, together with `index` identifies the limit
+    pub block_id: BlockId,
+    /// Position of the limit among the limits of its block
+    pub index: usize,
+    pub speed_kmh: f64,
+    pub start_m: f64,
+    pub end_m: f64,
+}
+
 #[derive(Default)]
 struct BlockTracker {
     blocks: HashMap<BlockId, Vec<TrainId>>,
@@ -215,6 +272,27 @@ impl BlockTracker {
     }
 }
 
+/// Dynamic state of the track as stored in a saved game, the static layout comes from the level
+#[derive(Serialize, Deserialize)]
+pub struct BlockMapState {
+    /// Trains occupying each block
+    occupied: Vec<(BlockId, Vec<TrainId>)>,
+    switches: Vec<(SwitchId, SwitchPosition)>,
+    /// Switches blocked by the dispatcher
+    #[serde(default)]
+    blocked_switches: Vec<SwitchId>,
+    /// Switches damaged by trains, until the dispatcher clears them
+    #[serde(default)]
+    failed_switches: Vec<SwitchId>,
+    /// Switches still moving to their position, with the remaining throw time in seconds
+    #[serde(default)]
+    moving_switches: Vec<(SwitchId, f32)>,
+    /// Aspect and route speed limit of each signal
+    signals: Vec<(SignalId, SignalAspect, SpeedLimit)>,
+    /// Temporary speed restrictions in km/h
+    restrictions: Vec<(BlockId, f64)>,
+}
+
 #[derive(Default, Resource)]
 pub struct BlockMap {
     blocks: SparseVec<Block>,
@@ -223,6 +301,8 @@ pub struct BlockMap {
     switches: SparseVec<Switch>,
     sections: SparseVec<Section>,
     sectioned_blocks: HashMap<BlockId, SectionId>,
+    speed_limits: HashMap<BlockId, Vec<LineSpeedLimit>>,
+    platforms: HashMap<BlockId, Platform>,
 }
 
 impl BlockMap {
@@ -256,29 +336,165 @@ impl BlockMap {
         self.sections.get(*section_id)
     }
 
-    fn process_switch_updates(&mut self, switch_updates: &mut MessageReader<SwitchUpdate>) {
+    /// Blocks or unblocks the switches as requested by the dispatcher
+    fn process_switch_blocking(&mut self, blocking: &mut MessageReader<SwitchBlocking>) {
+        for request in blocking.read() {
+            if let Some(switch) = self.switches.get_mut(request.switch_id) {
+                switch.blocked = request.blocked;
+            }
+        }
+    }
+
+    /// Throws the switches unless they are locked, returns the rejected throws
+    fn process_switch_updates(
+        &mut self,
+        switch_updates: &mut MessageReader<SwitchUpdate>,
+        station_map: &StationMap,
+        state_changes: &mut MessageWriter<SwitchStateChanged>,
+    ) -> Vec<SwitchThrowRejected> {
+        let mut rejected = Vec::new();
         for update in switch_updates.read() {
-            let switch = &mut self.switches[update.switch_id];
-            switch.position = update.position;
-            let (base, straight, side, direction) = (switch.base, switch.straight, switch.side, switch.direction);
-            let (active_leg, inactive_leg) = if update.position == SwitchPosition::Straight {
-                (straight, side)
-            } else {
-                (side, straight)
-            };
-            match direction {
-                Direction::Even => {
-                    self.blocks[base].next = Some(active_leg);
-                    self.blocks[active_leg].prev = Some(base);
-                    self.blocks[inactive_leg].prev = None;
-                }
-                Direction::Odd => {
-                    self.blocks[base].prev = Some(active_leg);
-                    self.blocks[active_leg].next = Some(base);
-                    self.blocks[inactive_leg].next = None;
-                }
-            };
+            if let Err(reason) = self.check_switch_throw(update.switch_id, update.position, station_map) {
+                rejected.push(SwitchThrowRejected {
+                    switch_id: update.switch_id,
+                    reason,
+                });
+            } else if let Some(state) = self.throw_switch(update.switch_id, update.position) {
+                state_changes.write(SwitchStateChanged {
+                    switch_id: update.switch_id,
+                    state,
+                });
+            }
         }
+        rejected
+    }
+
+    /// Checks whether the switch can be thrown to the position, returning the reason if it can't.
+    /// A switch is locked while a train occupies any of its blocks or a set route holds it in the
+    /// other position, while the dispatcher blocks it, and while it is damaged. Throwing it to the
+    /// position it is in or moving to is always allowed.
+    pub fn check_switch_throw(
+        &self,
+        switch_id: SwitchId,
+        position: SwitchPosition,
+        station_map: &StationMap,
+    ) -> Result<(), String> {
+        let Some(switch) = self.switches.get(switch_id) else {
+            return Err("does not exist".to_string());
+        };
+        if switch.position == position {
+            return Ok(());
+        }
+        if switch.failed {
+            return Err("is damaged".to_string());
+        }
+        if switch.blocked {
+            return Err("is blocked".to_string());
+        }
+        if let Some(block_id) = switch.blocks().into_iter().find(|&b| !self.tracker.is_block_free(b)) {
+            return Err(format!("is locked, block {} is occupied", block_id));
+        }
+        if let Some(route_id) = station_map.route_locking_switch(switch_id, position) {
+            return Err(format!("is locked by route {}", route_id));
+        }
+        Ok(())
+    }
+
+    /// Starts moving the switch to the position, it is detected there once its throw time has passed.
+    /// Returns the new state, or `None` if the switch is already moving to the position.
+    pub fn throw_switch(&mut self, switch_id: SwitchId, position: SwitchPosition) -> Option<SwitchState> {
+        let switch = &mut self.switches[switch_id];
+        match switch.state() {
+            SwitchState::Moving(to) if to == position => return None,
+            SwitchState::Detected(at) if at == position || switch.throw_time.is_zero() => {
+                self.set_switch_position(switch_id, position);
+            }
+            _ => {
+                switch.position = position;
+                switch.throw = Some(Timer::new(switch.throw_time, TimerMode::Once));
+                self.disconnect_switch(switch_id);
+            }
+        }
+        Some(self.switches[switch_id].state())
+    }
+
+    /// Advances the moving switches, returns the changes of the ones detected in their new position
+    pub fn tick_switches(&mut self, delta: Duration) -> Vec<SwitchStateChanged> {
+        let detected: Vec<(SwitchId, SwitchPosition)> = self
+            .switches
+            .iter_mut()
+            .filter_map(|switch| {
+                let throw = switch.throw.as_mut()?;
+                throw.tick(delta).is_finished().then_some((switch.id, switch.position))
+            })
+            .collect();
+        detected
+            .into_iter()
+            .map(|(switch_id, position)| {
+                self.set_switch_position(switch_id, position);
+                SwitchStateChanged {
+                    switch_id,
+                    state: SwitchState::Detected(position),
+                }
+            })
+            .collect()
+    }
+
+    /// Sets the switch detected in the position and connects the base block to the active leg
+    fn set_switch_position(&mut self, switch_id: SwitchId, position: SwitchPosition) {
+        let switch = &mut self.switches[switch_id];
+        switch.position = position;
+        switch.throw = None;
+        let (base, straight, side, direction) = (switch.base, switch.straight, switch.side, switch.direction);
+        let (active_leg, inactive_leg) = if position == SwitchPosition::Straight {
+            (straight, side)
+        } else {
+            (side, straight)
+        };
+        match direction {
+            Direction::Even => {
+                self.blocks[base].next = Some(active_leg);
+                self.blocks[active_leg].prev = Some(base);
+                self.blocks[inactive_leg].prev = None;
+            }
+            Direction::Odd => {
+                self.blocks[base].prev = Some(active_leg);
+                self.blocks[active_leg].next = Some(base);
+                self.blocks[inactive_leg].next = None;
+            }
+        };
+    }
+
+    /// A train trailed the switch from the leg it wasn't set for: the points are forced over to that
+    /// leg and the switch is out of use until the failure is cleared
+    pub fn damage_switch(&mut self, switch_id: SwitchId, position: SwitchPosition) {
+        self.set_switch_position(switch_id, position);
+        self.switches[switch_id].failed = true;
+    }
+
+    /// Puts a damaged switch back into use, returns whether it was damaged
+    pub fn clear_switch_failure(&mut self, switch_id: SwitchId) -> bool {
+        self.switches
+            .get_mut(switch_id)
+            .is_some_and(|switch| std::mem::replace(&mut switch.failed, false))
+    }
+
+    /// Disconnects both legs from the base block, while the points are moving
+    fn disconnect_switch(&mut self, switch_id: SwitchId) {
+        let switch = &self.switches[switch_id];
+        let (base, straight, side, direction) = (switch.base, switch.straight, switch.side, switch.direction);
+        match direction {
+            Direction::Even => {
+                self.blocks[base].next = None;
+                self.blocks[straight].prev = None;
+                self.blocks[side].prev = None;
+            }
+            Direction::Odd => {
+                self.blocks[base].prev = None;
+                self.blocks[straight].next = None;
+                self.blocks[side].next = None;
+            }
+        };
     }
 
     fn process_train_moves(
@@ -318,6 +534,10 @@ impl BlockMap {
     ) {
         let mut queue = VecDeque::from_iter(signal_updates.read().cloned());
         while let Some(update) = queue.pop_front() {
+            let limit_changed = match update.source {
+                SignalUpdateSource::Route(route_limit) => self.signals[update.signal_id].set_route_limit(route_limit),
+                _ => false,
+            };
             let signal = &self.signals[update.signal_id];
             let is_closed_manual = signal.is_closed_manual();
             let aspect = match update.source {
@@ -341,9 +561,14 @@ impl BlockMap {
                     }
                 }
                 SignalUpdateSource::Manual(aspect) => aspect,
+                SignalUpdateSource::Route(_) => SignalAspect::Unrestricting,
             };
 
-            if aspect != signal.speed_ctrl.aspect {
+            if aspect == signal.speed_ctrl.aspect {
+                if limit_changed {
+                    self.signals[update.signal_id].change_aspect(aspect);
+                }
+            } else {
                 let prev = self.lookup_signal(&signal.position, signal.direction.reverse(), signal.direction);
                 if let Some((prev, _)) = prev {
                     queue.push_back(SignalUpdate::new(
@@ -361,6 +586,234 @@ impl BlockMap {
         }
     }
 
+    fn process_speed_restrictions(&mut self, restrictions: &mut MessageReader<TemporarySpeedRestriction>) {
+        for restriction in restrictions.read() {
+            if !self.set_temporary_restriction(restriction.block_id, restriction.speed_limit) {
+                continue;
+            }
+            match restriction.speed_limit {
+                SpeedLimit::Restricted(speed_kmh) => info!(
+                    "Temporary speed restriction of {:.0} km/h imposed on block {}",
+                    speed_kmh, restriction.block_id
+                ),
+                SpeedLimit::Unrestricted => {
+                    info!("Temporary speed restriction lifted on block {}", restriction.block_id)
+                }
+            }
+        }
+    }
+
+    /// Replaces the temporary speed restriction over the whole block, returns false if there is no such block
+    fn set_temporary_restriction(&mut self, block_id: BlockId, speed_limit: SpeedLimit) -> bool {
+        let Some(block) = self.blocks.get(block_id) else {
+            return false;
+        };
+        let length_m = block.length_m;
+        let limits = self.speed_limits.entry(block_id).or_default();
+        limits.retain(|limit| !limit.temporary);
+        if let SpeedLimit::Restricted(speed_kmh) = speed_limit {
+            limits.push(LineSpeedLimit {
+                from_offset_m: 0.0,
+                to_offset_m: length_m,
+                speed_kmh,
+                temporary: true,
+            });
+        }
+        true
+    }
+
+    pub fn save_state(&self) -> BlockMapState {
+        let mut occupied: Vec<(BlockId, Vec<TrainId>)> = self
+            .tracker
+            .blocks
+            .iter()
+            .filter(|(_, trains)| !trains.is_empty())
+            .map(|(&block_id, trains)| (block_id, trains.clone()))
+            .collect();
+        occupied.sort_by_key(|&(block_id, _)| block_id);
+        let mut restrictions: Vec<(BlockId, f64)> = self
+            .speed_limits
+            .keys()
+            .filter_map(|&block_id| Some((block_id, self.temporary_speed_restriction(block_id)?)))
+            .collect();
+        restrictions.sort_by_key(|&(block_id, _)| block_id);
+        BlockMapState {
+            occupied,
+            switches: self.switches.iter().map(|s| (s.id, s.position)).collect(),
+            blocked_switches: self.switches.iter().filter(|s| s.blocked).map(|s| s.id).collect(),
+            failed_switches: self.switches.iter().filter(|s| s.failed).map(|s| s.id).collect(),
+            moving_switches: self
+                .switches
+                .iter()
+                .filter_map(|s| Some((s.id, s.throw.as_ref()?.remaining_secs())))
+                .collect(),
+            signals: self
+                .signals
+                .iter()
+                .map(|s| (s.id, s.speed_ctrl.aspect, s.route_limit))
+                .collect(),
+            restrictions,
+        }
+    }
+
+    /// Track updates announcing the blocks occupied by the trains, so that their consumers can rebuild
+    /// their state after the map is restored
+    pub fn occupation_updates<'a>(&self, trains: impl IntoIterator<Item = &'a Train>) -> Vec<TrackUpdate> {
+        trains
+            .into_iter()
+            .flat_map(|train| {
+                self.get_train_blocks(train.id)
+                    .into_iter()
+                    .flatten()
+                    .sorted()
+                    .map(move |&block_id| {
+                        TrackUpdate::from_train_move(
+                            &TrainMove::entered(block_id, train),
+                            self.get_section_by_block(block_id),
+                        )
+                    })
+            })
+            .collect()
+    }
+
+    /// Replaces the dynamic state with a saved one. No messages are sent, consumers of the track
+    /// updates have to restore their own state.
+    pub fn restore_state(&mut self, state: &BlockMapState) {
+        self.tracker = BlockTracker::default();
+        for (block_id, trains) in &state.occupied {
+            for &train_id in trains {
+                let update = TrackUpdate {
+                    block_id: *block_id,
+                    train_id,
+                    state: TrackState::Occupied,
+                    section_ctx: self.get_section_by_block(*block_id).map(Into::into),
+                    ..Default::default()
+                };
+                self.tracker.set_occupied(&update);
+            }
+        }
+        for &(switch_id, position) in &state.switches {
+            if self.switches.get(switch_id).is_some() {
+                self.set_switch_position(switch_id, position);
+            }
+        }
+        for switch in self.switches.iter_mut() {
+            switch.blocked = state.blocked_switches.contains(&switch.id);
+            switch.failed = state.failed_switches.contains(&switch.id);
+        }
+        for &(switch_id, remaining_s) in &state.moving_switches {
+            if let Some(switch) = self.switches.get_mut(switch_id) {
+                switch.throw = Some(Timer::from_seconds(remaining_s, TimerMode::Once));
+                self.disconnect_switch(switch_id);
+            }
+        }
+        for &(signal_id, aspect, route_limit) in &state.signals {
+            if let Some(signal) = self.signals.get_mut(signal_id) {
+                signal.set_route_limit(route_limit);
+                signal.change_aspect(aspect);
+            }
+        }
+        let blocks: Vec<BlockId> = self.speed_limits.keys().copied().collect();
+        for block_id in blocks {
+            self.set_temporary_restriction(block_id, SpeedLimit::Unrestricted);
+        }
+        for &(block_id, speed_kmh) in &state.restrictions {
+            self.set_temporary_restriction(block_id, SpeedLimit::Restricted(speed_kmh));
+        }
+    }
+
+    /// Takes over the dynamic state of the map built from the previous version of the level: switch
+    /// positions, speed restrictions and the occupation by the given trains. Signals keep their
+    /// initial aspects, as no route survives the rebuild.
+    pub fn carry_over(&mut self, previous: &BlockMap, trains: &HashSet<TrainId>) {
+        let mut state = previous.save_state();
+        for (_, occupants) in &mut state.occupied {
+            occupants.retain(|train_id| trains.contains(train_id));
+        }
+        state.occupied.retain(|(_, occupants)| !occupants.is_empty());
+        state.signals.clear();
+        self.restore_state(&state);
+    }
+
+    /// Temporary speed restriction imposed on the block, if any (used by the panel).
+    pub fn temporary_speed_restriction(&self, block_id: BlockId) -> Option<f64> {
+        self.speed_limits
+            .get(&block_id)?
+            .iter()
+            .find(|limit| limit.temporary)
+            .map(|limit| limit.speed_kmh)
+    }
+
+    /// Collects line speed limits overlapping the track from `start` up to `length_m` in the `direction`
+    pub fn lookup_speed_limits(&self, start: &TrackPoint, length_m: f64, direction: Direction) -> Vec<SpeedLimitAhead> {
+        let mut result = Vec::new();
+        // distance from `start` to the edge of the current block we've entered it through
+        let mut entry_m = -self.get_available_length(start, direction.reverse());
+        for point in self.walk(start, length_m, direction) {
+            let block = &self.blocks[point.block_id];
+            for (index, limit) in self.speed_limits.get(&block.id).into_iter().flatten().enumerate() {
+                let (near_m, far_m) = match direction {
+                    Direction::Even => (limit.from_offset_m, limit.to_offset_m),
+                    Direction::Odd => (block.length_m - limit.to_offset_m, block.length_m - limit.from_offset_m),
+                };
+                let (start_m, end_m) = (entry_m + near_m, entry_m + far_m);
+                if end_m >= 0.0 && start_m <= length_m {
+                    result.push(SpeedLimitAhead {
+                        block_id: block.id,
+                        index,
+                        speed_kmh: limit.speed_kmh,
+                        start_m,
+                        end_m,
+                    });
+                }
+            }
+            entry_m += block.length_m;
+        }
+        result
+    }
+
+    /// Distance from `start` to the stopping mark of the first platform of the station found within
+    /// `length_m` in the `direction`, negative if the mark in the starting block is already passed
+    pub fn lookup_platform_stop(
+        &self,
+        start: &TrackPoint,
+        length_m: f64,
+        direction: Direction,
+        station_id: StationId,
+    ) -> Option<f64> {
+        let mut block = &self.blocks[start.block_id];
+        // distance from `start` to the edge of the current block we've entered it through
+        let mut entry_m = -self.get_available_length(start, direction.reverse());
+        while entry_m <= length_m {
+            if let Some(platform) = self.platforms.get(&block.id)
+                && platform.station_id == station_id
+            {
+                let stop_m = entry_m
+                    + match direction {
+                        Direction::Even => platform.even_stop_m,
+                        Direction::Odd => block.length_m - platform.odd_stop_m,
+                    };
+                return (stop_m <= length_m).then_some(stop_m);
+            }
+            entry_m += block.length_m;
+            block = self.get_next(block.id, direction)?;
+        }
+        None
+    }
+
+    /// Station whose platform is the block, if any
+    pub fn platform_station(&self, block_id: BlockId) -> Option<StationId> {
+        self.platforms.get(&block_id).map(|platform| platform.station_id)
+    }
+
+    pub fn switch(&self, switch_id: SwitchId) -> Option<&Switch> {
+        self.switches.get(switch_id)
+    }
+
+    pub fn switches(&self) -> impl Iterator<Item = &Switch> {
+        self.switches.iter()
+    }
+
     /// Trains currently occupying the block, if any (used by the panel's hover tooltip).
     pub fn block_trains(&self, block_id: BlockId) -> Option<&Vec<TrainId>> {
         self.tracker.blocks.get(&block_id).filter(|v| !v.is_empty())
@@ -371,13 +824,20 @@ impl BlockMap {
         self.signals.get(signal_id)
     }
 
-    fn init(&self, track_updates: &mut MessageWriter<TrackUpdate>, switch_updates: &mut MessageWriter<SwitchUpdate>) {
-        switch_updates.write_batch(
-            self.switches
-                .iter()
-                .map(|switch| SwitchUpdate::new(switch.id, switch.position)),
-        );
-        track_updates.write_batch(self.blocks.iter().map(|block| TrackUpdate::block_reset(block.id)));
+    /// Updates announcing the switch positions and resetting every block, they bring the consumers
+    /// and the signals in line with a newly built map
+    pub fn initial_updates(&self) -> (Vec<TrackUpdate>, Vec<SwitchUpdate>) {
+        let switch_updates = self
+            .switches
+            .iter()
+            .map(|switch| SwitchUpdate::new(switch.id, switch.position, CommandSource::Automatic))
+            .collect();
+        let track_updates = self
+            .blocks
+            .iter()
+            .map(|block| TrackUpdate::block_reset(block.id))
+            .collect();
+        (track_updates, switch_updates)
     }
 
     /// Given a track state update, returns a collection of all signals that it affects
@@ -410,6 +870,44 @@ impl BlockMap {
             .expect("expected non-zero length")
     }
 
+    /// Block whose end the track stops at within `length_m` from `start` in the `direction`, if it
+    /// does: an open end, a switch leg the switch isn't set for, or a switch whose points are moving
+    pub fn track_end_within(&self, start: &TrackPoint, length_m: f64, direction: Direction) -> Option<BlockId> {
+        let mut block = &self.blocks[start.block_id];
+        let mut remaining_m = length_m - self.get_available_length(start, direction);
+        while remaining_m > 0.0 {
+            let Some(next) = self.get_next(block.id, direction) else {
+                return Some(block.id);
+            };
+            block = next;
+            remaining_m -= block.length_m;
+        }
+        None
+    }
+
+    /// What stops the track at the end of the block in the `direction`, when nothing is connected there
+    pub fn track_end(&self, block_id: BlockId, direction: Direction) -> TrackEnd {
+        for switch in &self.switches {
+            let facing = switch.direction == direction && switch.base == block_id;
+            let trailing = switch.direction != direction && (switch.straight == block_id || switch.side == block_id);
+            if !facing && !trailing {
+                continue;
+            }
+            if switch.throw.is_some() {
+                return TrackEnd::MovingSwitch(switch.id);
+            }
+            if trailing {
+                let leg = if switch.straight == block_id {
+                    SwitchPosition::Straight
+                } else {
+                    SwitchPosition::Side
+                };
+                return TrackEnd::SwitchSetAgainst(switch.id, leg);
+            }
+        }
+        TrackEnd::Open
+    }
+
     /// Tries to find a forward facing signal placed in the `direction` along the track,
     /// returning tuple of signal and distance to it
     pub fn lookup_signal_forward(&self, start: &TrackPoint, direction: Direction) -> Option<(&TrackSignal, f64)> {
@@ -439,6 +937,68 @@ impl BlockMap {
         None
     }
 
+    /// Blocks following the given one in the `direction` for any switch position
+    fn get_successors(&self, block_id: BlockId, direction: Direction) -> Vec<BlockId> {
+        let mut result: Vec<BlockId> = self.get_next(block_id, direction).map(|b| b.id).into_iter().collect();
+        for switch in &self.switches {
+            let candidates = if switch.direction == direction && switch.base == block_id {
+                vec![switch.straight, switch.side]
+            } else if switch.direction != direction && (switch.straight == block_id || switch.side == block_id) {
+                vec![switch.base]
+            } else {
+                continue;
+            };
+            for candidate in candidates {
+                if !result.contains(&candidate) {
+                    result.push(candidate);
+                }
+            }
+        }
+        result
+    }
+
+    /// Blocks following the given one in the `direction`, each with the switch setting needed to get there.
+    /// Only switches join an end they are placed at, whatever their position.
+    pub fn branches(&self, block_id: BlockId, direction: Direction) -> Vec<(BlockId, Option<SwitchSetting>)> {
+        let setting = |switch: &Switch, position| SwitchSetting {
+            switch_id: switch.id,
+            position,
+        };
+        let mut result = Vec::new();
+        for switch in &self.switches {
+            if switch.direction == direction && switch.base == block_id {
+                result.push((switch.straight, Some(setting(switch, SwitchPosition::Straight))));
+                result.push((switch.side, Some(setting(switch, SwitchPosition::Side))));
+            } else if switch.direction != direction && switch.straight == block_id {
+                result.push((switch.base, Some(setting(switch, SwitchPosition::Straight))));
+            } else if switch.direction != direction && switch.side == block_id {
+                result.push((switch.base, Some(setting(switch, SwitchPosition::Side))));
+            }
+        }
+        if result.is_empty() {
+            result.extend(self.get_next(block_id, direction).map(|block| (block.id, None)));
+        }
+        result
+    }
+
+    /// Whether the `to` block can be reached from the `from` block travelling in the `direction`,
+    /// with switches thrown as needed
+    pub fn is_reachable(&self, from: BlockId, to: BlockId, direction: Direction) -> bool {
+        let mut visited = HashSet::from([from]);
+        let mut queue = vec![from];
+        while let Some(block_id) = queue.pop() {
+            if block_id == to {
+                return true;
+            }
+            for next in self.get_successors(block_id, direction) {
+                if visited.insert(next) {
+                    queue.push(next);
+                }
+            }
+        }
+        false
+    }
+
     pub fn find_signal(&self, block_id: BlockId, direction: Direction) -> Option<&TrackSignal> {
         self.signals.find_signal(block_id, direction)
     }
@@ -472,12 +1032,34 @@ impl BlockMap {
             .flat_map(|sd| sd.blocks.iter().copied().map(|block_id| (block_id, sd.id)))
             .collect();
 
+        let mut speed_limits: HashMap<BlockId, Vec<LineSpeedLimit>> = HashMap::new();
+        for sld in &level.speed_limits {
+            speed_limits.entry(sld.block_id).or_default().push(sld.into());
+        }
+
+        let platforms: HashMap<BlockId, Platform> = level
+            .stations
+            .iter()
+            .flat_map(|station| {
+                station.platforms.iter().map(|pd| {
+                    let platform = Platform {
+                        station_id: station.id,
+                        even_stop_m: pd.even_stop_m,
+                        odd_stop_m: pd.odd_stop_m,
+                    };
+                    (pd.block_id, platform)
+                })
+            })
+            .collect();
+
         BlockMap {
             blocks,
             signals,
             switches,
             sections,
             sectioned_blocks,
+            speed_limits,
+            platforms,
             ..Default::default()
         }
     }
@@ -487,6 +1069,9 @@ impl BlockMap {
 pub struct Block {
     pub id: BlockId,
     pub length_m: f64,
+    /// Track gradient in per mille, positive when rising in the even direction
+    pub gradient_permille: f64,
+    pub curve_radius_m: Option<f64>,
     prev: Option<BlockId>,
     next: Option<BlockId>,
 }
@@ -496,6 +1081,8 @@ impl From<&BlockData> for Block {
         Block {
             id: value.id,
             length_m: value.length,
+            gradient_permille: value.gradient_permille,
+            curve_radius_m: value.curve_radius_m,
             ..Default::default()
         }
     }
@@ -527,7 +1114,18 @@ impl Block {
     }
 }
 
-#[derive(Default, Debug, Clone)]
+/// Where a train runs out of track, see [`BlockMap::track_end`]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum TrackEnd {
+    /// End of the line, a buffer stop
+    Open,
+    /// The points of the switch are moving
+    MovingSwitch(SwitchId),
+    /// The switch is set for its other leg than the given one
+    SwitchSetAgainst(SwitchId, SwitchPosition),
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct TrackPoint {
     pub block_id: BlockId,
     pub offset_m: f64,
@@ -621,10 +1219,18 @@ impl Plugin for MapPlugin {
         app.add_message::<TrackUpdate>()
             .add_message::<SignalUpdate>()
             .add_message::<SignalAspectChanged>()
+            .add_message::<TemporarySpeedRestriction>()
             .add_systems(OnExit(LoadingState::Loading), (setup, init).chain())
             .add_systems(
                 Update,
-                (switch_updates, train_moves, track_updates, signal_updates)
+                (
+                    switch_updates,
+                    tick_switches,
+                    speed_restrictions,
+                    train_moves,
+                    track_updates,
+                    signal_updates,
+                )
                     .chain()
                     .run_if(in_state(LoadingState::Instantiated)),
             );
@@ -642,7 +1248,9 @@ fn init(
     mut switch_updates: MessageWriter<SwitchUpdate>,
     mut next_loading_state: ResMut<NextState<LoadingState>>,
 ) {
-    block_map.init(&mut track_updates, &mut switch_updates);
+    let (initial_track_updates, initial_switch_updates) = block_map.initial_updates();
+    switch_updates.write_batch(initial_switch_updates);
+    track_updates.write_batch(initial_track_updates);
     next_loading_state.set(LoadingState::Instantiated);
 }
 
@@ -670,8 +1278,33 @@ fn signal_updates(
     block_map.process_signal_updates(&mut signal_updates, &mut aspect_changes);
 }
 
-fn switch_updates(mut block_map: ResMut<BlockMap>, mut switch_updates: MessageReader<SwitchUpdate>) {
-    block_map.process_switch_updates(&mut switch_updates);
+fn switch_updates(
+    mut block_map: ResMut<BlockMap>,
+    station_map: Res<StationMap>,
+    mut blocking: MessageReader<SwitchBlocking>,
+    mut switch_updates: MessageReader<SwitchUpdate>,
+    mut state_changes: MessageWriter<SwitchStateChanged>,
+    mut rejections: MessageWriter<SwitchThrowRejected>,
+    mut commands: Commands,
+) {
+    block_map.process_switch_blocking(&mut blocking);
+    for rejection in block_map.process_switch_updates(&mut switch_updates, &station_map, &mut state_changes) {
+        warn!("Switch {} {}", rejection.switch_id, rejection.reason);
+        commands.trigger(AudioEvent::error());
+        rejections.write(rejection);
+    }
+}
+
+fn tick_switches(
+    time: Res<Time>,
+    mut block_map: ResMut<BlockMap>,
+    mut state_changes: MessageWriter<SwitchStateChanged>,
+) {
+    state_changes.write_batch(block_map.tick_switches(time.delta()));
+}
+
+fn speed_restrictions(mut block_map: ResMut<BlockMap>, mut restrictions: MessageReader<TemporarySpeedRestriction>) {
+    block_map.process_speed_restrictions(&mut restrictions);
 }
 
 #[cfg(test)]
@@ -748,7 +1381,7 @@ mod tests {
         });
         BlockMap {
             blocks: blocks.into_iter().collect(),
-            signals: signals.flatten().into_iter().collect(),
+            signals: signals.flatten().collect(),
             ..Default::default()
         }
     }
@@ -855,6 +1488,155 @@ mod tests {
         assert!(map.lookup_signal_forward(&point, Direction::Odd).is_none());
     }
 
+    #[test]
+    fn speed_limits_even() {
+        let mut map = build_track();
+        map.speed_limits
+            .insert(1, vec![LineSpeedLimit::from(&speed_limit_data(1, 100.0, 300.0, 40.0))]);
+        map.speed_limits
+            .insert(3, vec![LineSpeedLimit::from(&speed_limit_data(3, 200.0, 400.0, 60.0))]);
+        let point = TrackPoint::new(1, 200.0);
+        let limits = map.lookup_speed_limits(&point, 1400.0, Direction::Even);
+        assert_eq!(
+            limits,
+            [SpeedLimitAhead {
+                block_id: 1,
+                index: 0,
+                speed_kmh: 40.0,
+                start_m: -100.0,
+                end_m: 100.0,
+            }]
+        );
+        let limits = map.lookup_speed_limits(&point, 1700.0, Direction::Even);
+        assert_eq!(limits.len(), 2);
+        assert_eq!(limits[1].start_m, 1500.0);
+        assert_eq!(limits[1].end_m, 1700.0);
+    }
+
+    #[test]
+    fn speed_limits_odd() {
+        let mut map = build_track();
+        map.speed_limits
+            .insert(1, vec![LineSpeedLimit::from(&speed_limit_data(1, 100.0, 300.0, 40.0))]);
+        let point = TrackPoint::new(2, 200.0);
+        let limits = map.lookup_speed_limits(&point, 2000.0, Direction::Odd);
+        assert_eq!(
+            limits,
+            [SpeedLimitAhead {
+                block_id: 1,
+                index: 0,
+                speed_kmh: 40.0,
+                start_m: 900.0,
+                end_m: 1100.0,
+            }]
+        );
+        assert!(map.lookup_speed_limits(&point, 800.0, Direction::Odd).is_empty());
+        assert!(map.lookup_speed_limits(&point, 2000.0, Direction::Even).is_empty());
+    }
+
+    #[test]
+    fn platform_stops() {
+        let mut map = build_track();
+        map.platforms.insert(
+            2,
+            Platform {
+                station_id: 1,
+                even_stop_m: 450.0,
+                odd_stop_m: 100.0,
+            },
+        );
+        let point = TrackPoint::new(1, 200.0);
+        assert_eq!(
+            map.lookup_platform_stop(&point, 2000.0, Direction::Even, 1),
+            Some(1250.0)
+        );
+        assert_eq!(map.lookup_platform_stop(&point, 1000.0, Direction::Even, 1), None);
+        assert_eq!(map.lookup_platform_stop(&point, 2000.0, Direction::Even, 2), None);
+
+        let point = TrackPoint::new(2, 50.0);
+        assert_eq!(map.lookup_platform_stop(&point, 500.0, Direction::Odd, 1), Some(-50.0));
+        let point = TrackPoint::new(3, 300.0);
+        assert_eq!(map.lookup_platform_stop(&point, 1000.0, Direction::Odd, 1), Some(700.0));
+        assert_eq!(map.lookup_platform_stop(&point, 500.0, Direction::Odd, 1), None);
+    }
+
+    #[test]
+    fn reachable_over_switches() {
+        // 1 -> 2 (switch base) -> 3 straight / 4 side, both legs merge back into 5
+        let blocks = [
+            (1, None, Some(2)),
+            (2, Some(1), Some(3)),
+            (3, Some(2), Some(5)),
+            (4, None, None),
+            (5, Some(3), None),
+        ]
+        .into_iter()
+        .map(|(id, prev, next)| Block {
+            id,
+            length_m: 100.0,
+            prev,
+            next,
+            ..Default::default()
+        });
+        let switches = [(1, 2, 3, 4, Direction::Even), (2, 5, 3, 4, Direction::Odd)]
+            .into_iter()
+            .map(|(id, base, straight, side, direction)| Switch {
+                id,
+                base,
+                straight,
+                side,
+                direction,
+                position: SwitchPosition::Straight,
+                throw_time: Duration::ZERO,
+                throw: None,
+                blocked: false,
+                failed: false,
+            });
+        let map = BlockMap {
+            blocks: blocks.collect(),
+            switches: switches.collect(),
+            ..Default::default()
+        };
+        assert!(map.is_reachable(1, 4, Direction::Even));
+        assert!(map.is_reachable(4, 5, Direction::Even));
+        assert!(map.is_reachable(5, 4, Direction::Odd));
+        assert!(map.is_reachable(4, 1, Direction::Odd));
+        assert!(!map.is_reachable(4, 1, Direction::Even));
+        assert!(!map.is_reachable(5, 1, Direction::Even));
+    }
+
+    #[test]
+    fn saved_state_is_restored() {
+        let mut map = build_track();
+        map.tracker.set_occupied(&TrackUpdate {
+            block_id: 2,
+            train_id: 7,
+            state: TrackState::Occupied,
+            ..Default::default()
+        });
+        map.signals[1].set_route_limit(SpeedLimit::Restricted(60.0));
+        map.signals[1].change_aspect(SignalAspect::Unrestricting);
+        map.set_temporary_restriction(3, SpeedLimit::Restricted(25.0));
+        let saved = toml::to_string(&map.save_state()).unwrap();
+
+        let mut restored = build_track();
+        restored.restore_state(&toml::from_str(&saved).unwrap());
+        assert_eq!(restored.block_trains(2), Some(&vec![7]));
+        assert_eq!(restored.get_train_blocks(7), Some(&HashSet::from([2])));
+        assert!(restored.signals[1].speed_ctrl.aspect == SignalAspect::Unrestricting);
+        assert_eq!(restored.signals[1].speed_ctrl.passing_kmh, SpeedLimit::Restricted(60.0));
+        assert_eq!(restored.temporary_speed_restriction(3), Some(25.0));
+    }
+
+    fn speed_limit_data(block_id: BlockId, from_offset_m: f64, to_offset_m: f64, speed_kmh: f64) -> SpeedLimitData {
+        SpeedLimitData {
+            block_id,
+            from_offset_m,
+            to_offset_m,
+            speed_kmh,
+        }
+    }
+
     #[test]
     fn affected_signals_busy() {
         let map = build_track_extended();
@@ -880,4 +1662,151 @@ mod tests {
         assert_eq!(result[1].position.block_id, 3);
         assert_eq!(result[1].direction, Direction::Odd);
     }
+
+    #[test]
+    fn switch_legs_disconnected_while_moving() {
+        let level = Level::test_fixture();
+        let mut map = BlockMap::from_level(&level);
+        assert_eq!(
+            map.throw_switch(1, SwitchPosition::Straight),
+            Some(SwitchState::Detected(SwitchPosition::Straight))
+        );
+        assert_eq!(map.get_next(4, Direction::Even).map(|b| b.id), Some(5));
+
+        assert_eq!(
+            map.throw_switch(1, SwitchPosition::Side),
+            Some(SwitchState::Moving(SwitchPosition::Side))
+        );
+        assert_eq!(map.throw_switch(1, SwitchPosition::Side), None);
+        assert!(map.get_next(4, Direction::Even).is_none());
+        assert!(map.get_next(5, Direction::Odd).is_none());
+        assert!(map.tick_switches(Duration::from_secs(3)).is_empty());
+
+        let detected = map.tick_switches(Duration::from_secs(1));
+        assert_eq!(detected.len(), 1);
+        assert_eq!(detected[0].state, SwitchState::Detected(SwitchPosition::Side));
+        assert_eq!(map.get_next(4, Direction::Even).map(|b| b.id), Some(20));
+        assert_eq!(map.get_next(20, Direction::Odd).map(|b| b.id), Some(4));
+    }
+
+    #[test]
+    fn switch_locked_under_occupied_leg() {
+        let level = Level::test_fixture();
+        let mut map = BlockMap::from_level(&level);
+        let station_map = StationMap::from_level(&level);
+        assert_eq!(map.check_switch_throw(1, SwitchPosition::Side, &station_map), Ok(()));
+        map.tracker.set_occupied(&TrackUpdate {
+            block_id: 20,
+            train_id: 1,
+            state: TrackState::Occupied,
+            ..Default::default()
+        });
+        assert_eq!(
+            map.check_switch_throw(1, SwitchPosition::Side, &station_map),
+            Err("is locked, block 20 is occupied".to_string())
+        );
+        // throwing to the current position moves nothing
+        assert_eq!(
+            map.check_switch_throw(1, SwitchPosition::Straight, &station_map),
+            Ok(())
+        );
+        assert!(map.check_switch_throw(9, SwitchPosition::Side, &station_map).is_err());
+    }
+
+    #[test]
+    fn blocked_switch_refuses_throws_and_survives_a_save() {
+        let level = Level::test_fixture();
+        let mut map = BlockMap::from_level(&level);
+        let station_map = StationMap::from_level(&level);
+        map.switches[1].blocked = true;
+        assert_eq!(
+            map.check_switch_throw(1, SwitchPosition::Side, &station_map),
+            Err("is blocked".to_string())
+        );
+        let mut restored = BlockMap::from_level(&level);
+        restored.restore_state(&map.save_state());
+        assert!(restored.switch(1).is_some_and(|switch| switch.blocked));
+    }
+
+    #[test]
+    fn track_ends_at_buffer_stops_and_moving_points() {
+        let mut map = BlockMap::from_level(&Level::test_fixture());
+        map.throw_switch(1, SwitchPosition::Straight);
+        map.throw_switch(2, SwitchPosition::Straight);
+        let start = TrackPoint::new(3, 700.0);
+        // 100 m to the end of block 3, then 10 m of switch base block 4
+        assert_eq!(map.track_end_within(&start, 109.0, Direction::Even), None);
+        assert_eq!(map.track_end_within(&start, 5000.0, Direction::Even), None);
+        assert_eq!(map.track_end_within(&start, 10_000.0, Direction::Even), Some(11));
+        assert_eq!(map.track_end(11, Direction::Even), TrackEnd::Open);
+        assert_eq!(map.track_end_within(&start, 4200.0, Direction::Odd), Some(1));
+
+        map.throw_switch(1, SwitchPosition::Side);
+        assert_eq!(map.track_end_within(&start, 109.0, Direction::Even), None);
+        assert_eq!(map.track_end_within(&start, 111.0, Direction::Even), Some(4));
+        assert_eq!(map.track_end(4, Direction::Even), TrackEnd::MovingSwitch(1));
+    }
+
+    #[test]
+    fn trailed_switch_is_forced_over_and_damaged() {
+        let level = Level::test_fixture();
+        let mut map = BlockMap::from_level(&level);
+        let station_map = StationMap::from_level(&level);
+        let start = TrackPoint {
+            block_id: 20,
+            offset_m: 0.0,
+        };
+        let length_m = map.get_available_length(&start, Direction::Odd) + 1.0;
+        assert_eq!(map.track_end_within(&start, length_m, Direction::Odd), Some(20));
+        assert_eq!(
+            map.track_end(20, Direction::Odd),
+            TrackEnd::SwitchSetAgainst(1, SwitchPosition::Side)
+        );
+
+        map.damage_switch(1, SwitchPosition::Side);
+        assert_eq!(map.get_next(20, Direction::Odd).map(|b| b.id), Some(4));
+        assert_eq!(map.track_end_within(&start, length_m, Direction::Odd), None);
+        assert_eq!(
+            map.check_switch_throw(1, SwitchPosition::Straight, &station_map),
+            Err("is damaged".to_string())
+        );
+        assert!(map.clear_switch_failure(1));
+        assert!(!map.clear_switch_failure(1));
+    }
+
+    #[test]
+    fn carry_over_keeps_switches_and_the_kept_trains() {
+        let level = Level::test_fixture();
+        let mut previous = BlockMap::from_level(&level);
+        for (block_id, train_id) in [(2, 1), (3, 1), (9, 2)] {
+            previous.tracker.set_occupied(&TrackUpdate {
+                block_id,
+                train_id,
+                state: TrackState::Occupied,
+                ..Default::default()
+            });
+        }
+        previous.throw_switch(1, SwitchPosition::Side);
+        previous.damage_switch(2, SwitchPosition::Side);
+        previous.set_temporary_restriction(6, SpeedLimit::Restricted(25.0));
+        previous.signals[50].change_aspect(SignalAspect::Unrestricting);
+
+        let mut map = BlockMap::from_level(&level);
+        map.carry_over(&previous, &HashSet::from([1]));
+        assert_eq!(map.get_train_blocks(1), Some(&HashSet::from([2, 3])));
+        assert!(map.get_train_blocks(2).is_none());
+        assert!(map.block_trains(9).is_none());
+        assert!(
+            map.switch(1)
+                .is_some_and(|switch| switch.position == SwitchPosition::Side)
+        );
+        assert!(map.switch(2).is_some_and(|switch| switch.failed));
+        // the throw goes on where it stopped
+        assert!(map.get_next(4, Direction::Even).is_none());
+        assert_eq!(map.tick_switches(Duration::from_secs(4)).len(), 1);
+        assert_eq!(map.get_next(4, Direction::Even).map(|b| b.id), Some(20));
+        assert_eq!(map.temporary_speed_restriction(6), Some(25.0));
+        let initial = BlockMap::from_level(&level).signals[50].speed_ctrl.aspect;
+        assert!(map.signals[50].speed_ctrl.aspect == initial);
+    }
 }
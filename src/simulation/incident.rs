@@ -0,0 +1,101 @@
+//! Accidents of trains running out of track: trailing a switch set against the train, entering a
+//! switch whose points are moving, and running past an open end. A trailed switch is damaged and
+//! a derailed or crashed train is failed, both stay out of use until the dispatcher clears them.
+
+use crate::assets::LoadingState;
+use crate::audio::AudioEvent;
+use crate::common::{BlockId, SwitchId, SwitchPosition, TrainId};
+use crate::simulation::block::BlockMap;
+use crate::simulation::station::{SwitchState, SwitchStateChanged};
+use crate::simulation::train::{Train, TrainDespawnRequest};
+use bevy::prelude::*;
+
+/// Fired when a train runs out of track
+#[derive(Message, Copy, Clone, PartialEq, Debug)]
+pub enum Incident {
+    /// The train trailed the switch from the leg it wasn't set for and forced the points over to
+    /// that leg. The train carries on, the switch is damaged.
+    SwitchDamaged {
+        train_id: TrainId,
+        switch_id: SwitchId,
+        position: SwitchPosition,
+    },
+    /// The train entered the switch while its points were moving and derailed
+    Derailment { train_id: TrainId, switch_id: SwitchId },
+    /// The train ran past the open end of the block
+    BufferStopCollision { train_id: TrainId, block_id: BlockId },
+}
+
+impl Incident {
+    pub fn train_id(&self) -> TrainId {
+        match *self {
+            Incident::SwitchDamaged { train_id, .. }
+            | Incident::Derailment { train_id, .. }
+            | Incident::BufferStopCollision { train_id, .. } => train_id,
+        }
+    }
+}
+
+/// Dispatcher action putting a damaged switch back into use, or removing a failed train from the track
+#[derive(Message, Copy, Clone, PartialEq, Debug)]
+pub enum ClearanceRequest {
+    Switch(SwitchId),
+    Train(TrainId),
+}
+
+pub struct IncidentPlugin;
+
+impl Plugin for IncidentPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_message::<Incident>()
+            .add_message::<ClearanceRequest>()
+            .add_systems(
+                Update,
+                (announce_incidents, clear_failures).run_if(in_state(LoadingState::Instantiated)),
+            );
+    }
+}
+
+/// The points of a trailed switch have already been forced over when the incident is reported,
+/// the switch state change is announced here
+fn announce_incidents(
+    mut incidents: MessageReader<Incident>,
+    mut state_changes: MessageWriter<SwitchStateChanged>,
+    mut commands: Commands,
+) {
+    for incident in incidents.read() {
+        if let Incident::SwitchDamaged {
+            switch_id, position, ..
+        } = *incident
+        {
+            state_changes.write(SwitchStateChanged {
+                switch_id,
+                state: SwitchState::Detected(position),
+            });
+        }
+        commands.trigger(AudioEvent::error());
+    }
+}
+
+fn clear_failures(
+    mut block_map: ResMut<BlockMap>,
+    mut requests: MessageReader<ClearanceRequest>,
+    trains: Query<&Train>,
+    mut despawn_requests: MessageWriter<TrainDespawnRequest>,
+) {
+    for request in requests.read() {
+        match *request {
+            ClearanceRequest::Switch(switch_id) => {
+                if block_map.clear_switch_failure(switch_id) {
+                    info!("Switch {} repaired", switch_id);
+                }
+            }
+            ClearanceRequest::Train(train_id) => {
+                if let Some(train) = trains.iter().find(|t| t.id == train_id && t.failed()) {
+                    info!("Failed train {} cleared from the track", train.number);
+                    despawn_requests.write(train_id.into());
+                }
+            }
+        }
+    }
+}
@@ -1,6 +1,12 @@
+pub mod ars;
 pub mod block;
+pub mod incident;
+pub mod random;
+pub mod route_generator;
 pub mod signal;
+pub mod spad;
 mod sparse_vec;
 pub mod spawner;
 pub mod station;
+pub mod timetable;
 pub mod train;
@@ -0,0 +1,139 @@
+use crate::assets::{AssetHandles, LoadingState};
+use crate::common::arg_value;
+use crate::level::Level;
+use bevy::prelude::*;
+use rand::SeedableRng;
+use rand::rngs::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+
+/// Source of all random draws in the simulation, so that runs with the same seed and inputs match
+#[derive(Resource, Deref, DerefMut)]
+pub struct SimulationRng {
+    seed: u64,
+    #[deref]
+    rng: ChaCha12Rng,
+}
+
+/// Seed of the run and the position in its random stream, as stored in a saved game
+#[derive(Serialize, Deserialize)]
+pub struct RngState {
+    seed: u64,
+    /// Number of 32-bit words drawn from the stream so far
+    word_pos: u64,
+}
+
+impl SimulationRng {
+    pub fn new(seed: u64) -> Self {
+        SimulationRng {
+            seed,
+            rng: ChaCha12Rng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The stream position is read without drawing, so saving doesn't change the running game's draws
+    pub fn save_state(&self) -> RngState {
+        RngState {
+            seed: self.seed,
+            word_pos: u64::try_from(self.rng.get_word_pos()).expect("fewer than 2^64 words drawn"),
+        }
+    }
+
+    pub fn from_state(state: &RngState) -> Self {
+        let mut rng = SimulationRng::new(state.seed);
+        rng.rng.set_word_pos(u128::from(state.word_pos));
+        rng
+    }
+}
+
+impl Default for SimulationRng {
+    fn default() -> Self {
+        SimulationRng::new(rand::random())
+    }
+}
+
+/// Reads the `--seed <n>` command line option
+pub fn seed_from_args(args: &[String]) -> Result<Option<u64>, String> {
+    arg_value(args, "--seed")?
+        .map(|seed| {
+            seed.parse()
+                .map_err(|_| "--seed must be followed by a number".to_string())
+        })
+        .transpose()
+}
+
+/// Seeds the simulation RNG when the level is instantiated. The seed given here takes precedence over
+/// the level's one, a random seed is used if neither is set.
+#[derive(Default)]
+pub struct RandomPlugin {
+    pub seed: Option<u64>,
+}
+
+#[derive(Resource)]
+struct SeedOverride(Option<u64>);
+
+impl Plugin for RandomPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SimulationRng>()
+            .insert_resource(SeedOverride(self.seed))
+            .add_systems(OnEnter(LoadingState::Instantiated), init);
+    }
+}
+
+fn init(
+    seed_override: Res<SeedOverride>,
+    handles: Res<AssetHandles>,
+    levels: Res<Assets<Level>>,
+    mut commands: Commands,
+) {
+    let level = levels.get(&handles.level).expect("level had been loaded");
+    let seed = seed_override.0.or(level.seed).unwrap_or_else(rand::random);
+    info!("Simulation seed {}", seed);
+    commands.insert_resource(SimulationRng::new(seed));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::RngExt;
+
+    #[test]
+    fn same_seed_gives_same_draws() {
+        let draws = |seed| {
+            let mut rng = SimulationRng::new(seed);
+            (0..8).map(|_| rng.random_range(0..1000)).collect::<Vec<u32>>()
+        };
+        assert_eq!(draws(42), draws(42));
+        assert_ne!(draws(42), draws(43));
+    }
+
+    #[test]
+    fn restored_state_continues_the_draws() {
+        let mut rng = SimulationRng::new(42);
+        let _: Vec<f64> = (0..5).map(|_| rng.random()).collect();
+        let _: u32 = rng.random_range(0..1000);
+        let saved = toml::to_string(&rng.save_state()).unwrap();
+        let continued: Vec<f64> = (0..8).map(|_| rng.random()).collect();
+
+        let mut restored = SimulationRng::from_state(&toml::from_str(&saved).unwrap());
+        assert_eq!(restored.seed(), 42);
+        assert_eq!((0..8).map(|_| restored.random()).collect::<Vec<f64>>(), continued);
+
+        // saving doesn't touch the running stream
+        let mut unsaved = SimulationRng::new(42);
+        let mut saving = SimulationRng::new(42);
+        let _ = saving.save_state();
+        assert_eq!(unsaved.random::<u64>(), saving.random::<u64>());
+    }
+
+    #[test]
+    fn seed_option_is_parsed() {
+        let args = |s: &str| s.split_whitespace().map(String::from).collect::<Vec<_>>();
+        assert_eq!(seed_from_args(&args("60 --seed 7")), Ok(Some(7)));
+        assert_eq!(seed_from_args(&args("60")), Ok(None));
+        assert!(seed_from_args(&args("60 --seed x")).is_err());
+    }
+}
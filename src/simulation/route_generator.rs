@@ -0,0 +1,212 @@
+//! Generation of candidate routes from the track layout, for level authors to review and merge
+//! instead of writing every route by hand.
+
+use crate::common::{BlockId, Direction, RouteId, SignalType, StationId, SwitchPosition};
+use crate::level::{Level, RouteData, SignalData, SwitchSetting};
+use crate::simulation::block::BlockMap;
+use crate::validation::LevelIssues;
+use itertools::Itertools;
+
+/// Speed limit given to routes over a switch side leg
+const DIVERGING_SPEED_KMH: f64 = 40.0;
+
+pub struct GeneratedRoute {
+    /// Station with a platform along the route, it is the one the route belongs to
+    pub station_id: Option<StationId>,
+    pub route: RouteData,
+    /// Route of the level with the same signal, target and switch settings
+    pub existing: Option<RouteId>,
+}
+
+/// Walks the track from each manual signal in its direction through every switch combination,
+/// up to the next signal facing the same way. Routes that run into an open end are not generated.
+/// New routes get IDs following the highest route ID of the level. The level must pass validation,
+/// its problems are returned otherwise.
+pub fn generate_routes(level: &Level) -> Result<Vec<GeneratedRoute>, LevelIssues> {
+    level.validate()?;
+    let block_map = BlockMap::from_level(level);
+    let mut next_id = level
+        .stations
+        .iter()
+        .flat_map(|s| &s.routes)
+        .map(|r| r.id)
+        .max()
+        .unwrap_or(0)
+        + 1;
+    let mut generated = Vec::new();
+    for signal in level.signals.iter().filter(|s| s.signal_type == SignalType::Manual) {
+        let mut paths = Vec::new();
+        walk(
+            &block_map,
+            signal.direction,
+            signal.block_id,
+            &Path::default(),
+            &mut paths,
+        );
+        for path in paths {
+            let mut route = route_along(level, signal, &path);
+            let existing = find_existing(level, &route);
+            route.id = match existing {
+                Some(id) => id,
+                None => {
+                    next_id += 1;
+                    next_id - 1
+                }
+            };
+            generated.push(GeneratedRoute {
+                station_id: station_along(level, signal.block_id, &path.blocks),
+                route,
+                existing,
+            });
+        }
+    }
+    Ok(generated)
+}
+
+/// Adds the generated routes missing from the level to their stations, returns how many were added
+pub fn merge_routes(level: &mut Level, routes: &[GeneratedRoute]) -> usize {
+    let mut added = 0;
+    for generated in routes.iter().filter(|g| g.existing.is_none()) {
+        let station = level.stations.iter_mut().find(|s| Some(s.id) == generated.station_id);
+        if let Some(station) = station {
+            station.routes.push(generated.route.clone());
+            added += 1;
+        }
+    }
+    added
+}
+
+#[derive(Default, Clone)]
+struct Path {
+    /// Blocks after the signal's block, the last one holds the next signal
+    blocks: Vec<BlockId>,
+    switches: Vec<SwitchSetting>,
+}
+
+fn walk(block_map: &BlockMap, direction: Direction, block_id: BlockId, path: &Path, paths: &mut Vec<Path>) {
+    for (next, setting) in block_map.branches(block_id, direction) {
+        if path.blocks.contains(&next) {
+            continue;
+        }
+        let mut branch = path.clone();
+        branch.blocks.push(next);
+        branch.switches.extend(setting);
+        if block_map.find_signal(next, direction).is_some() {
+            paths.push(branch);
+        } else {
+            walk(block_map, direction, next, &branch, paths);
+        }
+    }
+}
+
+fn route_along(level: &Level, signal: &SignalData, path: &Path) -> RouteData {
+    let (&target, passed) = path.blocks.split_last().expect("a path holds at least its target");
+    // Sections lying entirely on the way, in the order they are entered
+    let sections = level
+        .sections
+        .iter()
+        .filter(|section| section.blocks.iter().all(|id| passed.contains(id)))
+        .sorted_by_key(|section| {
+            section
+                .blocks
+                .iter()
+                .filter_map(|id| passed.iter().position(|p| p == id))
+                .min()
+        })
+        .map(|section| section.id)
+        .collect();
+    let diverging = path.switches.iter().any(|s| s.position == SwitchPosition::Side);
+    RouteData {
+        id: 0,
+        signal: signal.id,
+        sections,
+        target,
+        switches: path.switches.clone(),
+        speed_kmh: diverging.then_some(DIVERGING_SPEED_KMH),
+    }
+}
+
+fn station_along(level: &Level, signal_block: BlockId, blocks: &[BlockId]) -> Option<StationId> {
+    level
+        .stations
+        .iter()
+        .find(|station| {
+            station
+                .platforms
+                .iter()
+                .any(|p| p.block_id == signal_block || blocks.contains(&p.block_id))
+        })
+        .map(|station| station.id)
+}
+
+fn find_existing(level: &Level, route: &RouteData) -> Option<RouteId> {
+    let settings = |r: &RouteData| {
+        r.switches
+            .iter()
+            .map(|s| (s.switch_id, s.position == SwitchPosition::Straight))
+            .sorted()
+            .collect::<Vec<_>>()
+    };
+    level
+        .stations
+        .iter()
+        .flat_map(|s| &s.routes)
+        .find(|r| r.signal == route.signal && r.target == route.target && settings(r) == settings(route))
+        .map(|r| r.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_the_hand_written_routes() {
+        let level = Level::test_fixture();
+        let generated = generate_routes(&level).unwrap();
+        let routes: Vec<&RouteData> = level.stations.iter().flat_map(|s| &s.routes).collect();
+        assert_eq!(generated.len(), routes.len());
+        for route in routes {
+            let candidate = generated
+                .iter()
+                .find(|g| g.existing == Some(route.id))
+                .unwrap_or_else(|| panic!("route {} is not generated", route.id));
+            assert_eq!(candidate.station_id, Some(1));
+            assert_eq!(
+                candidate.route.sections, route.sections,
+                "sections of route {}",
+                route.id
+            );
+            assert_eq!(
+                candidate.route.speed_kmh, route.speed_kmh,
+                "speed of route {}",
+                route.id
+            );
+        }
+    }
+
+    #[test]
+    fn merges_missing_routes_into_their_station() {
+        let mut level = Level::test_fixture();
+        level.stations[0].routes.retain(|r| r.signal != 50);
+        let generated = generate_routes(&level).unwrap();
+        assert_eq!(merge_routes(&mut level, &generated), 2);
+        assert_eq!(level.validate(), Ok(()));
+        assert_eq!(
+            generate_routes(&level)
+                .unwrap()
+                .iter()
+                .filter(|g| g.existing.is_none())
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn invalid_level_is_reported() {
+        let mut level = Level::test_fixture();
+        // a signal on a missing block would panic when building the block map
+        level.signals[0].block_id = 99;
+        let issues = level.validate().expect_err("the signal block is missing");
+        assert_eq!(generate_routes(&level).err(), Some(issues));
+    }
+}
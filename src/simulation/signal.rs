@@ -3,6 +3,7 @@ use crate::level::SignalData;
 use crate::simulation::block::TrackPoint;
 use crate::simulation::sparse_vec::{Chunkable, SparseVec};
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt::Display;
 use std::ops::{Index, IndexMut};
@@ -64,8 +65,9 @@ impl FromIterator<TrackSignal> for SignalMap {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Default, Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum SpeedLimit {
+    #[default]
     Unrestricted,
     Restricted(f64),
 }
@@ -79,6 +81,12 @@ impl Display for SpeedLimit {
     }
 }
 
+impl From<Option<f64>> for SpeedLimit {
+    fn from(value: Option<f64>) -> Self {
+        value.map_or(SpeedLimit::Unrestricted, SpeedLimit::Restricted)
+    }
+}
+
 impl SpeedLimit {
     pub fn apply_limit(&self, limit_kmh: f64) -> f64 {
         match self {
@@ -88,7 +96,7 @@ impl SpeedLimit {
     }
 }
 
-#[derive(Default, Copy, Clone, PartialEq)]
+#[derive(Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum SignalAspect {
     /// Signal does not restrict the train's speed
     Unrestricting,
@@ -146,6 +154,16 @@ impl SpeedControl {
         }
     }
 
+    /// Same as [`SpeedControl::default_for_aspect`], but an open signal is passed at the route's
+    /// speed limit instead of the aspect's default one, if the route has it.
+    pub fn for_route(aspect: SignalAspect, route_limit: SpeedLimit) -> SpeedControl {
+        let mut speed_ctrl = Self::default_for_aspect(aspect);
+        if aspect != SignalAspect::Forbidding && matches!(route_limit, SpeedLimit::Restricted(_)) {
+            speed_ctrl.passing_kmh = route_limit;
+        }
+        speed_ctrl
+    }
+
     pub fn apply_limit(&self, limit_kmh: f64) -> Speeds {
         Speeds {
             passing_kmh: self.passing_kmh.apply_limit(limit_kmh),
@@ -162,6 +180,7 @@ pub struct TrackSignal {
     pub name: String,
     pub speed_ctrl: SpeedControl,
     pub signal_type: SignalType,
+    pub route_limit: SpeedLimit,
 }
 
 impl From<&SignalData> for TrackSignal {
@@ -188,10 +207,37 @@ impl Chunkable for TrackSignal {
 
 impl TrackSignal {
     pub fn change_aspect(&mut self, aspect: SignalAspect) {
-        self.speed_ctrl = SpeedControl::default_for_aspect(aspect);
+        self.speed_ctrl = SpeedControl::for_route(aspect, self.route_limit);
+    }
+
+    /// Sets the speed limit of the route the signal is opened for, returns true if it changed
+    pub fn set_route_limit(&mut self, route_limit: SpeedLimit) -> bool {
+        let changed = self.route_limit != route_limit;
+        self.route_limit = route_limit;
+        changed
     }
 
     pub fn is_closed_manual(&self) -> bool {
         self.signal_type == SignalType::Manual && self.speed_ctrl.aspect == SignalAspect::Forbidding
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_limit_replaces_the_aspect_speed() {
+        let route_limit = SpeedLimit::Restricted(80.0);
+        let restricting = SpeedControl::for_route(SignalAspect::Restricting, route_limit);
+        assert_eq!(restricting.passing_kmh, SpeedLimit::Restricted(80.0));
+        let default = SpeedControl::for_route(SignalAspect::Restricting, SpeedLimit::Unrestricted);
+        assert_eq!(default.passing_kmh, SpeedLimit::Restricted(40.0));
+        let unrestricting = SpeedControl::for_route(SignalAspect::Unrestricting, route_limit);
+        assert_eq!(unrestricting.passing_kmh, SpeedLimit::Restricted(80.0));
+        let slow = SpeedControl::for_route(SignalAspect::Restricting, SpeedLimit::Restricted(25.0));
+        assert_eq!(slow.passing_kmh, SpeedLimit::Restricted(25.0));
+        let closed = SpeedControl::for_route(SignalAspect::Forbidding, route_limit);
+        assert_eq!(closed.passing_kmh, SpeedLimit::Restricted(0.0));
+    }
+}
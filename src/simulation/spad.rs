@@ -0,0 +1,191 @@
+//! Signals passed at danger (SPAD): the front of a train passing a closed signal, or passing a
+//! signal faster than it allows. The train protection trips and brakes the train to a stand, the
+//! SPAD is kept in the register and the train is held until the dispatcher acknowledges it.
+
+use crate::assets::LoadingState;
+use crate::audio::AudioEvent;
+use crate::common::{SignalId, TrainId};
+use crate::simulation::train::Train;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Fired when the front of a train passes a signal showing [`SignalAspect::Forbidding`] or faster
+/// than its passing speed
+///
+/// [`SignalAspect::Forbidding`]: crate::simulation::signal::SignalAspect::Forbidding
+#[derive(Message, Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct SignalPassedAtDanger {
+    pub train_id: TrainId,
+    pub signal_id: SignalId,
+    /// Speed above the allowed passing speed, the whole speed when the signal was closed
+    pub overspeed_kmh: f64,
+}
+
+/// Dispatcher action acknowledging the SPADs at the signal, their trains may move on
+#[derive(Message, Copy, Clone, PartialEq, Debug)]
+pub struct SpadAcknowledgement {
+    pub signal_id: SignalId,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct SpadRecord {
+    pub spad: SignalPassedAtDanger,
+    pub acknowledged: bool,
+}
+
+/// Every SPAD of the game in the order they happened
+#[derive(Resource, Default)]
+pub struct SpadRegister {
+    records: Vec<SpadRecord>,
+}
+
+/// SPAD register as stored in a saved game
+#[derive(Default, Serialize, Deserialize)]
+pub struct SpadState {
+    records: Vec<SpadRecord>,
+}
+
+impl SpadRegister {
+    pub fn records(&self) -> &[SpadRecord] {
+        &self.records
+    }
+
+    pub fn unacknowledged(&self) -> impl Iterator<Item = &SignalPassedAtDanger> {
+        self.records.iter().filter(|r| !r.acknowledged).map(|r| &r.spad)
+    }
+
+    pub fn for_train(&self, train_id: TrainId) -> impl Iterator<Item = &SignalPassedAtDanger> {
+        self.records
+            .iter()
+            .map(|r| &r.spad)
+            .filter(move |spad| spad.train_id == train_id)
+    }
+
+    /// The signal has a SPAD that isn't acknowledged yet
+    pub fn is_marked(&self, signal_id: SignalId) -> bool {
+        self.unacknowledged().any(|spad| spad.signal_id == signal_id)
+    }
+
+    fn record(&mut self, spad: SignalPassedAtDanger) {
+        self.records.push(SpadRecord {
+            spad,
+            acknowledged: false,
+        });
+    }
+
+    /// Acknowledges the SPADs at the signal, returning the trains that committed them
+    fn acknowledge(&mut self, signal_id: SignalId) -> Vec<TrainId> {
+        self.records
+            .iter_mut()
+            .filter(|r| !r.acknowledged && r.spad.signal_id == signal_id)
+            .map(|r| {
+                r.acknowledged = true;
+                r.spad.train_id
+            })
+            .collect()
+    }
+
+    /// Forgets the SPADs that `keep` rejects
+    pub fn retain(&mut self, mut keep: impl FnMut(&SignalPassedAtDanger) -> bool) {
+        self.records.retain(|r| keep(&r.spad));
+    }
+
+    pub fn save_state(&self) -> SpadState {
+        SpadState {
+            records: self.records.clone(),
+        }
+    }
+
+    pub fn restore_state(&mut self, state: &SpadState) {
+        self.records = state.records.clone();
+    }
+}
+
+pub struct SpadPlugin;
+
+impl Plugin for SpadPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpadRegister>()
+            .add_message::<SignalPassedAtDanger>()
+            .add_message::<SpadAcknowledgement>()
+            .add_systems(
+                Update,
+                (register_spads, acknowledge_spads)
+                    .chain()
+                    .run_if(in_state(LoadingState::Instantiated)),
+            );
+    }
+}
+
+fn register_spads(
+    mut register: ResMut<SpadRegister>,
+    mut spads: MessageReader<SignalPassedAtDanger>,
+    mut commands: Commands,
+) {
+    for spad in spads.read() {
+        register.record(*spad);
+        commands.trigger(AudioEvent::error());
+    }
+}
+
+/// A train is released once every SPAD it committed is acknowledged
+fn acknowledge_spads(
+    mut register: ResMut<SpadRegister>,
+    mut acknowledgements: MessageReader<SpadAcknowledgement>,
+    mut trains: Query<&mut Train>,
+) {
+    for acknowledgement in acknowledgements.read() {
+        let train_ids = register.acknowledge(acknowledgement.signal_id);
+        if train_ids.is_empty() {
+            continue;
+        }
+        info!("SPAD at signal {} acknowledged", acknowledgement.signal_id);
+        for mut train in &mut trains {
+            let pending = register.unacknowledged().any(|spad| spad.train_id == train.id);
+            if train_ids.contains(&train.id) && !pending {
+                train.release_train_protection();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spad(train_id: TrainId, signal_id: SignalId) -> SignalPassedAtDanger {
+        SignalPassedAtDanger {
+            train_id,
+            signal_id,
+            overspeed_kmh: 12.0,
+        }
+    }
+
+    #[test]
+    fn acknowledged_spads_stay_in_the_register() {
+        let mut register = SpadRegister::default();
+        register.record(spad(1, 10));
+        register.record(spad(2, 10));
+        register.record(spad(1, 11));
+        assert!(register.is_marked(10));
+
+        assert_eq!(register.acknowledge(10), vec![1, 2]);
+        assert!(register.acknowledge(10).is_empty());
+        assert!(!register.is_marked(10));
+        assert!(register.is_marked(11));
+        assert_eq!(register.unacknowledged().collect::<Vec<_>>(), vec![&spad(1, 11)]);
+        assert_eq!(register.for_train(1).count(), 2);
+        assert_eq!(register.records().len(), 3);
+    }
+
+    #[test]
+    fn forgotten_spads_leave_the_register() {
+        let mut register = SpadRegister::default();
+        register.record(spad(1, 10));
+        register.record(spad(2, 11));
+        register.retain(|spad| spad.train_id != 2);
+        assert!(register.is_marked(10));
+        assert!(!register.is_marked(11));
+        assert_eq!(register.records().len(), 1);
+    }
+}
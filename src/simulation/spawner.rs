@@ -1,28 +1,41 @@
 use crate::assets::{AssetHandles, LoadingState};
 use crate::audio::AudioEvent;
-use crate::common::{BlockId, Direction, TrainId};
+use crate::common::{BlockId, CommandSource, Direction, TrainId};
 use crate::level::{Level, SpawnerKind};
-use crate::simulation::block::{BlockMap, SignalUpdate, SignalUpdateSource, TrackPoint};
+use crate::rolling_stock::RollingStock;
+use crate::simulation::block::{Block, BlockMap, SignalUpdate, SignalUpdateSource, TrackPoint};
+use crate::simulation::random::SimulationRng;
 use crate::simulation::signal::SignalAspect;
 use crate::simulation::train::{
-    RailVehicle, TrainDespawnRequest, TrainMove, TrainMoveKind, TrainSpawnRequest, get_random_train_number,
+    CallingStation, RailVehicle, TrainDespawnRequest, TrainMove, TrainMoveKind, TrainSpawnRequest,
+    get_random_train_number,
 };
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 
 const SPAWNER_POINT_OFFSET: f64 = 400.0;
+/// A requested train that hasn't entered its spawner by then is given up on, freeing the spawner
+const SPAWN_REQUEST_TIMEOUT_S: f32 = 10.0;
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub enum SpawnTrainType {
-    Cargo,
-    Passenger,
-    Locomotive,
-}
+/// Reference to a named consist in the rolling stock catalog
+#[derive(Clone, Debug, PartialEq, Eq, Deref, Serialize, Deserialize)]
+pub struct SpawnTrainType(pub String);
 
 #[derive(Event)]
 pub struct SpawnRequest {
     pub block_id: BlockId,
     pub train_type: SpawnTrainType,
+    /// Train number, a random one matching the direction is picked if not given
+    pub number: Option<String>,
+    /// Despawner block the train is heading to
+    pub destination: Option<BlockId>,
+    pub priority: u8,
+    pub calling_at: Vec<CallingStation>,
+    /// Planned exit time on the shift clock, for timetabled services
+    pub exit_time_s: Option<f64>,
+    pub source: CommandSource,
 }
 
 struct Occupation {
@@ -64,11 +77,13 @@ struct Spawner {
     speed_kmh: f64,
     spawn_point: TrackPoint,
     train: Option<Occupation>,
+    /// A train has been requested but has not entered the spawner yet, the request times out
+    request: Option<Timer>,
 }
 
 impl Spawner {
     fn is_busy(&self) -> bool {
-        self.train.is_some()
+        self.train.is_some() || self.request.is_some()
     }
 }
 
@@ -82,6 +97,115 @@ struct Despawner {
 #[derive(Resource, Deref, DerefMut, Default)]
 struct SpawnerMapper(HashMap<BlockId, Entity>);
 
+#[derive(Serialize, Deserialize)]
+struct SavedSpawner {
+    block_id: BlockId,
+    /// Train in the spawner and the number of the spawner's blocks it occupies
+    train: Option<(TrainId, u8)>,
+    /// Train about to leave through the despawner
+    leaving: Option<TrainId>,
+}
+
+/// Occupation of the spawners and despawners, as stored in a saved game
+#[derive(Serialize, Deserialize)]
+pub struct SpawnersState {
+    spawners: Vec<SavedSpawner>,
+}
+
+pub fn save_spawners(world: &mut World) -> SpawnersState {
+    let mut spawners: Vec<SavedSpawner> = world
+        .query::<(Option<&Spawner>, Option<&Despawner>)>()
+        .iter(world)
+        .filter_map(|(spawner, despawner)| {
+            Some(SavedSpawner {
+                block_id: spawner.map(|s| s.block_id).or(despawner.map(|d| d.block_id))?,
+                train: spawner.and_then(|s| s.train.as_ref().map(|o| (o.train_id, o.num_blocks))),
+                leaving: despawner.and_then(|d| d.train),
+            })
+        })
+        .collect();
+    spawners.sort_by_key(|s| s.block_id);
+    SpawnersState { spawners }
+}
+
+impl SpawnersState {
+    /// Forgets the occupation by trains other than the given ones
+    pub fn retain_trains(&mut self, trains: &HashSet<TrainId>) {
+        for saved in &mut self.spawners {
+            saved.train = saved.train.filter(|(train_id, _)| trains.contains(train_id));
+            saved.leaving = saved.leaving.filter(|train_id| trains.contains(train_id));
+        }
+    }
+}
+
+/// Replaces the spawners and despawners with ones built from the current level and block map,
+/// all of them empty
+pub fn rebuild_spawners(world: &mut World) {
+    let entities: Vec<Entity> = world
+        .query_filtered::<Entity, Or<(With<Spawner>, With<Despawner>)>>()
+        .iter(world)
+        .collect();
+    for entity in entities {
+        world.despawn(entity);
+    }
+    world.resource_mut::<SpawnerMapper>().clear();
+    if let Err(e) = world.run_system_cached(init) {
+        warn!("Failed to rebuild the spawners: {}", e);
+    }
+}
+
+/// Restores the occupation, pending spawn requests are dropped with the rest of the messages
+pub fn restore_spawners(world: &mut World, state: &SpawnersState) {
+    let saved: HashMap<BlockId, &SavedSpawner> = state.spawners.iter().map(|s| (s.block_id, s)).collect();
+    let mut query = world.query::<(Option<&mut Spawner>, Option<&mut Despawner>)>();
+    for (spawner, despawner) in query.iter_mut(world) {
+        if let Some(mut spawner) = spawner {
+            let saved = saved.get(&spawner.block_id);
+            spawner.train = saved
+                .and_then(|s| s.train)
+                .map(|(train_id, num_blocks)| Occupation { train_id, num_blocks });
+            spawner.request = None;
+        }
+        if let Some(mut despawner) = despawner {
+            despawner.train = saved.get(&despawner.block_id).and_then(|s| s.leaving);
+        }
+    }
+}
+
+/// Read-only access to the spawners for other simulation systems
+#[derive(SystemParam)]
+pub struct Spawners<'w, 's> {
+    mapper: Res<'w, SpawnerMapper>,
+    query: Query<'w, 's, &'static Spawner>,
+}
+
+impl Spawners<'_, '_> {
+    /// Whether the spawner at the given block is occupied, `None` if there is no spawner at the block
+    pub fn is_busy(&self, block_id: BlockId) -> Option<bool> {
+        let entity = self.mapper.get(&block_id)?;
+        self.query
+            .get(*entity)
+            .ok()
+            .filter(|spawner| spawner.block_id == block_id)
+            .map(Spawner::is_busy)
+    }
+}
+
+/// The rolling stock catalog loaded with the level
+#[derive(SystemParam)]
+struct Catalog<'w> {
+    handles: Res<'w, AssetHandles>,
+    catalogs: Res<'w, Assets<RollingStock>>,
+}
+
+impl Catalog<'_> {
+    fn get(&self) -> &RollingStock {
+        self.catalogs
+            .get(&self.handles.rolling_stock)
+            .expect("rolling stock had been loaded")
+    }
+}
+
 pub struct SpawnerPlugin;
 
 impl Plugin for SpawnerPlugin {
@@ -91,7 +215,8 @@ impl Plugin for SpawnerPlugin {
             .add_systems(OnEnter(LoadingState::Instantiated), init)
             .add_systems(
                 Update,
-                (update_spawners, update_despawners).run_if(in_state(LoadingState::Instantiated)),
+                (update_spawners, update_despawners, expire_spawn_requests)
+                    .run_if(in_state(LoadingState::Instantiated)),
             );
     }
 }
@@ -106,57 +231,68 @@ fn init(
 ) {
     let level = levels.get(&handles.level).expect("level had been loaded");
     for data in &level.spawners {
-        let block = block_map.get_block(data.block_id).expect("invalid block ID");
-        if let Some(end_direction) = block.get_end_direction() {
-            let direction = end_direction.reverse();
-            let mut entity = commands.spawn(());
-            spawn_mapper.insert(block.id, entity.id());
-
-            if matches!(data.kind, SpawnerKind::Spawn | SpawnerKind::Both) {
-                let spawn_offset = match direction {
-                    Direction::Even => block.length_m - SPAWNER_POINT_OFFSET,
-                    Direction::Odd => SPAWNER_POINT_OFFSET,
-                };
-                entity.insert(Spawner {
-                    block_id: block.id,
-                    direction,
-                    speed_kmh: data.speed_kmh,
-                    spawn_point: TrackPoint::new(block.id, spawn_offset),
-                    train: None,
-                });
-                // Add approach blocks so we can detect changes there as well
-                if data.approach_len > 0 {
-                    block_map
-                        .walk(&block.middle(), f64::INFINITY, direction)
-                        .take(data.approach_len as usize + 1)
-                        .for_each(|point| {
-                            spawn_mapper.insert(point.block_id, entity.id());
-                        });
-                }
+        let (block, direction, adjacent_block) = match spawner_track(&block_map, data.block_id) {
+            Ok(track) => track,
+            Err(reason) => {
+                error!("Spawner on block {} is left out: {}", data.block_id, reason);
+                continue;
             }
+        };
+        let mut entity = commands.spawn(());
+        spawn_mapper.insert(block.id, entity.id());
 
-            if matches!(data.kind, SpawnerKind::Despawn | SpawnerKind::Both) {
-                let adjacent_block = block_map.get_next(block.id, direction).expect("invalid block ID");
-                entity.insert(Despawner {
-                    block_id: block.id,
-                    adjacent_block_id: adjacent_block.id,
-                    train: None,
-                });
-                spawn_mapper.insert(adjacent_block.id, entity.id());
-                // If the end signal is present, open it permanently
-                if let Some(signal) = block_map.find_signal(block.id, end_direction) {
-                    signal_updates.write(SignalUpdate::new(
-                        signal.id,
-                        SignalUpdateSource::SignalPropagation(SignalAspect::Unrestricting),
-                    ));
-                }
+        if matches!(data.kind, SpawnerKind::Spawn | SpawnerKind::Both) {
+            let spawn_offset = match direction {
+                Direction::Even => block.length_m - SPAWNER_POINT_OFFSET,
+                Direction::Odd => SPAWNER_POINT_OFFSET,
+            };
+            entity.insert(Spawner {
+                block_id: block.id,
+                direction,
+                speed_kmh: data.speed_kmh,
+                spawn_point: TrackPoint::new(block.id, spawn_offset),
+                train: None,
+                request: None,
+            });
+            // Add approach blocks so we can detect changes there as well
+            if data.approach_len > 0 {
+                block_map
+                    .walk(&block.middle(), f64::INFINITY, direction)
+                    .take(data.approach_len as usize + 1)
+                    .for_each(|point| {
+                        spawn_mapper.insert(point.block_id, entity.id());
+                    });
+            }
+        }
+
+        if matches!(data.kind, SpawnerKind::Despawn | SpawnerKind::Both) {
+            entity.insert(Despawner {
+                block_id: block.id,
+                adjacent_block_id: adjacent_block.id,
+                train: None,
+            });
+            spawn_mapper.insert(adjacent_block.id, entity.id());
+            // If the end signal is present, open it permanently
+            if let Some(signal) = block_map.find_signal(block.id, direction.reverse()) {
+                signal_updates.write(SignalUpdate::new(
+                    signal.id,
+                    SignalUpdateSource::SignalPropagation(SignalAspect::Unrestricting),
+                ));
             }
-        } else {
-            panic!("spawner block {} has no open end", block.id)
         }
     }
 }
 
+/// The spawner's block, the direction trains run from it into the area and the block they enter
+fn spawner_track(block_map: &BlockMap, block_id: BlockId) -> Result<(&Block, Direction, &Block), &'static str> {
+    let block = block_map.get_block(block_id).ok_or("the block does not exist")?;
+    let direction = block.get_end_direction().ok_or("the block has no open end")?.reverse();
+    let adjacent_block = block_map
+        .get_next(block_id, direction)
+        .ok_or("the block is not joined to any other block")?;
+    Ok((block, direction, adjacent_block))
+}
+
 fn update_spawners(
     spawner_mapper: Res<SpawnerMapper>,
     mut query: Query<&mut Spawner>,
@@ -169,6 +305,7 @@ fn update_spawners(
             let spawner_id = spawner.block_id;
             match mv.kind {
                 TrainMoveKind::Entered => {
+                    spawner.request = None;
                     if let Some(existing) = spawner.train.as_mut() {
                         if existing.occupy(mv.train_id).is_err() {
                             warn!(
@@ -199,6 +336,18 @@ fn update_spawners(
     }
 }
 
+/// Frees the spawners whose requested train never entered them
+fn expire_spawn_requests(time: Res<Time>, mut query: Query<&mut Spawner>) {
+    for mut spawner in &mut query {
+        if let Some(request) = spawner.request.as_mut()
+            && request.tick(time.delta()).is_finished()
+        {
+            warn!("Requested train never entered spawner {}", spawner.block_id);
+            spawner.request = None;
+        }
+    }
+}
+
 fn update_despawners(
     spawner_mapper: Res<SpawnerMapper>,
     mut query: Query<&mut Despawner>,
@@ -232,12 +381,14 @@ fn update_despawners(
 fn spawn_requests(
     request: On<SpawnRequest>,
     spawner_mapper: Res<SpawnerMapper>,
-    query: Query<&Spawner>,
+    mut query: Query<&mut Spawner>,
+    catalog: Catalog,
+    mut rng: ResMut<SimulationRng>,
     mut spawn_requests: MessageWriter<TrainSpawnRequest>,
     mut commands: Commands,
 ) {
     if let Some(entity) = spawner_mapper.get(&request.block_id) {
-        let spawner = query.get(*entity).expect("invalid spawner entity");
+        let mut spawner = query.get_mut(*entity).expect("invalid spawner entity");
 
         if spawner.is_busy() {
             warn!("Spawner {} is currently occupied", spawner.block_id);
@@ -245,31 +396,93 @@ fn spawn_requests(
             return;
         }
 
-        let mut vehicles = Vec::new();
-        match request.train_type {
-            SpawnTrainType::Cargo => {
-                vehicles.extend([RailVehicle::new_locomotive(138_000.0, 18.15, 2250.0, 375.0); 2]);
-                vehicles.extend([RailVehicle::new_car(24_000.0, 15.0, 70_000.0); 60]);
-            }
-            SpawnTrainType::Passenger => {
-                vehicles.push(RailVehicle::new_locomotive(80_000.0, 16.0, 2942.0, 300.0));
-                vehicles.extend([RailVehicle::new_car(40_000.0, 24.0, 5_000.0); 25]);
-            }
-            SpawnTrainType::Locomotive => {
-                vehicles.push(RailVehicle::new_locomotive(138_000.0, 18.15, 2250.0, 375.0));
-                vehicles.push(RailVehicle::new_locomotive(138_000.0, 18.15, 2250.0, 375.0));
-            }
-        }
+        let catalog = catalog.get();
+        let Some(consist) = catalog.get_consist(&request.train_type) else {
+            warn!("Unknown consist '{}'", *request.train_type);
+            commands.trigger(AudioEvent::error());
+            return;
+        };
+        let vehicles = consist
+            .vehicles
+            .iter()
+            .flat_map(|entry| {
+                let data = catalog
+                    .get_vehicle(&entry.vehicle)
+                    .expect("consists are validated on load");
+                std::iter::repeat_n(RailVehicle::from(data), entry.count)
+            })
+            .collect();
 
         spawn_requests.write(TrainSpawnRequest {
-            number: get_random_train_number(spawner.direction),
-            top_speed_kmh: 80.0,
+            number: request
+                .number
+                .clone()
+                .unwrap_or_else(|| get_random_train_number(spawner.direction, &mut rng)),
+            top_speed_kmh: consist.top_speed_kmh,
             actual_speed_kmh: spawner.speed_kmh,
             position: spawner.spawn_point.clone(),
             direction: spawner.direction,
             vehicles,
+            destination: request.destination,
+            priority: request.priority,
+            calling_at: request.calling_at.clone(),
+            exit_time_s: request.exit_time_s,
         });
+        spawner.request = Some(Timer::from_seconds(SPAWN_REQUEST_TIMEOUT_S, TimerMode::Once));
 
         commands.trigger(AudioEvent::beep());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn occupation_by_removed_trains_is_forgotten() {
+        let mut state = SpawnersState {
+            spawners: vec![
+                SavedSpawner {
+                    block_id: 1,
+                    train: Some((1, 2)),
+                    leaving: Some(2),
+                },
+                SavedSpawner {
+                    block_id: 11,
+                    train: Some((3, 1)),
+                    leaving: Some(1),
+                },
+            ],
+        };
+        state.retain_trains(&HashSet::from([1]));
+        let occupation: Vec<_> = state
+            .spawners
+            .iter()
+            .map(|s| (s.block_id, s.train, s.leaving))
+            .collect();
+        assert_eq!(occupation, vec![(1, Some((1, 2)), None), (11, None, Some(1))]);
+    }
+
+    #[test]
+    fn spawners_need_an_open_end_and_a_neighbour() {
+        let level: Level = toml::from_str(
+            r##"
+            blocks = [[1, 100], [2, 50], [3, 100]]
+            connections = [[1, 2]]
+            switches = []
+            spawners = []
+            signals = []
+            background = "#000000"
+        "##,
+        )
+        .unwrap();
+        let block_map = BlockMap::from_level(&level);
+        let (block, direction, adjacent_block) = spawner_track(&block_map, 1).unwrap();
+        assert_eq!((block.id, direction, adjacent_block.id), (1, Direction::Even, 2));
+        assert_eq!(
+            spawner_track(&block_map, 3).err(),
+            Some("the block is not joined to any other block")
+        );
+        assert_eq!(spawner_track(&block_map, 4).err(), Some("the block does not exist"));
+    }
+}
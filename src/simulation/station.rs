@@ -1,23 +1,39 @@
 use crate::assets::{AssetHandles, LoadingState};
 use crate::audio::AudioEvent;
-use crate::common::{BlockId, Direction, RouteId, SectionId, SignalId, SwitchId, SwitchPosition};
+use crate::common::{BlockId, CommandSource, Direction, RouteId, SectionId, SignalId, SwitchId, SwitchPosition};
 use crate::level::{Level, SwitchData, SwitchSetting};
-use crate::simulation::block::{SignalUpdate, SignalUpdateSource, TrackState, TrackUpdate};
-use crate::simulation::signal::SignalAspect;
+use crate::simulation::block::{BlockMap, SignalUpdate, SignalUpdateSource, TrackState, TrackUpdate};
+use crate::simulation::signal::{SignalAspect, SpeedLimit};
 use crate::simulation::sparse_vec::{Chunkable, SparseVec};
+use crate::simulation::train::Train;
 use bevy::prelude::*;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::iter::once;
+use std::time::Duration;
+
+/// How long a cancelled route stays locked when a train was already approaching its signal
+const APPROACH_LOCK_SECS: f32 = 120.0;
+/// Extra distance on top of the braking distance within which a train is considered approaching
+const APPROACH_SIGHTING_M: f64 = 200.0;
+/// Time a switch takes to move when the level doesn't give one
+const DEFAULT_THROW_TIME_S: f32 = 4.0;
 
 #[derive(Message)]
 pub struct SwitchUpdate {
     pub switch_id: SwitchId,
     pub position: SwitchPosition,
+    pub source: CommandSource,
 }
 
 impl SwitchUpdate {
-    pub fn new(switch_id: SwitchId, position: SwitchPosition) -> Self {
-        Self { switch_id, position }
+    pub fn new(switch_id: SwitchId, position: SwitchPosition, source: CommandSource) -> Self {
+        Self {
+            switch_id,
+            position,
+            source,
+        }
     }
 }
 
@@ -30,13 +46,74 @@ pub struct RoutePending {
     pub pending: bool,
 }
 
+/// Fired when a used route releases one of its sections behind the train. Released sections
+/// are unlocked, so conflicting routes that share only them may already be set.
+#[derive(Message)]
+pub struct RouteSectionReleased {
+    pub route_id: RouteId,
+    pub section_id: SectionId,
+    pub blocks: Vec<BlockId>,
+}
+
+/// Fired when a switch throw is refused because the switch is locked
+#[derive(Message)]
+pub struct SwitchThrowRejected {
+    pub switch_id: SwitchId,
+    pub reason: String,
+}
+
+/// Blocks a switch in its position, or lifts the block. A blocked switch refuses every throw until
+/// the dispatcher unblocks it.
+#[derive(Message)]
+pub struct SwitchBlocking {
+    pub switch_id: SwitchId,
+    pub blocked: bool,
+}
+
+/// Switch state as reported by its point detection
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum SwitchState {
+    /// Points are locked in the position, the leg is connected
+    Detected(SwitchPosition),
+    /// Points are moving to the position, neither leg is connected
+    Moving(SwitchPosition),
+}
+
+/// Fired when a switch starts moving and when its points are detected in the new position
+#[derive(Message)]
+pub struct SwitchStateChanged {
+    pub switch_id: SwitchId,
+    pub state: SwitchState,
+}
+
 pub struct Switch {
     pub id: SwitchId,
     pub base: BlockId,
     pub straight: BlockId,
     pub side: BlockId,
     pub direction: Direction,
+    /// Position the switch is in or moving to
     pub position: SwitchPosition,
+    pub throw_time: Duration,
+    /// Runs while the points are moving
+    pub throw: Option<Timer>,
+    /// Blocked by the dispatcher, the switch can't be thrown
+    pub blocked: bool,
+    /// Damaged by a train trailing it, out of use until the dispatcher clears it
+    pub failed: bool,
+}
+
+impl Switch {
+    pub fn state(&self) -> SwitchState {
+        match self.throw {
+            Some(_) => SwitchState::Moving(self.position),
+            None => SwitchState::Detected(self.position),
+        }
+    }
+
+    pub fn blocks(&self) -> [BlockId; 3] {
+        [self.base, self.straight, self.side]
+    }
 }
 
 impl From<&SwitchData> for Switch {
@@ -48,6 +125,10 @@ impl From<&SwitchData> for Switch {
             side: data.side,
             direction: data.direction,
             position: SwitchPosition::Straight,
+            throw_time: Duration::from_secs_f32(data.throw_time_s.unwrap_or(DEFAULT_THROW_TIME_S)),
+            throw: None,
+            blocked: false,
+            failed: false,
         }
     }
 }
@@ -89,18 +170,32 @@ enum RouteState {
     Active,
     /// Route set, train entered, signal closed
     Used,
+    /// Route cancelled with a train on approach, signal closed, released when the timer runs out
+    ApproachLocked(Timer),
+}
+
+/// A section of a route, kept in travel order. Used routes release sections one by one
+/// as the train clears them.
+#[derive(Default)]
+struct RouteSection {
+    id: SectionId,
+    block_ids: Vec<BlockId>,
+    tracker: BusyTracker,
+    released: bool,
 }
 
 #[derive(Default)]
 struct Route {
     id: RouteId,
     signal_id: SignalId,
-    block_ids: Vec<BlockId>,
+    sections: Vec<RouteSection>,
     target_block_id: BlockId,
     switch_settings: Vec<SwitchSetting>,
-    tracker: BusyTracker,
+    speed_limit: SpeedLimit,
     state: RouteState,
     target_block_state: TrackState,
+    /// The route is set but its signal stays closed until every switch is detected in position
+    awaiting_detection: bool,
 }
 
 impl Chunkable for Route {
@@ -111,10 +206,92 @@ impl Chunkable for Route {
 
 impl Route {
     fn all_blocks(&self) -> impl Iterator<Item = BlockId> {
-        self.block_ids.iter().copied().chain(once(self.target_block_id))
+        self.sections
+            .iter()
+            .flat_map(|s| s.block_ids.iter().copied())
+            .chain(once(self.target_block_id))
+    }
+
+    /// Blocks still held by the route, i.e. all blocks except the ones in released sections
+    fn locked_blocks(&self) -> impl Iterator<Item = BlockId> {
+        self.sections
+            .iter()
+            .filter(|s| !s.released)
+            .flat_map(|s| s.block_ids.iter().copied())
+            .chain(once(self.target_block_id))
+    }
+
+    fn is_free(&self) -> bool {
+        self.sections.iter().all(|s| s.tracker.is_free())
+    }
+
+    /// Releases the leading sections that the train has already cleared, in travel order,
+    /// stopping at the first section that is still occupied
+    fn release_cleared_sections(&mut self) -> Vec<&RouteSection> {
+        let mut released = Vec::new();
+        for section in self.sections.iter_mut().filter(|s| !s.released) {
+            if !section.tracker.is_free() {
+                break;
+            }
+            section.released = true;
+            released.push(&*section);
+        }
+        released
+    }
+}
+
+/// Route state as stored in a saved game
+#[derive(Serialize, Deserialize)]
+enum SavedRouteState {
+    Inactive,
+    Active,
+    Used,
+    ApproachLocked { remaining_s: f32 },
+}
+
+impl From<&RouteState> for SavedRouteState {
+    fn from(state: &RouteState) -> Self {
+        match state {
+            RouteState::Inactive => SavedRouteState::Inactive,
+            RouteState::Active => SavedRouteState::Active,
+            RouteState::Used => SavedRouteState::Used,
+            RouteState::ApproachLocked(timer) => SavedRouteState::ApproachLocked {
+                remaining_s: timer.remaining_secs(),
+            },
+        }
     }
 }
 
+impl From<&SavedRouteState> for RouteState {
+    fn from(state: &SavedRouteState) -> Self {
+        match state {
+            SavedRouteState::Inactive => RouteState::Inactive,
+            SavedRouteState::Active => RouteState::Active,
+            SavedRouteState::Used => RouteState::Used,
+            SavedRouteState::ApproachLocked { remaining_s } => {
+                RouteState::ApproachLocked(Timer::from_seconds(*remaining_s, TimerMode::Once))
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedRoute {
+    id: RouteId,
+    state: SavedRouteState,
+    /// Busy blocks and the released flag of each section, in travel order
+    sections: Vec<(Vec<BlockId>, bool)>,
+    target_block_state: TrackState,
+    #[serde(default)]
+    awaiting_detection: bool,
+}
+
+/// Dynamic state of the routes as stored in a saved game
+#[derive(Serialize, Deserialize)]
+pub struct StationMapState {
+    routes: Vec<SavedRoute>,
+}
+
 #[derive(Resource)]
 pub struct StationMap {
     routes: SparseVec<Route>,
@@ -131,17 +308,22 @@ impl StationMap {
             .iter()
             .flat_map(|sd| sd.routes.iter())
             .map(|rd| {
-                let block_ids: Vec<BlockId> = rd
+                let route_sections = rd
                     .sections
                     .iter()
-                    .flat_map(|sid| sections[sid].iter().copied())
+                    .map(|sid| RouteSection {
+                        id: *sid,
+                        block_ids: sections[sid].clone(),
+                        ..Default::default()
+                    })
                     .collect();
                 Route {
                     id: rd.id,
                     signal_id: rd.signal,
-                    block_ids,
+                    sections: route_sections,
                     target_block_id: rd.target,
                     switch_settings: rd.switches.clone(),
+                    speed_limit: rd.speed_kmh.into(),
                     ..Default::default()
                 }
             })
@@ -153,8 +335,10 @@ impl StationMap {
                 .entry(route.target_block_id)
                 .or_default()
                 .push(route.id);
-            for &block_id in &route.block_ids {
-                blocks_to_routes.entry(block_id).or_default().push(route.id);
+            for section in &route.sections {
+                for &block_id in &section.block_ids {
+                    blocks_to_routes.entry(block_id).or_default().push(route.id);
+                }
             }
         }
 
@@ -180,7 +364,11 @@ impl StationMap {
         }
     }
 
-    fn track_route_state(&mut self, track_updates: &mut MessageReader<TrackUpdate>) {
+    fn track_route_state(
+        &mut self,
+        track_updates: &mut MessageReader<TrackUpdate>,
+        section_releases: &mut MessageWriter<RouteSectionReleased>,
+    ) {
         let mut recheck_route_ids = HashSet::new();
         for update in track_updates.read() {
             if let Some(route_ids) = self.blocks_to_routes.get(&update.block_id) {
@@ -189,7 +377,11 @@ impl StationMap {
                     if update.block_id == route.target_block_id {
                         route.target_block_state = update.state;
                     } else {
-                        route.tracker.handle_update(update);
+                        route
+                            .sections
+                            .iter_mut()
+                            .filter(|s| s.block_ids.contains(&update.block_id))
+                            .for_each(|s| s.tracker.handle_update(update));
                         recheck_route_ids.insert(route_id);
                     }
                 }
@@ -199,79 +391,366 @@ impl StationMap {
         for route_id in recheck_route_ids {
             let route = &mut self.routes[route_id];
             match route.state {
-                RouteState::Active if !route.tracker.is_free() => route.state = RouteState::Used,
-                RouteState::Used if route.tracker.is_free() => {
-                    route.state = RouteState::Inactive;
+                RouteState::Active | RouteState::ApproachLocked(_) if !route.is_free() => {
+                    route.state = RouteState::Used
+                }
+                RouteState::Used => {
+                    section_releases.write_batch(route.release_cleared_sections().into_iter().map(|s| {
+                        RouteSectionReleased {
+                            route_id,
+                            section_id: s.id,
+                            blocks: s.block_ids.clone(),
+                        }
+                    }));
+                    if route.sections.iter().all(|s| s.released) {
+                        route.state = RouteState::Inactive;
+                    }
                 }
                 _ => {}
             };
         }
     }
 
+    /// Checks whether the route can be set right now, returning the reason if it can't
+    fn check_activation(&self, route_id: RouteId, block_map: &BlockMap) -> Result<(), &'static str> {
+        let Some(route) = self.routes.get(route_id) else {
+            return Err("does not exist");
+        };
+        if route.state != RouteState::Inactive {
+            return Err("is already active");
+        }
+
+        if !route.is_free() {
+            return Err("sections are occupied");
+        }
+
+        // Conflicting routes only block us while they still hold the shared blocks
+        let blocks: HashSet<BlockId> = route.all_blocks().collect();
+        let conflict = self.conflicting_routes.get(&route_id).is_some_and(|v| {
+            v.iter().any(|&rid| {
+                let other = &self.routes[rid];
+                other.state != RouteState::Inactive && other.locked_blocks().any(|b| blocks.contains(&b))
+            })
+        });
+        if conflict {
+            return Err("conflicts with other routes");
+        }
+
+        if route.target_block_state == TrackState::Occupied {
+            return Err("target block is occupied");
+        }
+
+        let locked = route
+            .switch_settings
+            .iter()
+            .any(|s| block_map.check_switch_throw(s.switch_id, s.position, self).is_err());
+        if locked {
+            return Err("has locked switches");
+        }
+
+        let damaged = route
+            .switch_settings
+            .iter()
+            .any(|s| block_map.switch(s.switch_id).is_some_and(|switch| switch.failed));
+        if damaged {
+            return Err("has a damaged switch");
+        }
+        Ok(())
+    }
+
+    /// Sets the requested routes and throws their switches. A route's signal opens right away when
+    /// all of its switches are already detected in position, otherwise once they are.
     fn handle_route_activation(
         &mut self,
         requests: &mut MessageReader<RouteActivationRequest>,
+        block_map: &BlockMap,
         signal_updates: &mut MessageWriter<SignalUpdate>,
         switch_updates: &mut MessageWriter<SwitchUpdate>,
         route_pending: &mut MessageWriter<RoutePending>,
         commands: &mut Commands,
     ) {
         for req in requests.read() {
-            let route = &self.routes[req.route_id];
-            if route.state != RouteState::Inactive {
-                warn!("Route {} is already active", req.route_id);
-                commands.trigger(AudioEvent::error());
-                continue;
-            }
+            let (throws, opening) = match self.set_route(req.route_id, block_map) {
+                Ok(updates) => updates,
+                Err(reason) => {
+                    warn!("Route {} {}", req.route_id, reason);
+                    commands.trigger(AudioEvent::error());
+                    continue;
+                }
+            };
+            switch_updates.write_batch(throws);
+            signal_updates.write_batch(opening);
+            route_pending.write(RoutePending {
+                blocks: self.routes[req.route_id].all_blocks().collect(),
+                pending: true,
+            });
+            commands.trigger(AudioEvent::beep());
+        }
+    }
 
-            if !route.tracker.is_free() {
-                warn!("Route {} sections are occupied", req.route_id);
-                commands.trigger(AudioEvent::error());
+    /// Activates the route, returning the throws of its switches and the update opening its signal
+    /// if the switches are already detected in position
+    fn set_route(
+        &mut self,
+        route_id: RouteId,
+        block_map: &BlockMap,
+    ) -> Result<(Vec<SwitchUpdate>, Option<SignalUpdate>), &'static str> {
+        self.check_activation(route_id, block_map)?;
+        let route = &mut self.routes[route_id];
+        let throws = route
+            .switch_settings
+            .iter()
+            .map(|s| SwitchUpdate::new(s.switch_id, s.position, CommandSource::Automatic))
+            .collect();
+        route.awaiting_detection = !route.switch_settings.iter().all(|s| is_detected(block_map, s));
+        let opening = (!route.awaiting_detection)
+            .then(|| SignalUpdate::new(route.signal_id, SignalUpdateSource::Route(route.speed_limit)));
+        route.state = RouteState::Active;
+        route.sections.iter_mut().for_each(|s| s.released = false);
+        Ok((throws, opening))
+    }
+
+    /// Opens the signals of the set routes whose switches have all been detected in position
+    fn open_detected_routes<'a>(
+        &mut self,
+        state_changes: impl IntoIterator<Item = &'a SwitchStateChanged>,
+        block_map: &BlockMap,
+    ) -> Vec<SignalUpdate> {
+        let detected: HashSet<SwitchId> = state_changes
+            .into_iter()
+            .filter(|change| matches!(change.state, SwitchState::Detected(_)))
+            .map(|change| change.switch_id)
+            .collect();
+        if detected.is_empty() {
+            return Vec::new();
+        }
+        let mut opened = Vec::new();
+        for route in &mut self.routes {
+            if route.state != RouteState::Active
+                || !route.awaiting_detection
+                || !route.switch_settings.iter().any(|s| detected.contains(&s.switch_id))
+                || !route.switch_settings.iter().all(|s| is_detected(block_map, s))
+            {
                 continue;
             }
+            info!("Route {} switches are detected", route.id);
+            route.awaiting_detection = false;
+            opened.push(SignalUpdate::new(
+                route.signal_id,
+                SignalUpdateSource::Route(route.speed_limit),
+            ));
+        }
+        opened
+    }
 
-            let conflict = self
-                .conflicting_routes
-                .get(&req.route_id)
-                .is_some_and(|v| v.iter().any(|&rid| self.routes[rid].state != RouteState::Inactive));
-            if conflict {
-                warn!("Route {} conflicts with other routes", req.route_id);
+    /// Cancels active routes, closing their signals. If a train is already approaching the signal
+    /// and may not be able to stop in front of it, the route stays approach-locked for a while.
+    fn handle_route_cancellation(
+        &mut self,
+        requests: &mut MessageReader<RouteCancellationRequest>,
+        approached_signals: &HashSet<SignalId>,
+        signal_updates: &mut MessageWriter<SignalUpdate>,
+        route_pending: &mut MessageWriter<RoutePending>,
+        commands: &mut Commands,
+    ) {
+        for req in requests.read() {
+            let Some(signal_id) = self.routes.get(req.route_id).map(|route| route.signal_id) else {
+                warn!("Route {} does not exist", req.route_id);
                 commands.trigger(AudioEvent::error());
                 continue;
-            }
-
-            if route.target_block_state == TrackState::Occupied {
-                warn!("Route {} target block is occupied", req.route_id);
+            };
+            if !self.cancel_route(req.route_id, approached_signals.contains(&signal_id)) {
+                warn!("Route {} is not set or already in use", req.route_id);
                 commands.trigger(AudioEvent::error());
                 continue;
             }
 
-            switch_updates.write_batch(
-                route
-                    .switch_settings
-                    .iter()
-                    .map(|s| SwitchUpdate::new(s.switch_id, s.position)),
-            );
-
             signal_updates.write(SignalUpdate::new(
-                route.signal_id,
-                SignalUpdateSource::Manual(SignalAspect::Unrestricting),
+                signal_id,
+                SignalUpdateSource::Manual(SignalAspect::Forbidding),
             ));
+            let route = &self.routes[req.route_id];
+            if route.state == RouteState::Inactive {
+                route_pending.write(RoutePending {
+                    blocks: route.all_blocks().collect(),
+                    pending: false,
+                });
+            }
+            commands.trigger(AudioEvent::beep());
+        }
+    }
+
+    /// Takes an active route out of use, approach-locking it if a train is `approaching` its
+    /// signal. Returns false if the route isn't active.
+    fn cancel_route(&mut self, route_id: RouteId, approaching: bool) -> bool {
+        let Some(route) = self.routes.get_mut(route_id) else {
+            return false;
+        };
+        if route.state != RouteState::Active {
+            return false;
+        }
+        route.state = if approaching {
+            info!("Route {} is approach-locked for {} s", route_id, APPROACH_LOCK_SECS);
+            RouteState::ApproachLocked(Timer::from_seconds(APPROACH_LOCK_SECS, TimerMode::Once))
+        } else {
+            RouteState::Inactive
+        };
+        true
+    }
 
-            let route = &mut self.routes[req.route_id];
-            route.state = RouteState::Active;
+    fn tick_approach_locks(&mut self, delta: Duration, route_pending: &mut MessageWriter<RoutePending>) {
+        for route_id in self.expire_approach_locks(delta) {
             route_pending.write(RoutePending {
-                blocks: route.all_blocks().collect(),
-                pending: true,
+                blocks: self.routes[route_id].all_blocks().collect(),
+                pending: false,
             });
-            commands.trigger(AudioEvent::beep());
         }
     }
+
+    /// Releases the approach-locked routes whose lock ran out, returning them
+    fn expire_approach_locks(&mut self, delta: Duration) -> Vec<RouteId> {
+        let mut expired = Vec::new();
+        for route in &mut self.routes {
+            if let RouteState::ApproachLocked(timer) = &mut route.state
+                && timer.tick(delta).is_finished()
+            {
+                info!("Route {} approach locking expired", route.id);
+                route.state = RouteState::Inactive;
+                expired.push(route.id);
+            }
+        }
+        expired
+    }
+
+    /// Whether the route is set and awaiting a train, i.e. it can be cancelled
+    pub fn is_route_active(&self, route_id: RouteId) -> bool {
+        self.routes.get(route_id).is_some_and(|r| r.state == RouteState::Active)
+    }
+
+    /// Seconds left until an approach-locked route is released, `None` for any other state
+    pub fn approach_lock_remaining(&self, route_id: RouteId) -> Option<f32> {
+        match &self.routes.get(route_id)?.state {
+            RouteState::ApproachLocked(timer) => Some(timer.remaining_secs()),
+            _ => None,
+        }
+    }
+
+    /// Whether the route can be set right now
+    pub fn can_activate(&self, route_id: RouteId, block_map: &BlockMap) -> bool {
+        self.check_activation(route_id, block_map).is_ok()
+    }
+
+    /// A set route holding the switch in a position other than the given one
+    pub fn route_locking_switch(&self, switch_id: SwitchId, position: SwitchPosition) -> Option<RouteId> {
+        self.routes
+            .iter()
+            .find(|r| {
+                r.state != RouteState::Inactive
+                    && r.switch_settings
+                        .iter()
+                        .any(|s| s.switch_id == switch_id && s.position != position)
+            })
+            .map(|r| r.id)
+    }
+
+    /// Routes starting at the signal with their target blocks
+    pub fn routes_from_signal(&self, signal_id: SignalId) -> impl Iterator<Item = (RouteId, BlockId)> + '_ {
+        self.routes
+            .iter()
+            .filter(move |r| r.signal_id == signal_id)
+            .map(|r| (r.id, r.target_block_id))
+    }
+
+    /// Whether any route starting at the signal is set or still held by a train
+    pub fn is_signal_routed(&self, signal_id: SignalId) -> bool {
+        self.routes
+            .iter()
+            .any(|r| r.signal_id == signal_id && r.state != RouteState::Inactive)
+    }
+
+    /// Blocks of the set routes that trains haven't entered yet
+    pub fn pending_blocks(&self) -> impl Iterator<Item = BlockId> + '_ {
+        self.routes
+            .iter()
+            .filter(|r| r.state != RouteState::Inactive)
+            .flat_map(|r| {
+                r.sections
+                    .iter()
+                    .filter(|s| !s.released && s.tracker.is_free())
+                    .flat_map(|s| s.block_ids.iter().copied())
+                    .chain(once(r.target_block_id))
+            })
+    }
+
+    pub fn save_state(&self) -> StationMapState {
+        let routes = self
+            .routes
+            .iter()
+            .map(|route| SavedRoute {
+                id: route.id,
+                state: (&route.state).into(),
+                sections: route
+                    .sections
+                    .iter()
+                    .map(|s| (s.tracker.0.iter().copied().sorted().collect(), s.released))
+                    .collect(),
+                target_block_state: route.target_block_state,
+                awaiting_detection: route.awaiting_detection,
+            })
+            .collect();
+        StationMapState { routes }
+    }
+
+    pub fn restore_state(&mut self, state: &StationMapState) {
+        for route in &mut self.routes {
+            route.state = RouteState::Inactive;
+            route.target_block_state = TrackState::Freed;
+            route.awaiting_detection = false;
+            for section in &mut route.sections {
+                section.tracker = BusyTracker::default();
+                section.released = false;
+            }
+        }
+        for saved in &state.routes {
+            let Some(route) = self.routes.get_mut(saved.id) else {
+                continue;
+            };
+            route.state = (&saved.state).into();
+            route.target_block_state = saved.target_block_state;
+            route.awaiting_detection = saved.awaiting_detection;
+            for (section, (busy, released)) in route.sections.iter_mut().zip(&saved.sections) {
+                section.tracker = BusyTracker(busy.iter().copied().collect());
+                section.released = *released;
+            }
+        }
+    }
+
+    /// Whether the two routes share any blocks
+    pub fn routes_conflict(&self, route_id: RouteId, other_id: RouteId) -> bool {
+        route_id == other_id
+            || self
+                .conflicting_routes
+                .get(&route_id)
+                .is_some_and(|v| v.contains(&other_id))
+    }
+}
+
+fn is_detected(block_map: &BlockMap, setting: &SwitchSetting) -> bool {
+    block_map
+        .switch(setting.switch_id)
+        .is_some_and(|switch| switch.state() == SwitchState::Detected(setting.position))
 }
 
 #[derive(Message)]
 pub struct RouteActivationRequest {
     pub route_id: RouteId,
+    pub source: CommandSource,
+}
+
+#[derive(Message)]
+pub struct RouteCancellationRequest {
+    pub route_id: RouteId,
+    pub source: CommandSource,
 }
 
 pub struct StationPlugin;
@@ -281,11 +760,23 @@ impl Plugin for StationPlugin {
         app.add_systems(OnEnter(LoadingState::Instantiated), build_station_map)
             .add_systems(
                 Update,
-                (track_route_state, handle_route_activation).run_if(in_state(LoadingState::Instantiated)),
+                (
+                    track_route_state,
+                    handle_route_activation,
+                    open_detected_routes,
+                    handle_route_cancellation,
+                    tick_approach_locks,
+                )
+                    .run_if(in_state(LoadingState::Instantiated)),
             )
             .add_message::<RouteActivationRequest>()
+            .add_message::<RouteCancellationRequest>()
             .add_message::<RoutePending>()
-            .add_message::<SwitchUpdate>();
+            .add_message::<RouteSectionReleased>()
+            .add_message::<SwitchUpdate>()
+            .add_message::<SwitchStateChanged>()
+            .add_message::<SwitchThrowRejected>()
+            .add_message::<SwitchBlocking>();
     }
 }
 
@@ -294,12 +785,17 @@ fn build_station_map(handles: Res<AssetHandles>, levels: Res<Assets<Level>>, mut
     commands.insert_resource(StationMap::from_level(level));
 }
 
-fn track_route_state(mut station_map: ResMut<StationMap>, mut block_updates: MessageReader<TrackUpdate>) {
-    station_map.track_route_state(&mut block_updates);
+fn track_route_state(
+    mut station_map: ResMut<StationMap>,
+    mut block_updates: MessageReader<TrackUpdate>,
+    mut section_releases: MessageWriter<RouteSectionReleased>,
+) {
+    station_map.track_route_state(&mut block_updates, &mut section_releases);
 }
 
 fn handle_route_activation(
     mut station_map: ResMut<StationMap>,
+    block_map: Res<BlockMap>,
     mut requests: MessageReader<RouteActivationRequest>,
     mut signal_updates: MessageWriter<SignalUpdate>,
     mut switch_updates: MessageWriter<SwitchUpdate>,
@@ -308,9 +804,206 @@ fn handle_route_activation(
 ) {
     station_map.handle_route_activation(
         &mut requests,
+        &block_map,
         &mut signal_updates,
         &mut switch_updates,
         &mut route_pending,
         &mut commands,
     );
 }
+
+fn open_detected_routes(
+    mut station_map: ResMut<StationMap>,
+    block_map: Res<BlockMap>,
+    mut state_changes: MessageReader<SwitchStateChanged>,
+    mut signal_updates: MessageWriter<SignalUpdate>,
+) {
+    signal_updates.write_batch(station_map.open_detected_routes(state_changes.read(), &block_map));
+}
+
+fn handle_route_cancellation(
+    mut station_map: ResMut<StationMap>,
+    block_map: Res<BlockMap>,
+    trains: Query<&Train>,
+    mut requests: MessageReader<RouteCancellationRequest>,
+    mut signal_updates: MessageWriter<SignalUpdate>,
+    mut route_pending: MessageWriter<RoutePending>,
+    mut commands: Commands,
+) {
+    if requests.is_empty() {
+        return;
+    }
+
+    let approached_signals: HashSet<SignalId> = trains
+        .iter()
+        .filter_map(|train| {
+            let (signal, distance_m) = train.signal_ahead(&block_map)?;
+            (distance_m <= train.stopping_distance_m() + APPROACH_SIGHTING_M).then_some(signal.id)
+        })
+        .collect();
+
+    station_map.handle_route_cancellation(
+        &mut requests,
+        &approached_signals,
+        &mut signal_updates,
+        &mut route_pending,
+        &mut commands,
+    );
+}
+
+fn tick_approach_locks(
+    time: Res<Time>,
+    mut station_map: ResMut<StationMap>,
+    mut route_pending: MessageWriter<RoutePending>,
+) {
+    station_map.tick_approach_locks(time.delta(), &mut route_pending);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_route() -> Route {
+        let sections = [(1, vec![1, 2]), (2, vec![3]), (3, vec![4, 5])]
+            .into_iter()
+            .map(|(id, block_ids)| RouteSection {
+                id,
+                block_ids,
+                ..Default::default()
+            })
+            .collect();
+        Route {
+            id: 1,
+            sections,
+            target_block_id: 6,
+            state: RouteState::Used,
+            ..Default::default()
+        }
+    }
+
+    fn occupy(route: &mut Route, section_idx: usize, block_id: BlockId, state: TrackState) {
+        route.sections[section_idx].tracker.handle_update(&TrackUpdate {
+            block_id,
+            state,
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn release_sections_in_travel_order() {
+        let mut route = build_route();
+        occupy(&mut route, 0, 2, TrackState::Occupied);
+        occupy(&mut route, 1, 3, TrackState::Occupied);
+        assert!(route.release_cleared_sections().is_empty());
+
+        occupy(&mut route, 0, 2, TrackState::Freed);
+        let released: Vec<SectionId> = route.release_cleared_sections().iter().map(|s| s.id).collect();
+        assert_eq!(released, [1]);
+        assert_eq!(route.locked_blocks().collect::<Vec<_>>(), [3, 4, 5, 6]);
+
+        // section 3 hasn't been entered yet, but must wait for section 2 anyway
+        occupy(&mut route, 2, 4, TrackState::Occupied);
+        occupy(&mut route, 1, 3, TrackState::Freed);
+        let released: Vec<SectionId> = route.release_cleared_sections().iter().map(|s| s.id).collect();
+        assert_eq!(released, [2]);
+
+        occupy(&mut route, 2, 4, TrackState::Freed);
+        let released: Vec<SectionId> = route.release_cleared_sections().iter().map(|s| s.id).collect();
+        assert_eq!(released, [3]);
+        assert_eq!(route.locked_blocks().collect::<Vec<_>>(), [6]);
+    }
+
+    #[test]
+    fn set_route_locks_its_switches() {
+        let level = Level::test_fixture();
+        let block_map = BlockMap::from_level(&level);
+        let mut station_map = StationMap::from_level(&level);
+        station_map.routes[1].state = RouteState::Active;
+        assert_eq!(
+            block_map.check_switch_throw(1, SwitchPosition::Side, &station_map),
+            Err("is locked by route 1".to_string())
+        );
+        // the conflicting route over the other leg can't be set either
+        assert_eq!(
+            station_map.check_activation(2, &block_map),
+            Err("conflicts with other routes")
+        );
+        assert!(
+            block_map
+                .check_switch_throw(2, SwitchPosition::Side, &station_map)
+                .is_ok()
+        );
+
+        station_map.routes[1].state = RouteState::Inactive;
+        assert!(station_map.can_activate(2, &block_map));
+    }
+
+    #[test]
+    fn cancelled_route_is_released_without_an_approaching_train() {
+        let mut station_map = StationMap::from_level(&Level::test_fixture());
+        assert!(!station_map.cancel_route(1, false));
+
+        station_map.routes[1].state = RouteState::Active;
+        assert!(station_map.cancel_route(1, false));
+        assert!(station_map.routes[1].state == RouteState::Inactive);
+        assert!(station_map.expire_approach_locks(Duration::ZERO).is_empty());
+    }
+
+    #[test]
+    fn cancelled_route_stays_approach_locked_until_the_lock_expires() {
+        let mut station_map = StationMap::from_level(&Level::test_fixture());
+        station_map.routes[1].state = RouteState::Active;
+        assert!(station_map.cancel_route(1, true));
+        assert!(matches!(station_map.routes[1].state, RouteState::ApproachLocked(_)));
+        // an approach-locked route can neither be cancelled again nor set
+        assert!(!station_map.cancel_route(1, true));
+        assert!(!station_map.is_route_active(1));
+        assert_eq!(station_map.approach_lock_remaining(1), Some(APPROACH_LOCK_SECS));
+
+        let lock = Duration::from_secs_f32(APPROACH_LOCK_SECS);
+        assert!(station_map.expire_approach_locks(lock / 2).is_empty());
+        assert_eq!(station_map.expire_approach_locks(lock / 2), [1]);
+        assert!(station_map.routes[1].state == RouteState::Inactive);
+    }
+
+    #[test]
+    fn unknown_routes_are_rejected() {
+        let block_map = BlockMap::from_level(&Level::test_fixture());
+        let mut station_map = StationMap::from_level(&Level::test_fixture());
+        assert!(station_map.set_route(99, &block_map).is_err());
+        assert!(!station_map.cancel_route(99, false));
+        assert!(!station_map.is_route_active(99));
+        assert!(station_map.approach_lock_remaining(99).is_none());
+    }
+
+    #[test]
+    fn signal_opens_once_the_switches_are_detected() {
+        let mut level = Level::test_fixture();
+        level.switches[0].throw_time_s = Some(6.0);
+        let mut block_map = BlockMap::from_level(&level);
+        let mut station_map = StationMap::from_level(&level);
+
+        // switch 2 already lies straight, the route opens right away
+        let (_, opening) = station_map.set_route(3, &block_map).unwrap();
+        assert!(opening.is_some_and(|update| update.signal_id == 51));
+
+        let (throws, opening) = station_map.set_route(2, &block_map).unwrap();
+        assert!(opening.is_none());
+        assert!(station_map.routes[2].awaiting_detection);
+        for throw in &throws {
+            block_map.throw_switch(throw.switch_id, throw.position);
+        }
+        let changes = block_map.tick_switches(Duration::from_secs(5));
+        assert!(station_map.open_detected_routes(&changes, &block_map).is_empty());
+
+        let changes = block_map.tick_switches(Duration::from_secs(1));
+        let opened = station_map.open_detected_routes(&changes, &block_map);
+        assert_eq!(opened.len(), 1);
+        assert_eq!(opened[0].signal_id, 50);
+        assert!(matches!(
+            opened[0].source,
+            SignalUpdateSource::Route(SpeedLimit::Restricted(40.0))
+        ));
+        assert!(!station_map.routes[2].awaiting_detection);
+    }
+}
@@ -0,0 +1,235 @@
+use crate::assets::{AssetHandles, LoadingState};
+use crate::common::{BlockId, CommandSource};
+use crate::level::Level;
+use crate::simulation::spawner::{SpawnRequest, SpawnTrainType, Spawners};
+use crate::simulation::train::CallingStation;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Delays below this are not worth reporting
+const DELAY_REPORT_THRESHOLD_S: f64 = 1.0;
+
+#[derive(Clone, Serialize, Deserialize)]
+struct ScheduledService {
+    number: String,
+    train_type: SpawnTrainType,
+    entry: BlockId,
+    entry_time_s: f64,
+    exit: BlockId,
+    #[serde(default)]
+    exit_time_s: f64,
+    priority: u8,
+    calling_at: Vec<CallingStation>,
+    held: bool,
+}
+
+/// Services that have not entered the area yet, ordered by the scheduled entry time
+#[derive(Resource, Default)]
+pub struct Timetable {
+    /// Virtual time at which the shift started
+    shift_start_s: f64,
+    pending: Vec<ScheduledService>,
+}
+
+/// Services still to enter and the shift start, as stored in a saved game
+#[derive(Serialize, Deserialize)]
+pub struct TimetableState {
+    /// Virtual time at which the shift started, on the saved game's clock
+    shift_start_s: f64,
+    pending: Vec<ScheduledService>,
+}
+
+impl Timetable {
+    fn new(level: &Level, shift_start_s: f64) -> Self {
+        let mut pending: Vec<ScheduledService> = level
+            .timetable
+            .iter()
+            .map(|data| ScheduledService {
+                number: data.number.clone(),
+                train_type: SpawnTrainType(data.consist.clone()),
+                entry: data.entry,
+                entry_time_s: data.entry_time_s,
+                exit: data.exit,
+                exit_time_s: data.exit_time_s,
+                priority: data.priority,
+                calling_at: data
+                    .stops
+                    .iter()
+                    .map(|stop| CallingStation {
+                        station_id: stop.station,
+                        dwell_s: stop.departure_s - stop.arrival_s,
+                        arrival_s: Some(stop.arrival_s),
+                        departure_s: Some(stop.departure_s),
+                    })
+                    .collect(),
+                held: false,
+            })
+            .collect();
+        pending.sort_by(|a, b| a.entry_time_s.total_cmp(&b.entry_time_s));
+        Timetable { shift_start_s, pending }
+    }
+
+    pub fn shift_time_s(&self, elapsed_s: f64) -> f64 {
+        elapsed_s - self.shift_start_s
+    }
+
+    pub fn save_state(&self) -> TimetableState {
+        TimetableState {
+            shift_start_s: self.shift_start_s,
+            pending: self.pending.clone(),
+        }
+    }
+
+    /// Restores the saved services, `clock_offset_s` is the current virtual time less the saved one,
+    /// so the shift continues from the saved time
+    pub fn restore_state(&mut self, state: &TimetableState, clock_offset_s: f64) {
+        self.shift_start_s = state.shift_start_s + clock_offset_s;
+        self.pending = state.pending.clone();
+    }
+
+    /// Spawn requests for the services that are due, services at a busy spawner are retried later
+    fn dispatch(&mut self, now_s: f64, is_busy: impl Fn(BlockId) -> Option<bool>) -> Vec<SpawnRequest> {
+        let mut dispatched = HashSet::new();
+        let mut requests = Vec::new();
+        self.pending.retain_mut(|service| {
+            if service.entry_time_s > now_s {
+                return true;
+            }
+            match is_busy(service.entry) {
+                None => {
+                    warn!(
+                        "Service {} enters through block {} which has no spawner, dropped",
+                        service.number, service.entry
+                    );
+                    false
+                }
+                Some(busy) if busy || dispatched.contains(&service.entry) => {
+                    if !service.held {
+                        info!("Service {} is held, spawner {} is busy", service.number, service.entry);
+                        service.held = true;
+                    }
+                    true
+                }
+                Some(_) => {
+                    report_delay(&service.number, "entered", service.entry_time_s, now_s);
+                    requests.push(SpawnRequest {
+                        block_id: service.entry,
+                        train_type: service.train_type.clone(),
+                        number: Some(service.number.clone()),
+                        destination: Some(service.exit),
+                        priority: service.priority,
+                        calling_at: service.calling_at.clone(),
+                        exit_time_s: Some(service.exit_time_s),
+                        source: CommandSource::Automatic,
+                    });
+                    dispatched.insert(service.entry);
+                    false
+                }
+            }
+        });
+        requests
+    }
+}
+
+/// Logs how late the service is at a planned event, `actual_s` and `planned_s` are on the shift clock
+pub fn report_delay(number: &str, event: &str, planned_s: f64, actual_s: f64) {
+    let delay_s = actual_s - planned_s;
+    if delay_s >= DELAY_REPORT_THRESHOLD_S {
+        info!("Service {} {} {:.0} s late", number, event, delay_s);
+    }
+}
+
+pub struct TimetablePlugin;
+
+impl Plugin for TimetablePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Timetable>()
+            .add_systems(OnEnter(LoadingState::Instantiated), init)
+            .add_systems(Update, dispatch_services.run_if(in_state(LoadingState::Instantiated)));
+    }
+}
+
+fn init(handles: Res<AssetHandles>, levels: Res<Assets<Level>>, time: Res<Time>, mut commands: Commands) {
+    let level = levels.get(&handles.level).expect("level had been loaded");
+    commands.insert_resource(Timetable::new(level, time.elapsed_secs_f64()));
+}
+
+fn dispatch_services(mut timetable: ResMut<Timetable>, time: Res<Time>, spawners: Spawners, mut commands: Commands) {
+    let now_s = timetable.shift_time_s(time.elapsed_secs_f64());
+    for request in timetable.dispatch(now_s, |block_id| spawners.is_busy(block_id)) {
+        commands.trigger(request);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbers(requests: &[SpawnRequest]) -> Vec<&str> {
+        requests.iter().filter_map(|r| r.number.as_deref()).collect()
+    }
+
+    #[test]
+    fn due_services_are_dispatched_one_per_spawner() {
+        let mut timetable = Timetable::new(&Level::test_fixture(), 0.0);
+        assert!(timetable.dispatch(20.0, |_| Some(false)).is_empty());
+
+        // 2402 and 6102 both enter through block 1, 6101 and 0851 through block 11
+        let requests = timetable.dispatch(500.0, |_| Some(false));
+        assert_eq!(numbers(&requests), ["2402", "6101"]);
+        assert_eq!(requests[0].block_id, 1);
+        assert_eq!(requests[0].destination, Some(11));
+        assert_eq!(requests[0].source, CommandSource::Automatic);
+
+        let requests = timetable.dispatch(500.0, |_| Some(false));
+        assert_eq!(numbers(&requests), ["6102", "0851"]);
+        assert_eq!(timetable.pending.len(), 1);
+    }
+
+    #[test]
+    fn services_carry_their_planned_times() {
+        let mut timetable = Timetable::new(&Level::test_fixture(), 0.0);
+        let requests = timetable.dispatch(100.0, |block_id| Some(block_id != 11));
+        assert_eq!(numbers(&requests), ["6101"]);
+        assert_eq!(requests[0].exit_time_s, Some(480.0));
+        let stop = &requests[0].calling_at[0];
+        assert_eq!(
+            (stop.dwell_s, stop.arrival_s, stop.departure_s),
+            (60.0, Some(240.0), Some(300.0))
+        );
+    }
+
+    #[test]
+    fn services_at_a_busy_spawner_are_retried() {
+        let mut timetable = Timetable::new(&Level::test_fixture(), 0.0);
+        assert!(timetable.dispatch(30.0, |block_id| Some(block_id == 1)).is_empty());
+        assert!(timetable.pending[0].held);
+
+        let requests = timetable.dispatch(75.0, |_| Some(false));
+        assert_eq!(numbers(&requests), ["2402"]);
+        assert!(timetable.pending.iter().all(|service| !service.held));
+    }
+
+    #[test]
+    fn services_without_a_spawner_are_dropped() {
+        let mut timetable = Timetable::new(&Level::test_fixture(), 0.0);
+        let requests = timetable.dispatch(100.0, |block_id| (block_id == 11).then_some(false));
+        assert_eq!(numbers(&requests), ["6101"]);
+        assert_eq!(timetable.pending.len(), 3);
+    }
+
+    #[test]
+    fn restored_shift_continues_on_the_current_clock() {
+        let mut timetable = Timetable::new(&Level::test_fixture(), 100.0);
+        timetable.dispatch(200.0, |_| Some(false));
+        let saved = timetable.save_state();
+
+        // saved at 300 s of virtual time, loaded at 1000 s
+        let mut restored = Timetable::default();
+        restored.restore_state(&saved, 700.0);
+        assert_eq!(restored.shift_time_s(1000.0), timetable.shift_time_s(300.0));
+        let numbers = |t: &Timetable| t.pending.iter().map(|s| s.number.clone()).collect::<Vec<_>>();
+        assert_eq!(numbers(&restored), numbers(&timetable));
+    }
+}
@@ -1,9 +1,27 @@
-use crate::assets::LoadingState;
-use crate::common::{BlockId, Direction, SpeedConv, TrainId};
-use crate::simulation::block::{BlockMap, TrackPoint};
-use crate::simulation::signal::SpeedLimit;
+use crate::assets::{AssetHandles, LoadingState};
+use crate::common::{BlockId, Direction, RailCondition, SpeedConv, StationId, TrainId};
+use crate::level::Level;
+use crate::rolling_stock::{VehicleData, VehicleKind};
+use crate::simulation::block::{BlockMap, TrackEnd, TrackPoint};
+use crate::simulation::incident::Incident;
+use crate::simulation::random::SimulationRng;
+use crate::simulation::signal::{SignalAspect, SpeedControl, SpeedLimit, Speeds, TrackSignal};
+use crate::simulation::spad::SignalPassedAtDanger;
+use crate::simulation::timetable::{Timetable, report_delay};
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
-use std::collections::HashMap;
+use rand::RngExt;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+const GRAVITY_MPS2: f64 = 9.81;
+/// Floor of the deceleration assumed for braking distances, so that they stay finite on steep descents
+const MIN_DECELERATION_MPS2: f64 = 0.05;
+/// Overspeed past a signal the train protection tolerates, the speed control aims just under the limit
+const SPAD_TOLERANCE_KMH: f64 = 2.0;
+/// Overrun past a signal the train protection tolerates, a train creeping to a stand at a closed
+/// signal may come to rest just beyond it
+const SPAD_OVERRUN_M: f64 = 2.0;
 
 #[derive(Copy, Clone, PartialEq)]
 pub enum TrainMoveKind {
@@ -42,7 +60,7 @@ impl TrainMove {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 struct TrainControls {
     throttle: f64,
     brake_level: f64,
@@ -58,13 +76,41 @@ struct TrainControls {
 //     }
 // }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Serialize, Deserialize)]
 enum VehicleType {
     Locomotive,
     RailCar,
 }
 
-#[derive(Copy, Clone)]
+/// Running resistance of a vehicle on straight and level track (Davis equation): `A + B·v + C·v²`
+#[derive(Copy, Clone, Serialize, Deserialize)]
+pub struct DavisResistance {
+    /// Speed-independent rolling resistance (bearings, wheel-rail contact)
+    pub a_n: f64,
+    /// Resistance proportional to speed (flange friction, track deflection)
+    pub b_ns_per_m: f64,
+    /// Aerodynamic resistance, proportional to the square of speed
+    pub c_ns2_per_m2: f64,
+}
+
+impl DavisResistance {
+    /// Typical coefficients for a four-axle vehicle of the given total mass,
+    /// a leading vehicle takes most of the aerodynamic drag
+    fn typical(total_mass_kg: f64, leading: bool) -> DavisResistance {
+        let mass_t = total_mass_kg / 1000.0;
+        DavisResistance {
+            a_n: 6.4 * mass_t + 130.0 * 4.0,
+            b_ns_per_m: 0.49 * mass_t,
+            c_ns2_per_m2: if leading { 5.0 } else { 1.0 },
+        }
+    }
+
+    fn get_resistance_n(&self, speed_mps: f64) -> f64 {
+        self.a_n + self.b_ns_per_m * speed_mps + self.c_ns2_per_m2 * speed_mps.powi(2)
+    }
+}
+
+#[derive(Copy, Clone, Serialize, Deserialize)]
 pub struct RailVehicle {
     vehicle_type: VehicleType,
     mass_kg: f64,
@@ -73,6 +119,7 @@ pub struct RailVehicle {
     cargo_mass_kg: f64,
     power_w: f64,
     max_tractive_effort_n: f64,
+    resistance: DavisResistance,
 }
 
 impl RailVehicle {
@@ -85,6 +132,7 @@ impl RailVehicle {
             max_braking_force_n: 12_000.0,
             power_w: 0.0,
             max_tractive_effort_n: 0.0,
+            resistance: DavisResistance::typical(mass_kg + cargo_mass_kg, false),
         }
     }
 
@@ -97,11 +145,18 @@ impl RailVehicle {
             max_tractive_effort_n: max_tractive_effort_kn * 1000.0,
             max_braking_force_n: 50_000.0,
             cargo_mass_kg: 0.0,
+            resistance: DavisResistance::typical(mass_kg, true),
         }
     }
 
-    fn get_tractive_effort(&self, speed_mps: f64, throttle: f64) -> f64 {
-        match self.vehicle_type {
+    pub fn with_resistance(mut self, resistance: DavisResistance) -> RailVehicle {
+        self.resistance = resistance;
+        self
+    }
+
+    /// Tractive effort at the given speed and throttle, limited by the adhesion of the vehicle's weight
+    fn get_tractive_effort(&self, speed_mps: f64, throttle: f64, adhesion: f64) -> f64 {
+        let tractive_effort = match self.vehicle_type {
             VehicleType::Locomotive => {
                 let max_tractive_effort_n = self.max_tractive_effort_n * throttle;
                 if speed_mps < 0.01 {
@@ -113,29 +168,79 @@ impl RailVehicle {
                 }
             }
             VehicleType::RailCar => 0.0,
+        };
+        tractive_effort.min(self.get_weight_n() * adhesion)
+    }
+
+    /// Braking force at the given brake level, limited by the adhesion of the vehicle's weight
+    fn get_braking_force(&self, brake_level: f64, adhesion: f64) -> f64 {
+        (self.max_braking_force_n * brake_level).min(self.get_weight_n() * adhesion)
+    }
+
+    fn get_weight_n(&self) -> f64 {
+        (self.mass_kg + self.cargo_mass_kg) * GRAVITY_MPS2
+    }
+}
+
+impl From<&VehicleData> for RailVehicle {
+    fn from(data: &VehicleData) -> Self {
+        let mut vehicle = match data.kind {
+            VehicleKind::Locomotive | VehicleKind::Emu => {
+                let mut vehicle = RailVehicle::new_locomotive(
+                    data.mass_kg,
+                    data.length_m,
+                    data.power_kw,
+                    data.max_tractive_effort_kn,
+                );
+                vehicle.cargo_mass_kg = data.cargo_mass_kg;
+                vehicle
+            }
+            VehicleKind::Wagon => RailVehicle::new_car(data.mass_kg, data.length_m, data.cargo_mass_kg),
+        };
+        if let Some(braking_force_kn) = data.max_braking_force_kn {
+            vehicle.max_braking_force_n = braking_force_kn * 1000.0;
+        }
+        match data.resistance {
+            Some([a_n, b_ns_per_m, c_ns2_per_m2]) => vehicle.with_resistance(DavisResistance {
+                a_n,
+                b_ns_per_m,
+                c_ns2_per_m2,
+            }),
+            // Multiple unit cars run coupled behind a streamlined cab, so they get the trailing drag
+            None if data.kind == VehicleKind::Emu => {
+                vehicle.with_resistance(DavisResistance::typical(data.mass_kg + data.cargo_mass_kg, false))
+            }
+            None => vehicle,
         }
     }
 }
 
-#[derive(Default)]
+/// Curve resistance in N per kN of vehicle weight (Röckl's formula for standard gauge)
+fn get_curve_resistance_per_kn(curve_radius_m: f64) -> f64 {
+    let radius_m = curve_radius_m.max(50.0);
+    if radius_m >= 300.0 {
+        650.0 / (radius_m - 55.0)
+    } else {
+        500.0 / (radius_m - 30.0)
+    }
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
 struct TrainStats {
     length_m: f64,
     mass_kg: f64,
-    max_braking_force_n: f64,
 }
 
 fn get_train_stats<'a, I: IntoIterator<Item = &'a RailVehicle>>(vehicles: I) -> TrainStats {
-    let result = vehicles.into_iter().fold((0.0, 0.0, 0.0), |acc, vehicle| {
+    let result = vehicles.into_iter().fold((0.0, 0.0), |acc, vehicle| {
         (
             acc.0 + vehicle.length_m,
             acc.1 + vehicle.mass_kg + vehicle.cargo_mass_kg,
-            acc.2 + vehicle.max_braking_force_n,
         )
     });
     TrainStats {
         length_m: result.0,
         mass_kg: result.1,
-        max_braking_force_n: result.2,
     }
 }
 
@@ -149,7 +254,29 @@ impl NextTrainId {
     }
 }
 
-#[derive(Component, Default)]
+/// A station the train is scheduled to stop at
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct CallingStation {
+    pub station_id: StationId,
+    pub dwell_s: f64,
+    /// Planned arrival on the shift clock, for timetabled services
+    #[serde(default)]
+    pub arrival_s: Option<f64>,
+    /// Planned departure on the shift clock, the train doesn't leave earlier
+    #[serde(default)]
+    pub departure_s: Option<f64>,
+}
+
+/// State of a train standing at a platform
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum PlatformStop {
+    /// Remaining dwell time in seconds
+    Dwelling(f64),
+    /// Dwell is over, the train waits for the exit signal to clear
+    ReadyToDepart,
+}
+
+#[derive(Component, Default, Clone, Serialize, Deserialize)]
 pub struct Train {
     pub id: TrainId,
     pub number: String,
@@ -166,11 +293,59 @@ pub struct Train {
 
     front_position: TrackPoint,
     back_position: TrackPoint,
+
+    /// Force from the track profile (gradients and curves) opposing the motion, negative on descents
+    profile_resistance_n: f64,
+    /// Wheel-rail adhesion coefficient limiting traction and braking
+    adhesion: f64,
+
+    /// Despawner block the train is heading to, used for automatic route setting
+    destination: Option<BlockId>,
+    /// Trains with higher priority get their routes set first
+    priority: u8,
+    /// Stations still to call at, in order
+    calling_at: VecDeque<CallingStation>,
+    platform_stop: Option<PlatformStop>,
+    /// Upcoming line speed limit the train has started braking for, identified by its block and
+    /// index there. The train keeps obeying it as the braking distance shrinks with the speed.
+    #[serde(default)]
+    braking_for_limit: Option<(BlockId, usize)>,
+    /// Planned exit through the destination on the shift clock, for timetabled services
+    #[serde(default)]
+    exit_time_s: Option<f64>,
+    /// Shift time of the current update, the train keeps its timetable on it
+    #[serde(default)]
+    clock_s: f64,
+    /// Derailed or crashed, the train stands still until the dispatcher clears it
+    #[serde(default)]
+    failed: bool,
+    /// The train protection tripped on a SPAD, the train is braked to a stand and held there until
+    /// the dispatcher acknowledges the SPAD
+    #[serde(default)]
+    tripped: bool,
 }
 
 impl Train {
-    fn set_target_speed_mps(&mut self, speed_mps: f64) {
-        self.target_speed_margin_mps = rand::random::<f64>() * 0.5 + 0.35;
+    /// A standing train without vehicles, for the tests of other simulation modules
+    #[cfg(test)]
+    pub fn test_fixture(
+        front_position: TrackPoint,
+        direction: Direction,
+        destination: Option<BlockId>,
+        priority: u8,
+    ) -> Train {
+        Train {
+            back_position: front_position.clone(),
+            front_position,
+            direction,
+            destination,
+            priority,
+            ..default()
+        }
+    }
+
+    fn set_target_speed_mps(&mut self, speed_mps: f64, rng: &mut SimulationRng) {
+        self.target_speed_margin_mps = rng.random::<f64>() * 0.5 + 0.35;
         self.target_speed_mps = speed_mps;
         info!(
             "Train {} setting target speed to {:.2} km/h",
@@ -187,6 +362,10 @@ impl Train {
         self.front_position.block_id
     }
 
+    pub fn front_position(&self) -> &TrackPoint {
+        &self.front_position
+    }
+
     pub fn direction(&self) -> Direction {
         self.direction
     }
@@ -195,9 +374,43 @@ impl Train {
         self.target_speed_mps.kmh()
     }
 
+    pub fn destination(&self) -> Option<BlockId> {
+        self.destination
+    }
+
+    pub fn priority(&self) -> u8 {
+        self.priority
+    }
+
+    pub fn platform_stop(&self) -> Option<PlatformStop> {
+        self.platform_stop
+    }
+
+    pub fn failed(&self) -> bool {
+        self.failed
+    }
+
+    pub fn tripped(&self) -> bool {
+        self.tripped
+    }
+
+    /// Lets the train move on after its SPAD is acknowledged
+    pub fn release_train_protection(&mut self) {
+        if std::mem::replace(&mut self.tripped, false) {
+            info!("Train {} released by the train protection", self.number);
+        }
+    }
+
     /// Simple throttle and brake controls based on the difference between current and target speed.
     /// Returns `TrainControls` with values between 0.0 and 1.0.
     fn calculate_controls(&self) -> TrainControls {
+        if self.tripped {
+            // Emergency brake
+            return TrainControls {
+                throttle: 0.0,
+                brake_level: 1.0,
+            };
+        }
         let speed_diff_mps = (self.target_speed_mps - self.target_speed_margin_mps) - self.speed_mps;
         if self.speed_mps < 0.001 && self.target_speed_mps < 0.01 {
             return TrainControls {
@@ -231,37 +444,278 @@ impl Train {
             SpeedLimit::Restricted(speed_limit_kmh) => speed_limit_kmh.mps(),
         };
 
-        let braking_force = self.stats.max_braking_force_n * safety_factor;
-        let deceleration_mps2 = braking_force / self.stats.mass_kg;
+        let braking_force = self.get_braking_force_n(1.0) * safety_factor + self.profile_resistance_n;
+        let deceleration_mps2 = (braking_force / self.stats.mass_kg).max(MIN_DECELERATION_MPS2);
 
         let speed_diff_mps = self.speed_mps - target_speed_mps;
         let speed_sum = self.speed_mps + target_speed_mps;
         Some(0.0f64.max((speed_diff_mps * speed_sum) / (2.0 * deceleration_mps2)))
     }
 
-    fn update(&mut self, dt: f64, map: &BlockMap, train_moves: &mut MessageWriter<TrainMove>) {
-        const CREEP_SPEED_KMH: f64 = 20.0;
-        const CREEP_STOP_OFFSET_M: f64 = 50.0;
-        if dt <= 0.0 {
-            return;
+    /// Distance needed to come to a full stop from the current speed with full service braking.
+    pub fn stopping_distance_m(&self) -> f64 {
+        self.get_braking_distance(SpeedLimit::Restricted(0.0), 1.0)
+            .unwrap_or_default()
+    }
+
+    /// Signal the train runs to and the distance to it. The train stays bound to a signal it has
+    /// passed until its front is beyond the overrun tolerance, the distance is negative meanwhile.
+    pub fn signal_ahead<'a>(&self, map: &'a BlockMap) -> Option<(&'a TrackSignal, f64)> {
+        let behind = self.direction.reverse();
+        if map
+            .track_end_within(&self.front_position, SPAD_OVERRUN_M, behind)
+            .is_some()
+        {
+            return map.lookup_signal_forward(&self.front_position, self.direction);
         }
+        let start = map.step_by(&self.front_position, SPAD_OVERRUN_M, behind);
+        map.lookup_signal_forward(&start, self.direction)
+            .map(|(signal, distance_m)| (signal, distance_m - SPAD_OVERRUN_M))
+    }
 
-        // Calculate tractive effort and braking force
-        self.controls = self.calculate_controls();
+    fn get_braking_force_n(&self, brake_level: f64) -> f64 {
+        self.vehicles
+            .iter()
+            .map(|x| x.get_braking_force(brake_level, self.adhesion))
+            .sum()
+    }
+
+    /// Davis running resistance of the whole train, always opposing the motion
+    fn get_running_resistance_n(&self) -> f64 {
+        self.vehicles
+            .iter()
+            .map(|x| x.resistance.get_resistance_n(self.speed_mps))
+            .sum()
+    }
+
+    /// Applies current controls and resistances to the train speed over `dt`, returning the acceleration
+    fn apply_forces(&mut self, dt: f64) -> f64 {
         let tractive_effort = self
             .vehicles
             .iter()
-            .map(|x| x.get_tractive_effort(self.speed_mps, self.controls.throttle))
+            .map(|x| x.get_tractive_effort(self.speed_mps, self.controls.throttle, self.adhesion))
             .sum::<f64>();
-        let braking_force = self.stats.max_braking_force_n * self.controls.brake_level;
-        let net_force_n = tractive_effort - braking_force;
+        let braking_force = self.get_braking_force_n(self.controls.brake_level);
+        let net_force_n = tractive_effort - braking_force - self.get_running_resistance_n() - self.profile_resistance_n;
 
-        let mut acceleration_mps2 = if self.stats.mass_kg > 0.0 {
+        let acceleration_mps2 = if self.stats.mass_kg > 0.0 {
             net_force_n / self.stats.mass_kg
         } else {
             0.0
         };
         self.speed_mps += acceleration_mps2 * dt;
+        acceleration_mps2
+    }
+
+    /// Sums up gradient and curve resistance of every vehicle, taking the track profile
+    /// under the middle of each vehicle. Vehicles are ordered from the front of the train.
+    fn get_profile_resistance_n(&self, map: &BlockMap) -> f64 {
+        let reversed = self.direction.reverse();
+        let mut point = self.front_position.clone();
+        let mut step_m = 0.0;
+        let mut resistance_n = 0.0;
+        for vehicle in &self.vehicles {
+            step_m += vehicle.length_m / 2.0;
+            point = map.step_by(&point, step_m, reversed);
+            step_m = vehicle.length_m / 2.0;
+
+            let Some(block) = map.get_block(point.block_id) else {
+                continue;
+            };
+            let weight_n = vehicle.get_weight_n();
+            resistance_n += weight_n * self.direction.apply_sign(block.gradient_permille) / 1000.0;
+            if let Some(curve_radius_m) = block.curve_radius_m {
+                resistance_n += weight_n * get_curve_resistance_per_kn(curve_radius_m) / 1000.0;
+            }
+        }
+        resistance_n
+    }
+
+    /// The most restrictive line speed limit the train has to obey right now: limits still under
+    /// any part of the train, and upcoming limits once the train is within braking distance of them.
+    fn get_line_speed_limit_kmh(&mut self, map: &BlockMap) -> Option<f64> {
+        const LOOKAHEAD_MARGIN_M: f64 = 200.0;
+        let lookahead_m = self.stats.length_m + self.stopping_distance_m() + LOOKAHEAD_MARGIN_M;
+        let braking_for = self.braking_for_limit.take();
+        let mut obeyed_kmh: Option<f64> = None;
+        for limit in map.lookup_speed_limits(&self.back_position, lookahead_m, self.direction) {
+            let distance_m = limit.start_m - self.stats.length_m;
+            let upcoming = distance_m > 0.0;
+            let obeyed = !upcoming
+                || braking_for == Some((limit.block_id, limit.index))
+                || self
+                    .get_braking_distance(SpeedLimit::Restricted(limit.speed_kmh), 0.8)
+                    .is_some_and(|braking_distance_m| distance_m <= braking_distance_m + LOOKAHEAD_MARGIN_M);
+            if !obeyed || obeyed_kmh.is_some_and(|speed_kmh| speed_kmh <= limit.speed_kmh) {
+                continue;
+            }
+            obeyed_kmh = Some(limit.speed_kmh);
+            self.braking_for_limit = upcoming.then_some((limit.block_id, limit.index));
+        }
+        obeyed_kmh
+    }
+
+    /// Target speed on the approach to a point `distance_m` ahead, where the speed has to drop to `passing`
+    fn get_approach_speed_kmh(&self, distance_m: f64, speeds: &Speeds, passing: SpeedLimit) -> f64 {
+        const CREEP_SPEED_KMH: f64 = 20.0;
+        const CREEP_STOP_OFFSET_M: f64 = 50.0;
+        let Some(braking_distance_m) = self.get_braking_distance(passing, 0.8) else {
+            return speeds.approaching_kmh;
+        };
+        let approaching_mps = speeds.approaching_kmh.mps();
+        let need_slowdown = distance_m > braking_distance_m && self.target_speed_mps >= approaching_mps;
+        let normal_target = if need_slowdown || distance_m > braking_distance_m + 200.0 {
+            speeds.approaching_kmh
+        } else {
+            speeds.passing_kmh
+        };
+        // Two-phase stop: once the normal logic commits to stopping and speed is
+        // already low, hold at creep speed until the precise final braking point.
+        if normal_target < 0.1 && self.speed_mps <= CREEP_SPEED_KMH.mps() {
+            let creep_braking_dist = self.get_braking_distance(passing, 1.0).unwrap_or(0.0);
+            if distance_m > creep_braking_dist + CREEP_STOP_OFFSET_M {
+                CREEP_SPEED_KMH
+            } else {
+                speeds.passing_kmh
+            }
+        } else {
+            normal_target
+        }
+    }
+
+    /// Target speed imposed by station stops: approaching the stopping mark of the next calling
+    /// station, dwelling there and then holding until the exit signal clears. The train arrives once
+    /// it stands with its front in the platform block, within the stop window around the mark.
+    fn get_station_stop_speed_kmh(&mut self, dt: f64, map: &BlockMap, exit_signal_open: bool) -> Option<f64> {
+        const LOOKAHEAD_MARGIN_M: f64 = 400.0;
+        /// The creep stop brings trains to a stand up to about 50 m short of the mark
+        const STOP_WINDOW_SHORT_M: f64 = 60.0;
+        const STOP_WINDOW_PAST_M: f64 = 20.0;
+        match self.platform_stop {
+            Some(PlatformStop::Dwelling(remaining_s)) => {
+                let remaining_s = remaining_s - dt;
+                self.platform_stop = Some(if remaining_s > 0.0 {
+                    PlatformStop::Dwelling(remaining_s)
+                } else {
+                    PlatformStop::ReadyToDepart
+                });
+                return Some(0.0);
+            }
+            Some(PlatformStop::ReadyToDepart) if !exit_signal_open => return Some(0.0),
+            Some(PlatformStop::ReadyToDepart) => {
+                info!("Train {} departed", self.number);
+                self.platform_stop = None;
+                return None;
+            }
+            None => {}
+        }
+
+        let station_id = self.calling_at.front()?.station_id;
+        let lookahead_m = self.stopping_distance_m() + LOOKAHEAD_MARGIN_M;
+        let distance_m = map.lookup_platform_stop(&self.front_position, lookahead_m, self.direction, station_id)?;
+        let at_platform = map.platform_station(self.front_position.block_id) == Some(station_id);
+        if self.speed_mps == 0.0 && at_platform && (-STOP_WINDOW_PAST_M..=STOP_WINDOW_SHORT_M).contains(&distance_m) {
+            let station = self.calling_at.pop_front().expect("calling station present");
+            if let Some(arrival_s) = station.arrival_s {
+                report_delay(
+                    &self.number,
+                    &format!("arrived at station {}", station.station_id),
+                    arrival_s,
+                    self.clock_s,
+                );
+            }
+            // An early train waits for its planned departure
+            let dwell_s = station.departure_s.map_or(station.dwell_s, |departure_s| {
+                station.dwell_s.max(departure_s - self.clock_s)
+            });
+            info!(
+                "Train {} arrived at station {}, dwelling for {:.0} s",
+                self.number, station.station_id, dwell_s
+            );
+            self.platform_stop = Some(PlatformStop::Dwelling(dwell_s));
+            return Some(0.0);
+        }
+        if distance_m < 0.0 {
+            // Still braking past the mark, it's an overrun only if the train stands beyond the window
+            if self.speed_mps > 0.0 {
+                return Some(0.0);
+            }
+            warn!("Train {} overran the platform at station {}", self.number, station_id);
+            self.calling_at.pop_front();
+            return None;
+        }
+        let speeds = Speeds {
+            passing_kmh: 0.0,
+            approaching_kmh: self.top_speed_kmh,
+        };
+        Some(self.get_approach_speed_kmh(distance_m, &speeds, SpeedLimit::Restricted(0.0)))
+    }
+
+    /// Speed above the allowed one when the front overruns the signal, if it's a SPAD: the signal is
+    /// closed, or the train is faster than the signal allows beyond the tolerance
+    fn get_spad_overspeed_kmh(&self, speed_ctrl: &SpeedControl) -> Option<f64> {
+        let speed_kmh = self.speed_mps.kmh();
+        if speed_ctrl.aspect == SignalAspect::Forbidding {
+            return Some(speed_kmh);
+        }
+        let overspeed_kmh = speed_kmh - speed_ctrl.passing_kmh.apply_limit(f64::INFINITY);
+        (overspeed_kmh > SPAD_TOLERANCE_KMH).then_some(overspeed_kmh)
+    }
+
+    /// The train runs out of track at the end of the block. A switch set against it is trailed and
+    /// its points forced over, the train carries on. Otherwise the train derails or hits the buffer
+    /// stop and fails.
+    fn run_out_of_track(&mut self, block_id: BlockId, track_end: TrackEnd) -> Incident {
+        let train_id = self.id;
+        let incident = match track_end {
+            TrackEnd::SwitchSetAgainst(switch_id, position) => {
+                warn!("Train {} trailed switch {} set against it", self.number, switch_id);
+                return Incident::SwitchDamaged {
+                    train_id,
+                    switch_id,
+                    position,
+                };
+            }
+            TrackEnd::MovingSwitch(switch_id) => {
+                warn!(
+                    "Train {} derailed on switch {}, its points were moving",
+                    self.number, switch_id
+                );
+                Incident::Derailment { train_id, switch_id }
+            }
+            TrackEnd::Open => {
+                warn!(
+                    "Train {} hit the buffer stop at the end of block {}",
+                    self.number, block_id
+                );
+                Incident::BufferStopCollision { train_id, block_id }
+            }
+        };
+        self.failed = true;
+        self.speed_mps = 0.0;
+        incident
+    }
+
+    /// Advances the train by `dt`, collecting its block moves and SPADs and returning the incident
+    /// if it ran out of track
+    fn update(
+        &mut self,
+        dt: f64,
+        map: &BlockMap,
+        rail_condition: RailCondition,
+        rng: &mut SimulationRng,
+        train_moves: &mut Vec<TrainMove>,
+        spads: &mut Vec<SignalPassedAtDanger>,
+    ) -> Option<Incident> {
+        if dt <= 0.0 || self.failed {
+            return None;
+        }
+
+        // Calculate tractive effort, braking force and resistances
+        self.adhesion = rail_condition.adhesion();
+        self.profile_resistance_n = self.get_profile_resistance_n(map);
+        self.controls = self.calculate_controls();
+        let mut acceleration_mps2 = self.apply_forces(dt);
 
         if self.speed_mps < 0.1 && self.target_speed_mps < 0.25 {
             if self.speed_mps >= 0.0 {
@@ -270,69 +724,76 @@ impl Train {
             self.speed_mps = 0.0; // brake to full stop
             acceleration_mps2 = 0.0;
         }
+        if self.speed_mps < 0.0 {
+            // Trains don't roll back, a train that can't overcome the gradient stalls
+            self.speed_mps = 0.0;
+            acceleration_mps2 = 0.0;
+        }
 
         let dx = self.speed_mps * dt + 0.5 * acceleration_mps2 * dt.powi(2);
-        let target_speed_mps = match map.lookup_signal_forward(&self.front_position, self.direction) {
+        let signal_ahead = self.signal_ahead(map);
+        let exit_signal_open =
+            signal_ahead.is_none_or(|(signal, _)| signal.speed_ctrl.aspect != SignalAspect::Forbidding);
+        let target_speed_mps = match signal_ahead {
             Some((signal, distance_m)) => {
                 let speeds = signal.speed_ctrl.apply_limit(self.top_speed_kmh);
-                let braking_distance = self.get_braking_distance(signal.speed_ctrl.passing_kmh, 0.8);
-                let speed_limit_kmh = match braking_distance {
-                    None => speeds.approaching_kmh,
-                    Some(braking_distance_m) => {
-                        let approaching_mps = speeds.approaching_kmh.mps();
-                        let need_slowdown = distance_m > braking_distance_m && self.target_speed_mps >= approaching_mps;
-                        let normal_target = if need_slowdown || distance_m > braking_distance_m + 200.0 {
-                            speeds.approaching_kmh
-                        } else {
-                            speeds.passing_kmh
-                        };
-                        // Two-phase stop: once the normal logic commits to stopping and speed is
-                        // already low, hold at creep speed until the precise final braking point.
-                        if normal_target < 0.1 && self.speed_mps <= CREEP_SPEED_KMH.mps() {
-                            let creep_braking_dist = self
-                                .get_braking_distance(signal.speed_ctrl.passing_kmh, 1.0)
-                                .unwrap_or(0.0);
-                            if distance_m > creep_braking_dist + CREEP_STOP_OFFSET_M {
-                                CREEP_SPEED_KMH
-                            } else {
-                                speeds.passing_kmh
-                            }
-                        } else {
-                            normal_target
-                        }
-                    }
-                };
-
-                if distance_m < dx {
-                    info!(
-                        "Train {} passed signal {} at {:.2} km/h, allowed speed {:.2} km/h",
+                let speed_limit_kmh = self.get_approach_speed_kmh(distance_m, &speeds, signal.speed_ctrl.passing_kmh);
+                if distance_m + SPAD_OVERRUN_M < dx
+                    && let Some(overspeed_kmh) = self.get_spad_overspeed_kmh(&signal.speed_ctrl)
+                {
+                    warn!(
+                        "Train {} passed signal {} at danger at {:.2} km/h, allowed speed {:.2} km/h",
                         self.number,
                         signal.name,
                         self.speed_mps.kmh(),
                         speeds.passing_kmh,
                     );
+                    self.tripped = true;
+                    spads.push(SignalPassedAtDanger {
+                        train_id: self.id,
+                        signal_id: signal.id,
+                        overspeed_kmh,
+                    });
                 }
                 speed_limit_kmh.mps()
             }
             None => 20.0.mps(),
         };
+        let target_speed_mps = match self.get_line_speed_limit_kmh(map) {
+            Some(line_limit_kmh) => target_speed_mps.min(line_limit_kmh.mps()),
+            None => target_speed_mps,
+        };
+        let target_speed_mps = match self.get_station_stop_speed_kmh(dt, map, exit_signal_open) {
+            Some(stop_speed_kmh) => target_speed_mps.min(stop_speed_kmh.mps()),
+            None => target_speed_mps,
+        };
 
         if self.target_speed_mps != target_speed_mps {
-            self.set_target_speed_mps(target_speed_mps);
+            self.set_target_speed_mps(target_speed_mps, rng);
         }
 
+        let mut incident = None;
         if dx > 0.0 {
+            if let Some(block_id) = map.track_end_within(&self.front_position, dx, self.direction) {
+                let end = self.run_out_of_track(block_id, map.track_end(block_id, self.direction));
+                // a derailed or crashed train stays where it is, only a trailed switch is run through
+                if self.failed {
+                    return Some(end);
+                }
+                incident = Some(end);
+            }
             let new_front = map.step_by(&self.front_position, dx, self.direction);
             if self.front_position.block_id != new_front.block_id {
-                train_moves.write(TrainMove::entered(new_front.block_id, self));
+                train_moves.push(TrainMove::entered(new_front.block_id, self));
             }
             let new_back = map.step_by(&self.front_position, self.stats.length_m, self.direction.reverse());
             if self.back_position.block_id != new_back.block_id {
-                train_moves.write(TrainMove::exited(self.back_position.block_id, self));
+                train_moves.push(TrainMove::exited(self.back_position.block_id, self));
             }
             self.front_position = new_front;
             self.back_position = new_back;
         }
+        incident
     }
 }
 
@@ -355,17 +816,70 @@ pub struct TrainSpawnRequest {
     pub position: TrackPoint,
     pub direction: Direction,
     pub vehicles: Vec<RailVehicle>,
+    pub destination: Option<BlockId>,
+    pub priority: u8,
+    pub calling_at: Vec<CallingStation>,
+    /// Planned exit time on the shift clock
+    pub exit_time_s: Option<f64>,
 }
 
 #[derive(Resource, Deref, DerefMut, Default)]
 struct TrainMapper(HashMap<TrainId, Entity>);
 
+/// Trains with their consists and the train ID counter, as stored in a saved game
+#[derive(Serialize, Deserialize)]
+pub struct TrainsState {
+    next_id: TrainId,
+    trains: Vec<Train>,
+}
+
+pub fn save_trains(world: &mut World) -> TrainsState {
+    let mut trains: Vec<Train> = world.query::<&Train>().iter(world).cloned().collect();
+    trains.sort_by_key(|train| train.id);
+    TrainsState {
+        next_id: world.resource::<NextTrainId>().0,
+        trains,
+    }
+}
+
+/// Replaces all trains with the saved ones
+pub fn restore_trains(world: &mut World, state: TrainsState) {
+    let entities: Vec<Entity> = world.query_filtered::<Entity, With<Train>>().iter(world).collect();
+    for entity in entities {
+        world.despawn(entity);
+    }
+    let mapper: HashMap<TrainId, Entity> = state
+        .trains
+        .into_iter()
+        .map(|train| (train.id, world.spawn(train).id()))
+        .collect();
+    world.insert_resource(TrainMapper(mapper));
+    world.insert_resource(NextTrainId(state.next_id));
+}
+
+/// Despawns the trains that `keep` rejects, without announcing them leaving their blocks.
+/// Returns the number of despawned trains.
+pub fn retain_trains(world: &mut World, mut keep: impl FnMut(&Train) -> bool) -> usize {
+    let removed: Vec<(Entity, TrainId)> = world
+        .query::<(Entity, &Train)>()
+        .iter(world)
+        .filter(|(_, train)| !keep(train))
+        .map(|(entity, train)| (entity, train.id))
+        .collect();
+    for &(entity, train_id) in &removed {
+        world.resource_mut::<TrainMapper>().remove(&train_id);
+        world.despawn(entity);
+    }
+    removed.len()
+}
+
 pub struct TrainPlugin;
 
 impl Plugin for TrainPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<NextTrainId>()
             .init_resource::<TrainMapper>()
+            .init_resource::<RailCondition>()
             .add_message::<TrainMove>()
             .add_message::<TrainSpawnRequest>()
             .add_message::<TrainDespawnRequest>()
@@ -373,19 +887,60 @@ impl Plugin for TrainPlugin {
                 Update,
                 (spawn_trains, despawn_trains).run_if(in_state(LoadingState::Instantiated)),
             )
+            .add_systems(OnEnter(LoadingState::Instantiated), init_rail_condition)
             .add_systems(FixedUpdate, update.run_if(in_state(LoadingState::Instantiated)));
     }
 }
 
+fn init_rail_condition(handles: Res<AssetHandles>, levels: Res<Assets<Level>>, mut commands: Commands) {
+    let level = levels.get(&handles.level).expect("level had been loaded");
+    commands.insert_resource(level.rail_condition);
+}
+
+/// Messages written as the trains move
+#[derive(SystemParam)]
+struct TrainWriters<'w> {
+    train_moves: MessageWriter<'w, TrainMove>,
+    incidents: MessageWriter<'w, Incident>,
+    spads: MessageWriter<'w, SignalPassedAtDanger>,
+}
+
+/// A trailed switch is damaged right away, so the train runs on through it and following trains
+/// see the points where it left them
 fn update(
     time: Res<Time>,
-    block_map: Res<BlockMap>,
+    timetable: Res<Timetable>,
+    mut block_map: ResMut<BlockMap>,
+    rail_condition: Res<RailCondition>,
+    mut rng: ResMut<SimulationRng>,
     mut query: Query<&mut Train>,
-    mut train_moves: MessageWriter<TrainMove>,
+    mut writers: TrainWriters,
 ) {
-    query.iter_mut().for_each(|mut train| {
-        train.update(time.delta_secs_f64(), &block_map, &mut train_moves);
-    });
+    let mut train_moves = Vec::new();
+    let mut spads = Vec::new();
+    let now_s = timetable.shift_time_s(time.elapsed_secs_f64());
+    for mut train in &mut query {
+        train.clock_s = now_s;
+        let incident = train.update(
+            time.delta_secs_f64(),
+            &block_map,
+            *rail_condition,
+            &mut rng,
+            &mut train_moves,
+            &mut spads,
+        );
+        writers.train_moves.write_batch(train_moves.drain(..));
+        writers.spads.write_batch(spads.drain(..));
+        if let Some(incident) = incident {
+            if let Incident::SwitchDamaged {
+                switch_id, position, ..
+            } = incident
+            {
+                block_map.damage_switch(switch_id, position);
+            }
+            writers.incidents.write(incident);
+        }
+    }
 }
 
 fn despawn_trains(
@@ -401,6 +956,12 @@ fn despawn_trains(
             let train = query.get(entity).expect("invalid train entity");
             info!("Train {} despawned with ID {}", train.number, train.id);
             if let Some(blocks) = block_map.get_train_blocks(train.id) {
+                // Trains leaving through their destination despawner have kept to or missed their exit
+                if let (Some(destination), Some(exit_time_s)) = (train.destination, train.exit_time_s)
+                    && blocks.contains(&destination)
+                {
+                    report_delay(&train.number, "left the area", exit_time_s, train.clock_s);
+                }
                 train_moves.write_batch(blocks.iter().map(|&b| TrainMove::exited(b, train)));
             }
             commands.entity(entity).despawn();
@@ -410,6 +971,7 @@ fn despawn_trains(
 
 fn spawn_trains(
     block_map: Res<BlockMap>,
+    rail_condition: Res<RailCondition>,
     mut mapper: ResMut<TrainMapper>,
     mut requests: MessageReader<TrainSpawnRequest>,
     mut train_moves: MessageWriter<TrainMove>,
@@ -434,6 +996,11 @@ fn spawn_trains(
             speed_mps: spawn.actual_speed_kmh.mps(),
             front_position: spawn.position.clone(),
             back_position: trace.last().cloned().expect("at least one track point"),
+            adhesion: rail_condition.adhesion(),
+            destination: spawn.destination,
+            priority: spawn.priority,
+            calling_at: spawn.calling_at.iter().cloned().collect(),
+            exit_time_s: spawn.exit_time_s,
             ..default()
         };
         train_moves.write_batch(trace.iter().map(|point| TrainMove::entered(point.block_id, &train)));
@@ -447,11 +1014,392 @@ fn spawn_trains(
     }
 }
 
-pub fn get_random_train_number(direction: Direction) -> String {
-    let num = rand::random_range(1000..=9999);
+pub fn get_random_train_number(direction: Direction, rng: &mut SimulationRng) -> String {
+    let num = rng.random_range(1000..=9999);
     match num % 2 {
         0 if direction == Direction::Odd => (num + 1).to_string(),
         1 if direction == Direction::Even => (num - 1).to_string(),
         _ => num.to_string(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::SwitchPosition;
+
+    const DT: f64 = 1.0 / 64.0;
+
+    fn build_train(vehicles: Vec<RailVehicle>, speed_kmh: f64, rail_condition: RailCondition) -> Train {
+        Train {
+            stats: get_train_stats(&vehicles),
+            vehicles,
+            speed_mps: speed_kmh.mps(),
+            adhesion: rail_condition.adhesion(),
+            ..default()
+        }
+    }
+
+    fn passenger_train(speed_kmh: f64) -> Train {
+        let mut vehicles = vec![RailVehicle::new_locomotive(80_000.0, 16.0, 2942.0, 300.0)];
+        vehicles.extend([RailVehicle::new_car(40_000.0, 24.0, 5_000.0); 25]);
+        build_train(vehicles, speed_kmh, RailCondition::Dry)
+    }
+
+    fn locomotive(rail_condition: RailCondition) -> Train {
+        let vehicles = vec![RailVehicle::new_locomotive(138_000.0, 18.15, 2250.0, 375.0)];
+        build_train(vehicles, 0.0, rail_condition)
+    }
+
+    /// Runs the train with fixed controls for `duration_s` or until it stops,
+    /// returning the elapsed time and travelled distance
+    fn simulate(train: &mut Train, throttle: f64, brake_level: f64, duration_s: f64) -> (f64, f64) {
+        train.controls = TrainControls { throttle, brake_level };
+        let (mut elapsed_s, mut distance_m) = (0.0, 0.0);
+        while elapsed_s < duration_s - 1e-9 {
+            train.apply_forces(DT);
+            elapsed_s += DT;
+            if train.speed_mps < 0.0 {
+                train.speed_mps = 0.0;
+                break;
+            }
+            distance_m += train.speed_mps * DT;
+        }
+        (elapsed_s, distance_m)
+    }
+
+    #[test]
+    fn coasting_train_slows_down() {
+        let mut train = passenger_train(80.0);
+        let (_, distance_m) = simulate(&mut train, 0.0, 0.0, 60.0);
+        assert!((train.get_speed_kmh() - 71.59).abs() < 0.05);
+        assert!((distance_m - 1262.1).abs() < 1.0);
+    }
+
+    #[test]
+    fn full_braking_stopping_curve() {
+        let mut train = passenger_train(80.0);
+        let predicted_m = train.stopping_distance_m();
+        let (elapsed_s, distance_m) = simulate(&mut train, 0.0, 1.0, f64::INFINITY);
+        assert_eq!(train.speed_mps, 0.0);
+        assert!((elapsed_s - 70.0).abs() < 0.1);
+        assert!((distance_m - 768.1).abs() < 1.0);
+        // running resistance helps braking, so the prediction without it is conservative
+        assert!(distance_m < predicted_m);
+    }
+
+    #[test]
+    fn signal_passed_at_danger() {
+        let mut train = passenger_train(60.0);
+        let closed = SpeedControl::default_for_aspect(SignalAspect::Forbidding);
+        assert!((train.get_spad_overspeed_kmh(&closed).unwrap() - 60.0).abs() < 1e-9);
+        let restricting = SpeedControl::default_for_aspect(SignalAspect::Restricting);
+        assert!((train.get_spad_overspeed_kmh(&restricting).unwrap() - 20.0).abs() < 1e-9);
+        let open = SpeedControl::default_for_aspect(SignalAspect::Unrestricting);
+        assert_eq!(train.get_spad_overspeed_kmh(&open), None);
+
+        train.target_speed_mps = 60.0.mps();
+        train.tripped = true;
+        assert_eq!(train.calculate_controls().brake_level, 1.0);
+        train.release_train_protection();
+        assert_eq!(train.calculate_controls().brake_level, 0.0);
+    }
+
+    #[test]
+    fn curve_resistance_follows_roeckl() {
+        assert!((get_curve_resistance_per_kn(800.0) - 650.0 / 745.0).abs() < 1e-9);
+        assert!((get_curve_resistance_per_kn(200.0) - 500.0 / 170.0).abs() < 1e-9);
+        // very tight radii are clamped
+        assert!((get_curve_resistance_per_kn(20.0) - 25.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn profile_resistance_opposes_climbing() {
+        let map = BlockMap::from_level(&Level::test_fixture());
+        let mut train = locomotive(RailCondition::Dry);
+        let weight_n = train.vehicles[0].get_weight_n();
+
+        // block 1 rises by 6 per mille in the even direction
+        train.front_position = TrackPoint {
+            block_id: 1,
+            offset_m: 1000.0,
+        };
+        train.direction = Direction::Even;
+        assert!((train.get_profile_resistance_n(&map) - weight_n * 0.006).abs() < 1e-6);
+        train.direction = Direction::Odd;
+        assert!((train.get_profile_resistance_n(&map) + weight_n * 0.006).abs() < 1e-6);
+
+        // block 2 rises by 4 per mille on an 800 m curve
+        train.front_position = TrackPoint {
+            block_id: 2,
+            offset_m: 700.0,
+        };
+        train.direction = Direction::Even;
+        let expected_n = weight_n * (4.0 + get_curve_resistance_per_kn(800.0)) / 1000.0;
+        assert!((train.get_profile_resistance_n(&map) - expected_n).abs() < 1e-6);
+    }
+
+    #[test]
+    fn running_resistance_grows_with_speed() {
+        let mut train = passenger_train(0.0);
+        let standing_n = train.get_running_resistance_n();
+        let davis_a_n: f64 = train.vehicles.iter().map(|v| v.resistance.a_n).sum();
+        assert!((standing_n - davis_a_n).abs() < 1e-9);
+        train.speed_mps = 100.0.mps();
+        let running_n = train.get_running_resistance_n();
+        assert!(running_n > standing_n);
+        train.speed_mps = 160.0.mps();
+        assert!(train.get_running_resistance_n() - running_n > running_n - standing_n);
+    }
+
+    #[test]
+    fn adhesion_limits_starting_tractive_effort() {
+        for (rail_condition, expected_mps2) in [(RailCondition::Dry, 2.707), (RailCondition::Wet, 1.952)] {
+            let mut train = locomotive(rail_condition);
+            train.controls = TrainControls {
+                throttle: 1.0,
+                brake_level: 0.0,
+            };
+            let acceleration_mps2 = train.apply_forces(DT);
+            assert!((acceleration_mps2 - expected_mps2).abs() < 0.001);
+        }
+    }
+
+    #[test]
+    fn acceleration_curve_on_slippery_rails() {
+        let mut dry = locomotive(RailCondition::Dry);
+        simulate(&mut dry, 1.0, 0.0, 30.0);
+        assert!((dry.get_speed_kmh() - 107.69).abs() < 0.05);
+
+        let mut leaves = locomotive(RailCondition::Leaves);
+        simulate(&mut leaves, 1.0, 0.0, 30.0);
+        assert!((leaves.get_speed_kmh() - 82.0).abs() < 0.05);
+    }
+
+    /// The passenger train running even towards station 1, whose even stopping mark is at 940 m of block 6
+    fn calling_train(map: &BlockMap, block_id: BlockId, offset_m: f64, speed_kmh: f64) -> Train {
+        let mut train = passenger_train(speed_kmh);
+        train.top_speed_kmh = 80.0;
+        train.direction = Direction::Even;
+        train.front_position = TrackPoint::new(block_id, offset_m);
+        train.back_position = map.step_by(&train.front_position, train.stats.length_m, Direction::Odd);
+        train.calling_at.push_back(CallingStation {
+            station_id: 1,
+            dwell_s: 30.0,
+            arrival_s: Some(500.0),
+            departure_s: Some(530.0),
+        });
+        train.clock_s = 500.0;
+        train
+    }
+
+    #[test]
+    fn train_stops_at_the_platform() {
+        let map = BlockMap::from_level(&Level::test_fixture());
+        let mut rng = SimulationRng::new(1);
+        let mut train = calling_train(&map, 5, 0.0, 40.0);
+        let (mut train_moves, mut spads) = (Vec::new(), Vec::new());
+        for _ in 0..(300.0 / DT) as usize {
+            train.update(DT, &map, RailCondition::Dry, &mut rng, &mut train_moves, &mut spads);
+            if train.platform_stop.is_some() {
+                break;
+            }
+        }
+        assert_eq!(train.platform_stop, Some(PlatformStop::Dwelling(30.0)));
+        assert_eq!(train.front_position.block_id, 6);
+        assert!((880.0..=940.0).contains(&train.front_position.offset_m));
+        assert!(train.calling_at.is_empty());
+    }
+
+    #[test]
+    fn standing_short_of_the_platform_is_not_an_arrival() {
+        let map = BlockMap::from_level(&Level::test_fixture());
+        // held by a signal 80 m short of the mark
+        let mut train = calling_train(&map, 6, 860.0, 0.0);
+        assert!(train.get_station_stop_speed_kmh(DT, &map, false).unwrap() > 0.0);
+        assert_eq!(train.platform_stop, None);
+        assert_eq!(train.calling_at.len(), 1);
+    }
+
+    #[test]
+    fn overrun_is_decided_once_the_train_stands() {
+        let map = BlockMap::from_level(&Level::test_fixture());
+        // still braking a few metres past the mark, then standing within the stop window
+        let mut train = calling_train(&map, 6, 950.0, 10.0);
+        assert_eq!(train.get_station_stop_speed_kmh(DT, &map, false), Some(0.0));
+        assert_eq!(train.calling_at.len(), 1);
+        train.speed_mps = 0.0;
+        assert_eq!(train.get_station_stop_speed_kmh(DT, &map, false), Some(0.0));
+        assert_eq!(train.platform_stop, Some(PlatformStop::Dwelling(30.0)));
+
+        // standing well past the mark
+        let mut train = calling_train(&map, 6, 1000.0, 0.0);
+        assert_eq!(train.get_station_stop_speed_kmh(DT, &map, false), None);
+        assert_eq!(train.platform_stop, None);
+        assert!(train.calling_at.is_empty());
+    }
+
+    #[test]
+    fn early_train_waits_for_its_planned_departure() {
+        let map = BlockMap::from_level(&Level::test_fixture());
+        let mut train = calling_train(&map, 6, 920.0, 0.0);
+        train.clock_s = 440.0;
+        assert_eq!(train.get_station_stop_speed_kmh(DT, &map, false), Some(0.0));
+        assert_eq!(train.platform_stop, Some(PlatformStop::Dwelling(90.0)));
+
+        // a late train still dwells for the planned time
+        let mut train = calling_train(&map, 6, 920.0, 0.0);
+        train.clock_s = 560.0;
+        assert_eq!(train.get_station_stop_speed_kmh(DT, &map, false), Some(0.0));
+        assert_eq!(train.platform_stop, Some(PlatformStop::Dwelling(30.0)));
+    }
+
+    fn running_train(map: &BlockMap, block_id: BlockId, offset_m: f64, direction: Direction) -> Train {
+        let mut train = passenger_train(60.0);
+        train.top_speed_kmh = 80.0;
+        train.direction = direction;
+        train.front_position = TrackPoint::new(block_id, offset_m);
+        train.back_position = map.step_by(&train.front_position, train.stats.length_m, direction.reverse());
+        train
+    }
+
+    /// Runs the train for a single step, returning the incident and the block moves
+    fn step(train: &mut Train, map: &BlockMap) -> (Option<Incident>, Vec<TrainMove>) {
+        let mut rng = SimulationRng::new(1);
+        let (mut train_moves, mut spads) = (Vec::new(), Vec::new());
+        let incident = train.update(DT, map, RailCondition::Dry, &mut rng, &mut train_moves, &mut spads);
+        (incident, train_moves)
+    }
+
+    #[test]
+    fn train_keeps_braking_for_a_speed_limit_ahead() {
+        let map = BlockMap::from_level(&Level::test_fixture());
+        // 60 km/h limit starts 300 m ahead, at 200 m into block 2
+        let mut train = running_train(&map, 1, 1900.0, Direction::Even);
+        train.speed_mps = 80.0.mps();
+        assert_eq!(train.get_line_speed_limit_kmh(&map), Some(60.0));
+        assert_eq!(train.braking_for_limit, Some((2, 0)));
+
+        // the braking distance shrinks as the train slows down, but the limit is still obeyed
+        train.speed_mps = 62.0.mps();
+        assert_eq!(train.get_line_speed_limit_kmh(&map), Some(60.0));
+        let mut unaware = running_train(&map, 1, 1900.0, Direction::Even);
+        unaware.speed_mps = 62.0.mps();
+        assert_eq!(unaware.get_line_speed_limit_kmh(&map), None);
+
+        // under the train the limit applies regardless
+        let mut train = running_train(&map, 2, 400.0, Direction::Even);
+        assert_eq!(train.get_line_speed_limit_kmh(&map), Some(60.0));
+        assert_eq!(train.braking_for_limit, None);
+    }
+
+    #[test]
+    fn train_derails_on_moving_points_and_stays_put() {
+        let mut map = BlockMap::from_level(&Level::test_fixture());
+        map.throw_switch(1, SwitchPosition::Side);
+        let mut train = running_train(&map, 4, 9.9, Direction::Even);
+        let (incident, train_moves) = step(&mut train, &map);
+        assert_eq!(
+            incident,
+            Some(Incident::Derailment {
+                train_id: train.id,
+                switch_id: 1
+            })
+        );
+        assert!(train.failed);
+        assert_eq!(train.speed_mps, 0.0);
+        assert_eq!(train.front_position.offset_m, 9.9);
+        assert!(train_moves.is_empty());
+        let (incident, train_moves) = step(&mut train, &map);
+        assert!(incident.is_none() && train_moves.is_empty());
+    }
+
+    #[test]
+    fn train_hits_the_buffer_stop_and_stays_put() {
+        let map = BlockMap::from_level(&Level::test_fixture());
+        let mut train = running_train(&map, 1, 0.1, Direction::Odd);
+        let (incident, train_moves) = step(&mut train, &map);
+        assert_eq!(
+            incident,
+            Some(Incident::BufferStopCollision {
+                train_id: train.id,
+                block_id: 1
+            })
+        );
+        assert!(train.failed);
+        assert_eq!(train.front_position.block_id, 1);
+        assert_eq!(train.front_position.offset_m, 0.1);
+        assert!(train_moves.is_empty());
+    }
+
+    #[test]
+    fn train_runs_through_a_trailed_switch() {
+        let map = BlockMap::from_level(&Level::test_fixture());
+        let mut train = running_train(&map, 20, 0.1, Direction::Odd);
+        let (incident, _) = step(&mut train, &map);
+        assert_eq!(
+            incident,
+            Some(Incident::SwitchDamaged {
+                train_id: train.id,
+                switch_id: 1,
+                position: SwitchPosition::Side
+            })
+        );
+        assert!(!train.failed);
+        assert!(train.speed_mps > 0.0);
+    }
+
+    /// Runs the train until it stands or `duration_s` passes, returning the SPADs
+    fn run(train: &mut Train, map: &BlockMap, duration_s: f64) -> Vec<SignalPassedAtDanger> {
+        let mut rng = SimulationRng::new(1);
+        let (mut train_moves, mut spads) = (Vec::new(), Vec::new());
+        for _ in 0..(duration_s / DT) as usize {
+            train.update(DT, map, RailCondition::Dry, &mut rng, &mut train_moves, &mut spads);
+            if train.speed_mps == 0.0 {
+                break;
+            }
+        }
+        spads
+    }
+
+    #[test]
+    fn train_brakes_to_a_stand_at_a_closed_signal() {
+        let map = BlockMap::from_level(&Level::test_fixture());
+        let (signal, _) = map
+            .lookup_signal_forward(&TrackPoint::new(2, 100.0), Direction::Even)
+            .unwrap();
+        assert!(signal.id == 50 && signal.speed_ctrl.aspect == SignalAspect::Forbidding);
+        // a heavy train creeping up to the signal comes to rest just beyond it
+        let mut freight = running_train(&map, 2, 1300.0, Direction::Even);
+        let mut vehicles = vec![RailVehicle::new_locomotive(80_000.0, 16.0, 2942.0, 300.0)];
+        vehicles.extend([RailVehicle::new_car(40_000.0, 24.0, 60_000.0); 25]);
+        freight.stats = get_train_stats(&vehicles);
+        freight.vehicles = vehicles;
+        freight.speed_mps = 20.0.mps();
+        for (mut train, start_kmh) in [
+            (running_train(&map, 2, 100.0, Direction::Even), 40.0),
+            (running_train(&map, 2, 100.0, Direction::Even), 80.0),
+            (freight, 20.0),
+        ] {
+            train.speed_mps = start_kmh.mps();
+            let spads = run(&mut train, &map, 600.0);
+            assert_eq!(train.speed_mps, 0.0, "from {} km/h", start_kmh);
+            assert!(spads.is_empty(), "from {} km/h", start_kmh);
+            assert!(!train.tripped);
+            let (signal, distance_m) = train.signal_ahead(&map).unwrap();
+            assert_eq!(signal.id, 50);
+            assert!(distance_m > -SPAD_OVERRUN_M, "stood at {}", train.front_position);
+        }
+    }
+
+    #[test]
+    fn passing_a_closed_signal_trips_the_train() {
+        let map = BlockMap::from_level(&Level::test_fixture());
+        let mut train = running_train(&map, 2, 1370.0, Direction::Even);
+        let spads = run(&mut train, &map, 1.0);
+        assert_eq!(spads.len(), 1);
+        assert_eq!((spads[0].train_id, spads[0].signal_id), (train.id, 50));
+        assert!((spads[0].overspeed_kmh - 60.0).abs() < 1.0);
+        assert!(train.tripped);
+    }
+}
@@ -123,18 +123,25 @@ fn time_controls(
 
 fn on_time_scale_change(
     change: On<TimeScaleChanged>,
+    mut time_controls: ResMut<TimeControls>,
     mut time: ResMut<Time<Virtual>>,
     mut overlay_config: ResMut<FpsOverlayConfig>,
     query: Single<Entity, With<TimeScaleText>>,
     mut writer: TextUiWriter,
 ) {
+    // The change may come from a replay rather than from the keys
+    if let Some(index) = MULTIPLIERS.iter().position(|&m| m == change.time_scale) {
+        time_controls.multiplier_index = index;
+    }
+    time_controls.time_scale = change.time_scale;
     time.set_relative_speed_f64(change.time_scale);
     overlay_config.refresh_interval = Duration::from_millis((100.0 * change.time_scale) as u64);
     *writer.text(query.entity(), 0) = time_scale_formatted(change.time_scale);
     info!("Setting timescale to {}", change.time_scale);
 }
 
-fn on_pause_toggle(toggle: On<PauseToggled>, mut time: ResMut<Time<Virtual>>) {
+fn on_pause_toggle(toggle: On<PauseToggled>, mut time_controls: ResMut<TimeControls>, mut time: ResMut<Time<Virtual>>) {
+    time_controls.paused = toggle.paused;
     if toggle.paused {
         time.pause();
         info!("Paused");
@@ -0,0 +1,114 @@
+//! Command line tools for level authors.
+//!
+//! Usage: `rail-tools <command> <args...>`
+//!
+//! - `validate-level <level.toml>` — list every problem found in a level file
+//! - `generate-routes <level.toml>` — print the routes missing from a level, by station, to merge into it
+
+use rail_dispatch::common::StationId;
+use rail_dispatch::level::{Level, RouteData};
+use rail_dispatch::simulation::route_generator::generate_routes;
+use serde::Serialize;
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: rail-tools validate-level <level.toml>\n       rail-tools generate-routes <level.toml>";
+
+fn read_level(path: &str) -> Result<Level, String> {
+    let contents = std::fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+    toml::from_str(&contents).map_err(|e| format!("can't parse {}: {}", path, e))
+}
+
+fn validate_level(path: &str) -> Result<(), String> {
+    let level = read_level(path)?;
+    match level.validate() {
+        Ok(()) => {
+            println!("{}: ok", path);
+            Ok(())
+        }
+        Err(issues) => {
+            for issue in &issues.0 {
+                println!("{}: {}", path, issue);
+            }
+            Err(format!("{}: {} problems found", path, issues.0.len()))
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct StationRoutes<'a> {
+    id: StationId,
+    name: &'a str,
+    routes: Vec<&'a RouteData>,
+}
+
+#[derive(Serialize)]
+struct GeneratedRoutes<'a> {
+    stations: Vec<StationRoutes<'a>>,
+    /// Routes with no station platform along them
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    unassigned: Vec<&'a RouteData>,
+}
+
+fn print_generated_routes(path: &str) -> Result<(), String> {
+    let level = read_level(path)?;
+    let generated = match generate_routes(&level) {
+        Ok(generated) => generated,
+        Err(issues) => {
+            // stdout carries the generated routes only
+            for issue in &issues.0 {
+                eprintln!("{}: {}", path, issue);
+            }
+            return Err(format!(
+                "{}: {} problems found, no routes generated",
+                path,
+                issues.0.len()
+            ));
+        }
+    };
+    let new_routes: Vec<_> = generated.iter().filter(|g| g.existing.is_none()).collect();
+    let output = GeneratedRoutes {
+        stations: level
+            .stations
+            .iter()
+            .map(|station| StationRoutes {
+                id: station.id,
+                name: &station.name,
+                routes: new_routes
+                    .iter()
+                    .filter(|g| g.station_id == Some(station.id))
+                    .map(|g| &g.route)
+                    .collect(),
+            })
+            .filter(|station| !station.routes.is_empty())
+            .collect(),
+        unassigned: new_routes
+            .iter()
+            .filter(|g| g.station_id.is_none())
+            .map(|g| &g.route)
+            .collect(),
+    };
+    print!("{}", toml::to_string(&output).map_err(|e| e.to_string())?);
+    eprintln!(
+        "{}: {} routes generated, {} already in the level",
+        path,
+        generated.len(),
+        generated.len() - new_routes.len()
+    );
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["validate-level", path] => validate_level(path),
+        ["generate-routes", path] => print_generated_routes(path),
+        _ => Err(USAGE.to_string()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,482 @@
+use crate::common::{BlockId, Direction, SwitchId, SwitchPosition};
+use crate::level::{Level, RouteData, SwitchData};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use thiserror::Error;
+
+/// A problem in the level data, the game can't be built from a level having any
+#[derive(Debug, PartialEq, Error)]
+pub enum LevelIssue {
+    #[error("duplicate {kind} ID {id}")]
+    DuplicateId { kind: &'static str, id: u32 },
+    #[error("{owner} refers to unknown {kind} {id}")]
+    UnknownReference { owner: String, kind: &'static str, id: u32 },
+    #[error("signal {signal_id} at {offset_m} m is outside block {block_id} of length {length_m} m")]
+    SignalOffset {
+        signal_id: u32,
+        block_id: BlockId,
+        offset_m: f64,
+        length_m: f64,
+    },
+    #[error("speed limit from {from_m} m to {to_m} m is outside block {block_id} of length {length_m} m")]
+    SpeedLimitRange {
+        block_id: BlockId,
+        from_m: f64,
+        to_m: f64,
+        length_m: f64,
+    },
+    #[error("{direction:?} end of block {block_id} is joined more than once")]
+    EndJoinedTwice { block_id: BlockId, direction: Direction },
+    #[error("switch {switch_id} uses block {block_id} more than once")]
+    SwitchRepeatsBlock { switch_id: SwitchId, block_id: BlockId },
+    #[error("switch {switch_id} can't connect block {block_id}, its end at the switch is joined by a connection")]
+    SwitchLegConnected { switch_id: SwitchId, block_id: BlockId },
+    #[error("switch {switch_id} has an invalid throw time of {throw_time_s} s")]
+    SwitchThrowTime { switch_id: SwitchId, throw_time_s: f32 },
+    #[error("spawner on block {0} has no open end")]
+    SpawnerWithoutOpenEnd(BlockId),
+    #[error("spawner on block {0} is not joined to any other block")]
+    SpawnerWithoutNeighbour(BlockId),
+    #[error(
+        "service {number} is planned to leave at {exit_time_s} s, before its entry or last departure at {earliest_s} s"
+    )]
+    ServiceExitTime {
+        number: String,
+        exit_time_s: f64,
+        earliest_s: f64,
+    },
+    #[error("route {route_id}: {reason}")]
+    RouteSwitches { route_id: u32, reason: String },
+}
+
+/// All problems found in a level, one per line
+#[derive(Debug, PartialEq)]
+pub struct LevelIssues(pub Vec<LevelIssue>);
+
+impl fmt::Display for LevelIssues {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, issue) in self.0.iter().enumerate() {
+            if idx > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for LevelIssues {}
+
+/// An end of a block, the even end is the one a train running in the even direction leaves through
+type BlockEnd = (BlockId, Direction);
+
+/// Checks the references, IDs and topology of a level, collecting every problem found
+pub fn validate_level(level: &Level) -> Vec<LevelIssue> {
+    let mut issues = Vec::new();
+
+    let block_lengths: HashMap<BlockId, f64> = level.blocks.iter().map(|b| (b.id, b.length)).collect();
+    let section_ids: HashSet<u32> = level.sections.iter().map(|s| s.id).collect();
+    let switch_ids: HashSet<u32> = level.switches.iter().map(|s| s.id).collect();
+    let signal_ids: HashSet<u32> = level.signals.iter().map(|s| s.id).collect();
+    let station_ids: HashSet<u32> = level.stations.iter().map(|s| s.id).collect();
+    let spawner_blocks: HashSet<u32> = level.spawners.iter().map(|s| s.block_id).collect();
+    let routes: Vec<&RouteData> = level.stations.iter().flat_map(|s| &s.routes).collect();
+
+    let mut check_unique = |kind: &'static str, ids: &mut dyn Iterator<Item = u32>| {
+        let mut seen = HashSet::new();
+        let mut reported = HashSet::new();
+        for id in ids {
+            if !seen.insert(id) && reported.insert(id) {
+                issues.push(LevelIssue::DuplicateId { kind, id });
+            }
+        }
+    };
+    check_unique("block", &mut level.blocks.iter().map(|b| b.id));
+    check_unique("signal", &mut level.signals.iter().map(|s| s.id));
+    check_unique("switch", &mut level.switches.iter().map(|s| s.id));
+    check_unique("section", &mut level.sections.iter().map(|s| s.id));
+    check_unique("station", &mut level.stations.iter().map(|s| s.id));
+    check_unique("route", &mut routes.iter().map(|r| r.id));
+    check_unique("spawner block", &mut level.spawners.iter().map(|s| s.block_id));
+    check_unique("geometry block", &mut level.geometry.iter().map(|g| g.id));
+
+    let mut references = References {
+        issues: &mut issues,
+        known: HashMap::from([
+            ("block", block_lengths.keys().copied().collect()),
+            ("section", section_ids),
+            ("switch", switch_ids),
+            ("signal", signal_ids),
+            ("station", station_ids),
+            ("spawner block", spawner_blocks),
+        ]),
+    };
+    for conn in &level.connections {
+        let owner = format!("connection {} -> {}", conn.start, conn.end);
+        references.check(&owner, "block", [conn.start, conn.end]);
+    }
+    for switch in &level.switches {
+        let owner = format!("switch {}", switch.id);
+        references.check(&owner, "block", [switch.base, switch.straight, switch.side]);
+    }
+    for spawner in &level.spawners {
+        references.check("spawner", "block", [spawner.block_id]);
+    }
+    for signal in &level.signals {
+        references.check(&format!("signal {}", signal.id), "block", [signal.block_id]);
+    }
+    for limit in &level.speed_limits {
+        references.check("speed limit", "block", [limit.block_id]);
+    }
+    for section in &level.sections {
+        references.check(
+            &format!("section {}", section.id),
+            "block",
+            section.blocks.iter().copied(),
+        );
+    }
+    for station in &level.stations {
+        let owner = format!("station {}", station.id);
+        references.check(&owner, "block", station.platforms.iter().map(|p| p.block_id));
+        for route in &station.routes {
+            let owner = format!("route {}", route.id);
+            references.check(&owner, "signal", [route.signal]);
+            references.check(&owner, "section", route.sections.iter().copied());
+            references.check(&owner, "block", [route.target]);
+            references.check(&owner, "switch", route.switches.iter().map(|s| s.switch_id));
+        }
+    }
+    for service in &level.timetable {
+        let owner = format!("service {}", service.number);
+        references.check(&owner, "spawner block", [service.entry, service.exit]);
+        references.check(&owner, "station", service.stops.iter().map(|s| s.station));
+    }
+    for geometry in &level.geometry {
+        references.check("geometry", "block", [geometry.id]);
+    }
+
+    for signal in &level.signals {
+        if let Some(&length_m) = block_lengths.get(&signal.block_id)
+            && !(0.0..=length_m).contains(&signal.offset_m)
+        {
+            issues.push(LevelIssue::SignalOffset {
+                signal_id: signal.id,
+                block_id: signal.block_id,
+                offset_m: signal.offset_m,
+                length_m,
+            });
+        }
+    }
+    for limit in &level.speed_limits {
+        if let Some(&length_m) = block_lengths.get(&limit.block_id)
+            && (limit.from_offset_m < 0.0 || limit.to_offset_m > length_m || limit.from_offset_m > limit.to_offset_m)
+        {
+            issues.push(LevelIssue::SpeedLimitRange {
+                block_id: limit.block_id,
+                from_m: limit.from_offset_m,
+                to_m: limit.to_offset_m,
+                length_m,
+            });
+        }
+    }
+
+    for switch in &level.switches {
+        if let Some(throw_time_s) = switch.throw_time_s
+            && !(throw_time_s >= 0.0 && throw_time_s.is_finite())
+        {
+            issues.push(LevelIssue::SwitchThrowTime {
+                switch_id: switch.id,
+                throw_time_s,
+            });
+        }
+    }
+
+    for service in &level.timetable {
+        let earliest_s = service
+            .stops
+            .iter()
+            .map(|stop| stop.departure_s)
+            .fold(service.entry_time_s, f64::max);
+        if service.exit_time_s < earliest_s {
+            issues.push(LevelIssue::ServiceExitTime {
+                number: service.number.clone(),
+                exit_time_s: service.exit_time_s,
+                earliest_s,
+            });
+        }
+    }
+
+    let topology = Topology::new(level);
+    issues.extend(topology.check_ends(level));
+    for spawner in &level.spawners {
+        if !block_lengths.contains_key(&spawner.block_id) {
+            continue;
+        }
+        match topology.open_ends(spawner.block_id) {
+            0 => issues.push(LevelIssue::SpawnerWithoutOpenEnd(spawner.block_id)),
+            2 => issues.push(LevelIssue::SpawnerWithoutNeighbour(spawner.block_id)),
+            _ => {}
+        }
+    }
+    for route in routes {
+        if let Err(reason) = topology.check_route(level, route) {
+            issues.push(LevelIssue::RouteSwitches {
+                route_id: route.id,
+                reason,
+            });
+        }
+    }
+
+    issues
+}
+
+struct References<'a> {
+    issues: &'a mut Vec<LevelIssue>,
+    known: HashMap<&'static str, HashSet<u32>>,
+}
+
+impl References<'_> {
+    fn check(&mut self, owner: &str, kind: &'static str, ids: impl IntoIterator<Item = u32>) {
+        for id in ids {
+            if !self.known[kind].contains(&id) {
+                self.issues.push(LevelIssue::UnknownReference {
+                    owner: owner.to_string(),
+                    kind,
+                    id,
+                });
+            }
+        }
+    }
+}
+
+/// Block ends joined by connections and switches
+struct Topology<'a> {
+    connections: HashMap<BlockEnd, BlockId>,
+    /// Switches by the end of the base block they split at
+    facing: HashMap<BlockEnd, &'a SwitchData>,
+    /// Switches by the end of a leg block they join at, with the position connecting the leg
+    trailing: HashMap<BlockEnd, (&'a SwitchData, SwitchPosition)>,
+}
+
+impl<'a> Topology<'a> {
+    fn new(level: &'a Level) -> Self {
+        let mut topology = Topology {
+            connections: HashMap::new(),
+            facing: HashMap::new(),
+            trailing: HashMap::new(),
+        };
+        for conn in &level.connections {
+            topology.connections.insert((conn.start, Direction::Even), conn.end);
+            topology.connections.insert((conn.end, Direction::Odd), conn.start);
+        }
+        for switch in &level.switches {
+            let leg_direction = switch.direction.reverse();
+            topology.facing.insert((switch.base, switch.direction), switch);
+            topology
+                .trailing
+                .insert((switch.straight, leg_direction), (switch, SwitchPosition::Straight));
+            topology
+                .trailing
+                .insert((switch.side, leg_direction), (switch, SwitchPosition::Side));
+        }
+        topology
+    }
+
+    fn check_ends(&self, level: &Level) -> Vec<LevelIssue> {
+        let mut issues = Vec::new();
+        let mut joined: HashSet<BlockEnd> = HashSet::new();
+        let mut reported: HashSet<BlockEnd> = HashSet::new();
+        let mut join = |end: BlockEnd, issues: &mut Vec<LevelIssue>| {
+            if !joined.insert(end) && reported.insert(end) {
+                issues.push(LevelIssue::EndJoinedTwice {
+                    block_id: end.0,
+                    direction: end.1,
+                });
+            }
+        };
+        for conn in &level.connections {
+            join((conn.start, Direction::Even), &mut issues);
+            join((conn.end, Direction::Odd), &mut issues);
+        }
+
+        for switch in &level.switches {
+            let blocks = [switch.base, switch.straight, switch.side];
+            for (idx, &block_id) in blocks.iter().enumerate() {
+                if blocks[..idx].contains(&block_id) {
+                    issues.push(LevelIssue::SwitchRepeatsBlock {
+                        switch_id: switch.id,
+                        block_id,
+                    });
+                }
+            }
+            let leg_direction = switch.direction.reverse();
+            let ends = [
+                (switch.base, switch.direction),
+                (switch.straight, leg_direction),
+                (switch.side, leg_direction),
+            ];
+            for end in ends {
+                if self.connections.contains_key(&end) {
+                    issues.push(LevelIssue::SwitchLegConnected {
+                        switch_id: switch.id,
+                        block_id: end.0,
+                    });
+                } else {
+                    // Two switches sharing a junction end would overwrite each other's links
+                    join(end, &mut issues);
+                }
+            }
+        }
+        issues
+    }
+
+    /// Number of the block's ends not joined to any other block
+    fn open_ends(&self, block_id: BlockId) -> usize {
+        [Direction::Even, Direction::Odd]
+            .into_iter()
+            .filter(|&direction| {
+                let end = (block_id, direction);
+                !self.connections.contains_key(&end)
+                    && !self.facing.contains_key(&end)
+                    && !self.trailing.contains_key(&end)
+            })
+            .count()
+    }
+
+    /// Follows the route from its signal with the route's switch settings applied, it has to reach the target
+    /// over every switch it sets and no other
+    fn check_route(&self, level: &Level, route: &RouteData) -> Result<(), String> {
+        let Some(signal) = level.signals.iter().find(|s| s.id == route.signal) else {
+            return Ok(());
+        };
+        let settings: HashMap<SwitchId, SwitchPosition> =
+            route.switches.iter().map(|s| (s.switch_id, s.position)).collect();
+        let direction = signal.direction;
+        let mut passed_switches = HashSet::new();
+        let mut visited = HashSet::new();
+        let mut block_id = signal.block_id;
+        while block_id != route.target {
+            if !visited.insert(block_id) {
+                return Err(format!("the switch settings lead into a loop at block {}", block_id));
+            }
+            let end = (block_id, direction);
+            block_id = if let Some(&next) = self.connections.get(&end) {
+                next
+            } else if let Some(switch) = self.facing.get(&end) {
+                passed_switches.insert(switch.id);
+                match settings.get(&switch.id) {
+                    Some(SwitchPosition::Straight) => switch.straight,
+                    Some(SwitchPosition::Side) => switch.side,
+                    None => return Err(format!("switch {} on the way is not set", switch.id)),
+                }
+            } else if let Some(&(switch, position)) = self.trailing.get(&end) {
+                passed_switches.insert(switch.id);
+                match settings.get(&switch.id) {
+                    Some(&setting) if setting == position => switch.base,
+                    Some(_) => return Err(format!("switch {} is set against the way", switch.id)),
+                    None => return Err(format!("switch {} on the way is not set", switch.id)),
+                }
+            } else {
+                return Err(format!(
+                    "the track ends at block {} before reaching target block {}",
+                    block_id, route.target
+                ));
+            };
+        }
+        if let Some(setting) = route.switches.iter().find(|s| !passed_switches.contains(&s.switch_id)) {
+            return Err(format!("switch {} is set but not on the way", setting.switch_id));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEVEL: &str = r##"
+        blocks = [[1, 100], [2, 50], [3, 200], [4, 200]]
+        connections = [[1, 2]]
+        switches = [[1, 2, 3, 4, 1]]
+        spawners = [[1, "both"], [3, "both"], [4, "despawn"]]
+        signals = [[1, 1, 90, "1", 1, "manual"]]
+        sections = [[1, [2]]]
+        stations = [{ id = 1, name = "s", routes = [
+            [1, 1, [1], 3, [{ switch_id = 1, position = "straight" }]],
+        ] }]
+        timetable = [{ number = "1", consist = "c", entry = 1, entry_time_s = 60, exit = 4, exit_time_s = 300 }]
+        background = "#000000"
+    "##;
+
+    #[test]
+    fn valid_levels_have_no_issues() {
+        let level: Level = toml::from_str(LEVEL).unwrap();
+        assert_eq!(validate_level(&level), []);
+        assert_eq!(validate_level(&Level::test_fixture()), []);
+    }
+
+    #[test]
+    fn all_issues_are_reported() {
+        let broken = LEVEL
+            .replace("[1, 1, 90,", "[1, 1, 120,")
+            .replace("[[1, 2, 3, 4, 1]]", "[[1, 2, 3, 4, 1, -2.0]]")
+            .replace("[[1, [2]]]", "[[1, [2]], [1, [7]]]")
+            .replace("connections = [[1, 2]]", "connections = [[1, 2], [2, 3]]")
+            .replace("exit_time_s = 300", "exit_time_s = 30");
+        let level: Level = toml::from_str(&broken).unwrap();
+        let issues = validate_level(&level);
+        assert_eq!(
+            issues,
+            [
+                LevelIssue::DuplicateId { kind: "section", id: 1 },
+                LevelIssue::UnknownReference {
+                    owner: "section 1".to_string(),
+                    kind: "block",
+                    id: 7
+                },
+                LevelIssue::SignalOffset {
+                    signal_id: 1,
+                    block_id: 1,
+                    offset_m: 120.0,
+                    length_m: 100.0
+                },
+                LevelIssue::SwitchThrowTime {
+                    switch_id: 1,
+                    throw_time_s: -2.0
+                },
+                LevelIssue::ServiceExitTime {
+                    number: "1".to_string(),
+                    exit_time_s: 30.0,
+                    earliest_s: 60.0
+                },
+                LevelIssue::SwitchLegConnected {
+                    switch_id: 1,
+                    block_id: 2
+                },
+                LevelIssue::SwitchLegConnected {
+                    switch_id: 1,
+                    block_id: 3
+                },
+                LevelIssue::RouteSwitches {
+                    route_id: 1,
+                    reason: "switch 1 is set but not on the way".to_string()
+                },
+            ]
+        );
+
+        let level: Level = toml::from_str(&LEVEL.replace("position = \"straight\"", "position = \"side\"")).unwrap();
+        assert_eq!(
+            validate_level(&level),
+            [LevelIssue::RouteSwitches {
+                route_id: 1,
+                reason: "the track ends at block 4 before reaching target block 3".to_string()
+            }]
+        );
+
+        let isolated = LEVEL
+            .replace("[4, 200]]", "[4, 200], [5, 100]]")
+            .replace("[4, \"despawn\"]]", "[4, \"despawn\"], [5, \"despawn\"]]");
+        let level: Level = toml::from_str(&isolated).unwrap();
+        assert_eq!(validate_level(&level), [LevelIssue::SpawnerWithoutNeighbour(5)]);
+    }
+}